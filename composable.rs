@@ -1,16 +1,33 @@
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 mod manager {
     pub mod account {
-        use super::super::Actions;
+        use super::super::{Actions, Field, Fields, Reader, Value, Wire};
         // Ví dụ cấu trúc dữ liệu cụ thể
-        #[derive(Clone, Debug)]
+        #[derive(Clone)]
         pub struct Account {
             pub username: String,
             pub balance: f64,
+            pub password: String,
+        }
+
+        // Không để mật khẩu lộ ra khi in bằng `{:?}`
+        impl std::fmt::Debug for Account {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct("Account")
+                    .field("username", &self.username)
+                    .field("balance", &self.balance)
+                    .field("password", &"[REDACTED]")
+                    .finish()
+            }
         }
 
         impl Actions for Account {
@@ -26,6 +43,32 @@ mod manager {
                 // Logic xóa Account
             }
         }
+
+        impl Fields for Account {
+            fn fields(&self) -> Vec<Field> {
+                vec![
+                    Field::new("username", Value::Str(self.username.clone())),
+                    Field::new("balance", Value::Num(self.balance)),
+                    Field::secret("password", Value::Str(self.password.clone())),
+                ]
+            }
+        }
+
+        impl Wire for Account {
+            fn encode(&self, out: &mut Vec<u8>) {
+                self.username.encode(out);
+                out.extend_from_slice(&self.balance.to_be_bytes());
+                self.password.encode(out);
+            }
+
+            fn decode(input: &mut Reader<'_>) -> Option<Self> {
+                Some(Account {
+                    username: String::decode(input)?,
+                    balance: f64::from_be_bytes(input.take(8)?.try_into().ok()?),
+                    password: String::decode(input)?,
+                })
+            }
+        }
     }
 }
 // Định nghĩa trait DataActions cho các hành động dữ liệu
@@ -56,7 +99,7 @@ impl<T> State<T> {
 }
 
 // Định nghĩa các Action có thể có
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Action<T>
 where
     T: Actions,
@@ -66,10 +109,129 @@ where
     Delete(T),
 }
 
+impl<T: Actions> Action<T> {
+    // Tên biến thể của action, dùng làm nhãn cho metrics và span
+    fn kind(&self) -> &'static str {
+        match self {
+            Action::Create(_) => "create",
+            Action::Update(_) => "update",
+            Action::Delete(_) => "delete",
+        }
+    }
+
+    // Gắn danh tính của người dispatch vào action
+    fn by(self, identity: &Identity) -> Signed<T> {
+        Signed {
+            identity: identity.clone(),
+            action: self,
+        }
+    }
+
+    // Quyền cần có để dispatch action này
+    fn permission(&self) -> Permission {
+        match self {
+            Action::Create(_) => Permission::Create,
+            Action::Update(_) => Permission::Update,
+            Action::Delete(_) => Permission::Delete,
+        }
+    }
+}
+
+// Action kèm danh tính người dispatch, được Store kiểm tra trước khi chạy reducer
+#[derive(Debug, Clone)]
+struct Signed<T: Actions> {
+    identity: Identity,
+    action: Action<T>,
+}
+
 // Định nghĩa Middleware
 type Middleware<T> = dyn Fn(&Action<T>, &State<T>) + Send + Sync;
 
+// Quyền tương ứng với từng loại action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Permission {
+    Create,
+    Update,
+    Delete,
+}
+
+// Vai trò của người dùng và các quyền đi kèm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Admin,
+    Editor,
+    Viewer,
+}
+
+impl Role {
+    fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Admin => &[Permission::Create, Permission::Update, Permission::Delete],
+            Role::Editor => &[Permission::Create, Permission::Update],
+            Role::Viewer => &[],
+        }
+    }
+}
+
+// Danh tính của người dispatch action
+#[derive(Debug, Clone)]
+struct Identity {
+    id: String,
+    roles: Vec<Role>,
+}
+
+impl Identity {
+    fn new(id: &str, roles: Vec<Role>) -> Self {
+        Identity {
+            id: id.to_string(),
+            roles,
+        }
+    }
+
+    fn can(&self, permission: Permission) -> bool {
+        self.roles
+            .iter()
+            .any(|role| role.permissions().contains(&permission))
+    }
+}
+
+// Lỗi trả về cho presenter khi action bị từ chối
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Unauthorized {
+    identity: String,
+    action: &'static str,
+}
+
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' không có quyền thực hiện '{}'",
+            self.identity, self.action
+        )
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+// Định nghĩa Guard: chạy trước middleware, có thể từ chối action
+type Guard<T> = dyn Fn(&Action<T>, &Identity) -> Result<(), Unauthorized> + Send + Sync;
+
+// Guard mặc định: kiểm tra vai trò của người gọi có quyền với loại action hay không
+fn authorize<T: Actions>(action: &Action<T>, identity: &Identity) -> Result<(), Unauthorized> {
+    if identity.can(action.permission()) {
+        Ok(())
+    } else {
+        Err(Unauthorized {
+            identity: identity.id.clone(),
+            action: action.kind(),
+        })
+    }
+}
+
 // Định nghĩa Reducer
+type Reducer<T> = dyn Fn(&State<T>, Action<T>) -> State<T> + Send + Sync;
+
 fn reducer<T: Actions + Clone>(state: &State<T>, action: Action<T>) -> State<T> {
     let mut new_state = state.clone();
     match action {
@@ -92,38 +254,97 @@ fn reducer<T: Actions + Clone>(state: &State<T>, action: Action<T>) -> State<T>
 // Định nghĩa Store
 struct Store<T: Actions + Clone + 'static> {
     state: Arc<State<T>>,
-    reducer: Box<dyn Fn(&State<T>, Action<T>) -> State<T> + Send + Sync>,
-    middlewares: Vec<Box<Middleware<T>>>,
-    plugins: Vec<Box<dyn Plugin<T>>>,
+    // Cấu hình dùng Arc để nhiều Store (ví dụ theo phiên) có thể chia sẻ
+    reducer: Arc<Reducer<T>>,
+    guards: Vec<Arc<Guard<T>>>,
+    middlewares: Vec<Arc<Middleware<T>>>,
+    plugins: Vec<Arc<dyn Plugin<T>>>,
 }
 
 impl<T: Actions + Clone + 'static> Store<T> {
+    // Store mới luôn có guard `authorize`, ví dụ chỉ admin được `Delete`
     fn new(initial_state: T) -> Self {
         Store {
             state: Arc::new(State::new(initial_state)),
-            reducer: Box::new(reducer::<T>),
+            reducer: Arc::new(reducer::<T>),
+            guards: vec![Arc::new(authorize::<T>)],
             middlewares: Vec::new(),
             plugins: Vec::new(),
         }
     }
 
-    fn dispatch(&mut self, action: Action<T>) {
+    // Dispatch action kèm danh tính người gọi; state giữ nguyên nếu một guard từ chối.
+    // Mọi log trong guard, middleware, reducer và plugin nằm trong span `dispatch`; thời
+    // gian middleware và reducer được ghi vào span trước khi nó đóng.
+    fn dispatch(&mut self, signed: Signed<T>) -> Result<(), Unauthorized> {
+        let Signed { identity, action } = signed;
+        let kind = action.kind();
+        let span = tracing::info_span!(
+            "dispatch",
+            kind,
+            middleware_us = tracing::field::Empty,
+            reducer_us = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+        for guard in &self.guards {
+            guard(&action, &identity)?;
+        }
+        let started = Instant::now();
         for middleware in &self.middlewares {
             middleware(&action, &self.state);
         }
+        let middleware = started.elapsed();
+        span.record("middleware_us", middleware.as_micros() as u64);
         for plugin in &self.plugins {
             plugin.on_action(&action, &self.state);
         }
+        let started = Instant::now();
+        let new_state = (self.reducer)(&self.state, action);
+        let timing = Timing {
+            middleware,
+            reducer: started.elapsed(),
+        };
+        span.record("reducer_us", timing.reducer.as_micros() as u64);
+        self.state = Arc::new(new_state);
+        for plugin in &self.plugins {
+            plugin.on_dispatched(&timing, &self.state);
+        }
+        Ok(())
+    }
+
+    // Áp dụng action đã được kiểm tra ở nơi khác (ví dụ nhận qua sync), chỉ chạy reducer
+    fn apply(&mut self, action: Action<T>) {
         let new_state = (self.reducer)(&self.state, action);
         self.state = Arc::new(new_state);
     }
 
+    // Thay toàn bộ state bằng snapshot
+    fn restore(&mut self, shared: T) {
+        self.state = Arc::new(State::new(shared));
+    }
+
+    // Tạo Store mới với state riêng nhưng dùng chung reducer, guard, middleware và plugin
+    fn fork(&self, state: State<T>) -> Self {
+        Store {
+            state: Arc::new(state),
+            reducer: Arc::clone(&self.reducer),
+            guards: self.guards.clone(),
+            middlewares: self.middlewares.clone(),
+            plugins: self.plugins.clone(),
+        }
+    }
+
+    // Thêm guard chạy sau `authorize`
+    fn add_guard(&mut self, guard: Box<Guard<T>>) {
+        self.guards.push(Arc::from(guard));
+    }
+
     fn add_middleware(&mut self, middleware: Box<Middleware<T>>) {
-        self.middlewares.push(middleware);
+        self.middlewares.push(Arc::from(middleware));
     }
 
     fn add_plugin<P: Plugin<T> + 'static>(&mut self, plugin: P) {
-        self.plugins.push(Box::new(plugin));
+        self.plugins.push(Arc::new(plugin));
     }
 }
 
@@ -140,91 +361,2020 @@ impl<T: Actions + Clone + 'static> Database<T> {
     }
 }
 
-// Định nghĩa Effect
-struct Effect<T: Actions + Clone + 'static> {
-    operation: Box<dyn FnMut(&mut Database<T>, &mut State<T>) + Send>,
+// Nơi lưu state của các phiên bị loại để khôi phục khi phiên được mở lại
+trait Persist<T> {
+    fn save(&mut self, id: &str, state: &State<T>) -> std::io::Result<()>;
+    // Lấy ra (và xóa) state đã lưu của phiên
+    fn take(&mut self, id: &str) -> std::io::Result<Option<State<T>>>;
 }
 
-impl<T: Actions + Clone + 'static> Effect<T> {
-    fn apply(&mut self, database: &mut Database<T>, state: &mut State<T>) {
-        (self.operation)(database, state);
+// Lưu trong bộ nhớ, mất khi tiến trình kết thúc
+struct Memory<T> {
+    states: HashMap<String, State<T>>,
+}
+
+impl<T> Memory<T> {
+    fn new() -> Self {
+        Memory {
+            states: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> Persist<T> for Memory<T> {
+    fn save(&mut self, id: &str, state: &State<T>) -> std::io::Result<()> {
+        self.states.insert(id.to_string(), state.clone());
+        Ok(())
+    }
+
+    fn take(&mut self, id: &str) -> std::io::Result<Option<State<T>>> {
+        Ok(self.states.remove(id))
     }
 }
 
-// Định nghĩa Logger
-fn logger<T: Actions + std::fmt::Debug + 'static>(action: &Action<T>, state: &State<T>) {
-    println!("Action: {:?}, State: {:?}", action, state);
+// Lưu mỗi phiên thành một file trong thư mục, mã hóa bằng `Wire`
+struct Directory {
+    path: PathBuf,
 }
 
-// Định nghĩa trait Plugin với generics
-trait Plugin<T>
+impl Directory {
+    fn new(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        Ok(Directory { path })
+    }
+
+    // Tên file lấy từ mã hex của id để id như "../x" không thoát khỏi thư mục
+    fn file(&self, id: &str) -> PathBuf {
+        let name: String = id.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.path.join(format!("{}.state", name))
+    }
+}
+
+impl<T: Wire> Persist<T> for Directory {
+    fn save(&mut self, id: &str, state: &State<T>) -> std::io::Result<()> {
+        let mut out = Vec::new();
+        state.encode(&mut out);
+        let file = self.file(id);
+        let tmp = file.with_extension("tmp");
+        std::fs::write(&tmp, out)?;
+        std::fs::rename(tmp, file)
+    }
+
+    fn take(&mut self, id: &str) -> std::io::Result<Option<State<T>>> {
+        let file = self.file(id);
+        let bytes = match std::fs::read(&file) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let state = State::decode(&mut Reader::new(&bytes)).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "state không hợp lệ")
+        })?;
+        std::fs::remove_file(file)?;
+        Ok(Some(state))
+    }
+}
+
+// Một phiên làm việc: Store riêng và thời điểm hoạt động gần nhất
+struct Session<T: Actions + Clone + 'static, G> {
+    store: Store<T>,
+    global: Arc<G>,
+    last_active: Instant,
+}
+
+impl<T: Actions + Clone + 'static, G> Session<T, G> {
+    // Dispatch trong phiên; mỗi lần dispatch đều tính là phiên còn hoạt động
+    fn dispatch(&mut self, signed: Signed<T>) -> Result<(), Unauthorized> {
+        self.last_active = Instant::now();
+        self.store.dispatch(signed)
+    }
+}
+
+// Quản lý nhiều phiên trong cùng một tiến trình, mỗi phiên có state tách biệt
+struct Sessions<T: Actions + Clone + 'static, G> {
+    template: Store<T>,
+    global: Arc<G>,
+    sessions: HashMap<String, Session<T, G>>,
+    storage: Box<dyn Persist<T>>,
+    idle: Duration,
+}
+
+impl<T: Actions + Clone + 'static, G> Sessions<T, G> {
+    // `template` cung cấp cấu hình dùng chung, `global` là state chỉ đọc cho mọi phiên
+    fn new(template: Store<T>, global: G, idle: Duration) -> Self {
+        Sessions {
+            template,
+            global: Arc::new(global),
+            sessions: HashMap::new(),
+            storage: Box::new(Memory::new()),
+            idle,
+        }
+    }
+
+    // Nơi lưu state của phiên bị loại, mặc định là bộ nhớ
+    fn storage(mut self, storage: impl Persist<T> + 'static) -> Self {
+        self.storage = Box::new(storage);
+        self
+    }
+
+    // Mở (hoặc lấy lại) phiên; state đã lưu khi bị loại sẽ được khôi phục
+    fn open(
+        &mut self,
+        id: &str,
+        initial_state: impl FnOnce() -> T,
+    ) -> std::io::Result<&mut Session<T, G>> {
+        if !self.sessions.contains_key(id) {
+            let state = match self.storage.take(id)? {
+                Some(state) => state,
+                None => State::new(initial_state()),
+            };
+            let session = Session {
+                store: self.template.fork(state),
+                global: Arc::clone(&self.global),
+                last_active: Instant::now(),
+            };
+            self.sessions.insert(id.to_string(), session);
+        }
+        let session = self.sessions.get_mut(id).unwrap();
+        session.last_active = Instant::now();
+        Ok(session)
+    }
+
+    // Loại các phiên không hoạt động quá `idle` tính đến `now` và lưu toàn bộ state của chúng;
+    // phiên nào lưu lỗi thì vẫn được giữ lại trong bộ nhớ và các phiên còn lại vẫn được xử lý tiếp
+    fn evict_idle(&mut self, now: Instant) -> Eviction {
+        let idle = self.idle;
+        let mut expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| now.saturating_duration_since(session.last_active) >= idle)
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired.sort();
+        let mut eviction = Eviction::default();
+        for id in expired {
+            match self.storage.save(&id, &self.sessions[&id].store.state) {
+                Ok(()) => {
+                    self.sessions.remove(&id);
+                    eviction.evicted.push(id);
+                }
+                Err(error) => eviction.failed.push((id, error)),
+            }
+        }
+        eviction
+    }
+}
+
+// Kết quả của một lần loại phiên: các phiên đã loại và các phiên lưu lỗi (vẫn còn trong bộ nhớ)
+#[derive(Debug, Default)]
+struct Eviction {
+    evicted: Vec<String>,
+    failed: Vec<(String, std::io::Error)>,
+}
+
+// Đọc tuần tự từ một buffer đã nhận
+struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Reader { input }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.input.len() < len {
+            return None;
+        }
+        let (head, tail) = self.input.split_at(len);
+        self.input = tail;
+        Some(head)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+// Mã hóa nhị phân cho dữ liệu truyền qua kênh đồng bộ
+trait Wire: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut Reader<'_>) -> Option<Self>;
+}
+
+impl Wire for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.len() as u32).to_be_bytes());
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Option<Self> {
+        let len = u32::from_be_bytes(input.take(4)?.try_into().ok()?) as usize;
+        String::from_utf8(input.take(len)?.to_vec()).ok()
+    }
+}
+
+impl<T: Wire> Wire for State<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.is_loading as u8);
+        match &self.error {
+            Some(error) => {
+                out.push(1);
+                error.encode(out);
+            }
+            None => out.push(0),
+        }
+        self.shared.encode(out);
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Option<Self> {
+        let is_loading = input.take(1)?[0] != 0;
+        let error = match input.take(1)?[0] {
+            0 => None,
+            1 => Some(String::decode(input)?),
+            _ => return None,
+        };
+        let mut state = State::new(T::decode(input)?);
+        state.is_loading = is_loading;
+        state.error = error;
+        Some(state)
+    }
+}
+
+impl<T: Actions + Wire> Wire for Action<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (tag, data) = match self {
+            Action::Create(data) => (0u8, data),
+            Action::Update(data) => (1, data),
+            Action::Delete(data) => (2, data),
+        };
+        out.push(tag);
+        data.encode(out);
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Option<Self> {
+        let tag = input.take(1)?[0];
+        let data = T::decode(input)?;
+        match tag {
+            0 => Some(Action::Create(data)),
+            1 => Some(Action::Update(data)),
+            2 => Some(Action::Delete(data)),
+            _ => None,
+        }
+    }
+}
+
+// Giao thức đồng bộ giữa Primary (nguồn sự thật) và Mirror
+#[derive(Debug, Clone)]
+enum Message<T: Actions> {
+    // Mirror kết nối (lại) và báo số thứ tự cuối cùng đã áp dụng
+    Hello { last: u64 },
+    // Action đã được Primary áp dụng với số thứ tự `seq`
+    Action { seq: u64, action: Action<T> },
+    // Toàn bộ state tại số thứ tự `seq`, dùng khi Mirror bị tụt quá xa
+    Snapshot { seq: u64, state: T },
+    // Mirror đề xuất action, dựa trên state ở số thứ tự `base`
+    Propose { base: u64, action: Action<T> },
+    // Primary từ chối đề xuất
+    Reject { base: u64, reason: String },
+}
+
+impl<T: Actions + Wire> Wire for Message<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Hello { last } => {
+                out.push(0);
+                out.extend_from_slice(&last.to_be_bytes());
+            }
+            Message::Action { seq, action } => {
+                out.push(1);
+                out.extend_from_slice(&seq.to_be_bytes());
+                action.encode(out);
+            }
+            Message::Snapshot { seq, state } => {
+                out.push(2);
+                out.extend_from_slice(&seq.to_be_bytes());
+                state.encode(out);
+            }
+            Message::Propose { base, action } => {
+                out.push(3);
+                out.extend_from_slice(&base.to_be_bytes());
+                action.encode(out);
+            }
+            Message::Reject { base, reason } => {
+                out.push(4);
+                out.extend_from_slice(&base.to_be_bytes());
+                reason.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut Reader<'_>) -> Option<Self> {
+        let tag = input.take(1)?[0];
+        let number = input.u64()?;
+        match tag {
+            0 => Some(Message::Hello { last: number }),
+            1 => Some(Message::Action {
+                seq: number,
+                action: Action::decode(input)?,
+            }),
+            2 => Some(Message::Snapshot {
+                seq: number,
+                state: T::decode(input)?,
+            }),
+            3 => Some(Message::Propose {
+                base: number,
+                action: Action::decode(input)?,
+            }),
+            4 => Some(Message::Reject {
+                base: number,
+                reason: String::decode(input)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Độ dài tối đa của một message, để peer không thể bắt cấp phát bộ nhớ tùy ý
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+// Gửi một message có tiền tố độ dài qua bất kỳ AsyncWrite nào
+async fn send<T, W>(writer: &mut W, message: &Message<T>) -> std::io::Result<()>
 where
-    T: Actions + Clone + 'static,
+    T: Actions + Wire,
+    W: tokio::io::AsyncWrite + Unpin,
 {
-    fn name(&self) -> String;
-    fn on_action(&self, action: &Action<T>, state: &State<T>);
-    fn as_any(&self) -> &dyn Any;
+    use tokio::io::AsyncWriteExt;
+
+    let mut body = Vec::new();
+    message.encode(&mut body);
+    if body.len() > MAX_FRAME {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "message vượt quá MAX_FRAME",
+        ));
+    }
+    writer.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
 }
 
-// Ví dụ về cách sử dụng plugin với cấu trúc dữ liệu khác nhau
-#[derive(Debug)]
-struct Logging;
+// Nhận một message; `None` khi kết nối đã đóng
+async fn recv<T, R>(reader: &mut R) -> std::io::Result<Option<Message<T>>>
+where
+    T: Actions + Wire,
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
 
-impl<T: Actions + Clone + std::fmt::Debug + 'static> Plugin<T> for Logging {
-    fn name(&self) -> String {
-        "Logging".to_string()
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "message vượt quá MAX_FRAME",
+        ));
     }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Message::decode(&mut Reader::new(&body))
+        .map(Some)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "message không hợp lệ"))
+}
+
+// Chính sách khi Mirror đề xuất action dựa trên state đã cũ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conflict {
+    // Áp dụng theo thứ tự đến Primary, bất kể `base`
+    Serialize,
+    // Từ chối đề xuất nếu Primary đã có action mới hơn `base`
+    RejectStale,
+}
+
+// Phía nguồn sự thật: đánh số thứ tự action và giữ log gần nhất để Mirror bắt kịp
+struct Primary<T: Actions + Clone + 'static> {
+    store: Store<T>,
+    seq: u64,
+    log: VecDeque<(u64, Action<T>)>,
+    capacity: usize,
+    conflict: Conflict,
+}
 
-    fn on_action(&self, action: &Action<T>, state: &State<T>) {
-        println!("Logging action: {:?}, state: {:?}", action, state);
+impl<T: Actions + Clone + 'static> Primary<T> {
+    fn new(store: Store<T>, capacity: usize, conflict: Conflict) -> Self {
+        Primary {
+            store,
+            seq: 0,
+            log: VecDeque::new(),
+            capacity,
+            conflict,
+        }
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn record(&mut self, action: Action<T>) -> Message<T> {
+        self.seq += 1;
+        self.log.push_back((self.seq, action.clone()));
+        if self.log.len() > self.capacity {
+            self.log.pop_front();
+        }
+        Message::Action {
+            seq: self.seq,
+            action,
+        }
+    }
+
+    // Dispatch cục bộ; message trả về cần được gửi tới các Mirror
+    fn dispatch(&mut self, signed: Signed<T>) -> Result<Message<T>, Unauthorized> {
+        let action = signed.action.clone();
+        self.store.dispatch(signed)?;
+        Ok(self.record(action))
+    }
+
+    // Xử lý message từ Mirror đã xác thực là `peer`, trả về các message cần gửi đi
+    fn handle(&mut self, peer: &Identity, message: Message<T>) -> Vec<Message<T>> {
+        match message {
+            Message::Hello { last } => {
+                let oldest = self
+                    .log
+                    .front()
+                    .map(|(seq, _)| *seq)
+                    .unwrap_or(self.seq + 1);
+                // `last` do peer gửi nên có thể là bất kỳ giá trị nào
+                let replayable =
+                    matches!(last.checked_add(1), Some(next) if next >= oldest && last < self.seq);
+                if last == self.seq {
+                    Vec::new()
+                } else if replayable {
+                    self.log
+                        .iter()
+                        .filter(|(seq, _)| *seq > last)
+                        .map(|(seq, action)| Message::Action {
+                            seq: *seq,
+                            action: action.clone(),
+                        })
+                        .collect()
+                } else {
+                    vec![Message::Snapshot {
+                        seq: self.seq,
+                        state: self.store.state.shared.clone(),
+                    }]
+                }
+            }
+            Message::Propose { base, action } => {
+                if self.conflict == Conflict::RejectStale && base != self.seq {
+                    return vec![Message::Reject {
+                        base,
+                        reason: format!("state đã thay đổi (seq {})", self.seq),
+                    }];
+                }
+                match self.dispatch(action.by(peer)) {
+                    Ok(message) => vec![message],
+                    Err(error) => vec![Message::Reject {
+                        base,
+                        reason: error.to_string(),
+                    }],
+                }
+            }
+            // Primary không nhận action hay snapshot từ Mirror
+            _ => Vec::new(),
+        }
     }
 }
 
-// Hàm async để xử lý các tác vụ bất đồng bộ
-async fn async_action_handler<T: Actions + Clone + 'static>(
-    mut effect: Effect<T>,
-    database: &mut Database<T>,
-    state: &mut State<T>,
-) {
-    effect.apply(database, state);
+// Phía bản sao: chỉ áp dụng action theo đúng thứ tự Primary đã đánh số
+struct Mirror<T: Actions + Clone + 'static> {
+    store: Store<T>,
+    last: u64,
 }
 
-#[tokio::main]
-async fn main() {
-    let mut store = Store::new(manager::account::Account {
-        username: "user".to_string(),
-        balance: 0.0,
-    });
-    let effect = Effect {
-        operation: Box::new(|_, _| {}),
-    }; // Khởi tạo effect tùy thuộc vào logic của bạn
-    let mut database = Database::new(); // Khởi tạo database
-    let mut state = State::new(manager::account::Account {
-        username: "user".to_string(),
-        balance: 0.0,
-    }); // Khởi tạo state
+impl<T: Actions + Clone + 'static> Mirror<T> {
+    fn new(store: Store<T>) -> Self {
+        Mirror { store, last: 0 }
+    }
 
-    // Thêm middleware cho logging
-    store.add_middleware(Box::new(|action, state| {
-        logger(action, state);
-    }));
-    
-    // Thêm plugin cho Logging
-    store.add_plugin(Logging);
+    // Gửi khi kết nối hoặc kết nối lại
+    fn hello(&self) -> Message<T> {
+        Message::Hello { last: self.last }
+    }
 
-    // Tạo các action và dispatch chúng
-    let action = Action::Create(manager::account::Account {
-        username: "new_user".to_string(),
-        balance: 0.0,
-    });
+    // Đề xuất action lên Primary thay vì thay đổi state cục bộ
+    fn propose(&self, action: Action<T>) -> Message<T> {
+        Message::Propose {
+            base: self.last,
+            action,
+        }
+    }
 
-    // Sử dụng async_action_handler để xử lý bất đồng bộ
-    async_action_handler(effect, &mut database, &mut state).await;
+    // Áp dụng message từ Primary; trả về `Hello` để yêu cầu resync khi phát hiện thiếu action,
+    // hoặc `Rejected` khi đề xuất của Mirror bị từ chối
+    fn handle(&mut self, message: Message<T>) -> Result<Option<Message<T>>, Rejected> {
+        let next = self.last.checked_add(1);
+        match message {
+            Message::Action { seq, action } if Some(seq) == next => {
+                self.store.apply(action);
+                self.last = seq;
+                Ok(None)
+            }
+            Message::Action { seq, .. } if seq > self.last => Ok(Some(self.hello())),
+            Message::Snapshot { seq, state } => {
+                self.store.restore(state);
+                self.last = seq;
+                Ok(None)
+            }
+            Message::Reject { base, reason } => Err(Rejected { base, reason }),
+            // Action trùng lặp (đã áp dụng) hoặc message chỉ dành cho Primary
+            _ => Ok(None),
+        }
+    }
+}
+
+// Đề xuất của Mirror bị Primary từ chối
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rejected {
+    base: u64,
+    reason: String,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "đề xuất dựa trên seq {} bị từ chối: {}",
+            self.base, self.reason
+        )
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+// Định nghĩa Effect
+struct Effect<T: Actions + Clone + 'static> {
+    operation: Box<dyn FnMut(&mut Database<T>, &mut State<T>) + Send>,
+}
+
+impl<T: Actions + Clone + 'static> Effect<T> {
+    fn apply(&mut self, database: &mut Database<T>, state: &mut State<T>) {
+        (self.operation)(database, state);
+    }
+}
+
+// Giá trị của một trường khi ghi log
+#[derive(Debug, Clone)]
+enum Value {
+    Str(String),
+    Num(f64),
+    Bool(bool),
+}
 
-    store.dispatch(action); // Dispatch hành động bất đồng bộ
+impl Value {
+    fn to_json(&self) -> String {
+        match self {
+            Value::Str(s) => escape(s),
+            Value::Num(n) if n.is_finite() => n.to_string(),
+            Value::Num(_) => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+}
+
+// Một trường dữ liệu; `secret` tương đương với đánh dấu `#[redact]` trên trường
+#[derive(Debug, Clone)]
+struct Field {
+    name: &'static str,
+    value: Value,
+    secret: bool,
 }
 
+impl Field {
+    fn new(name: &'static str, value: Value) -> Self {
+        Field {
+            name,
+            value,
+            secret: false,
+        }
+    }
+
+    // Trường chứa thông tin nhạy cảm, luôn bị che khi ghi log
+    fn secret(name: &'static str, value: Value) -> Self {
+        Field {
+            name,
+            value,
+            secret: true,
+        }
+    }
+}
+
+// Trait để liệt kê các trường của dữ liệu thay vì in toàn bộ bằng `{:?}`
+trait Fields {
+    fn fields(&self) -> Vec<Field>;
+}
+
+// Escape chuỗi theo JSON
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Mức log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+// File log tự xoay vòng khi vượt quá kích thước: app.log -> app.log.1 -> app.log.2 ...
+struct Rotating {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl Rotating {
+    fn open(path: impl Into<PathBuf>, max_bytes: u64, max_files: usize) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Rotating {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn backup(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        for index in (1..self.max_files).rev() {
+            let from = self.backup(index);
+            if from.exists() {
+                std::fs::rename(&from, self.backup(index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            std::fs::rename(&self.path, self.backup(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+// Nơi ghi log: stdout hoặc file xoay vòng
+enum Sink {
+    Stdout,
+    File(Mutex<Rotating>),
+}
+
+// Logger có cấu trúc: ghi mỗi action thành một dòng JSON, che các trường nhạy cảm
+struct Logger {
+    sink: Sink,
+    default_level: Level,
+    min_level: Level,
+    levels: HashMap<&'static str, Level>,
+    redacted: Vec<&'static str>,
+    sample: u64,
+    counter: AtomicU64,
+}
+
+impl Logger {
+    fn new(sink: Sink) -> Self {
+        Logger {
+            sink,
+            default_level: Level::Info,
+            min_level: Level::Info,
+            levels: HashMap::new(),
+            redacted: Vec::new(),
+            sample: 1,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    // Mức log cho một loại action, ví dụ `delete` ở mức `Warn`
+    fn level(mut self, kind: &'static str, level: Level) -> Self {
+        self.levels.insert(kind, level);
+        self
+    }
+
+    // Bỏ qua các dòng có mức thấp hơn `level`
+    fn min_level(mut self, level: Level) -> Self {
+        self.min_level = level;
+        self
+    }
+
+    // Che thêm trường theo tên, ngoài các trường đã đánh dấu `secret`
+    fn redact(mut self, name: &'static str) -> Self {
+        self.redacted.push(name);
+        self
+    }
+
+    // Chỉ ghi 1 trên `every` action (0 và 1 đều nghĩa là ghi tất cả)
+    fn sample(mut self, every: u64) -> Self {
+        self.sample = every.max(1);
+        self
+    }
+
+    fn render(&self, fields: &[Field]) -> String {
+        let body: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                let value = if field.secret || self.redacted.contains(&field.name) {
+                    escape("[REDACTED]")
+                } else {
+                    field.value.to_json()
+                };
+                format!("{}:{}", escape(field.name), value)
+            })
+            .collect();
+        format!("{{{}}}", body.join(","))
+    }
+
+    fn line<T: Actions + Fields>(
+        &self,
+        level: Level,
+        action: &Action<T>,
+        state: &State<T>,
+    ) -> String {
+        let payload = match action {
+            Action::Create(data) | Action::Update(data) | Action::Delete(data) => data,
+        };
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let error = match &state.error {
+            Some(error) => escape(error),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"ts\":{},\"level\":\"{}\",\"action\":\"{}\",\"payload\":{},\"state\":{{\"is_loading\":{},\"error\":{},\"shared\":{}}}}}",
+            timestamp,
+            level.as_str(),
+            action.kind(),
+            self.render(&payload.fields()),
+            Value::Bool(state.is_loading).to_json(),
+            error,
+            self.render(&state.shared.fields()),
+        )
+    }
+
+    fn log<T: Actions + Fields>(&self, action: &Action<T>, state: &State<T>) {
+        let level = *self
+            .levels
+            .get(action.kind())
+            .unwrap_or(&self.default_level);
+        if level < self.min_level {
+            return;
+        }
+        if !self
+            .counter
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample)
+        {
+            return;
+        }
+        let line = self.line(level, action, state);
+        match &self.sink {
+            Sink::Stdout => println!("{}", line),
+            Sink::File(file) => {
+                if let Err(error) = file.lock().unwrap().write_line(&line) {
+                    eprintln!("Không thể ghi log: {}", error);
+                }
+            }
+        }
+    }
+
+    // Chuyển logger thành middleware cho Store
+    fn into_middleware<T: Actions + Clone + Fields + 'static>(self) -> Box<Middleware<T>> {
+        Box::new(move |action, state| self.log(action, state))
+    }
+}
+
+// Định nghĩa trait Plugin với generics
+trait Plugin<T>
+where
+    T: Actions + Clone + 'static,
+{
+    fn name(&self) -> String;
+    fn on_action(&self, action: &Action<T>, state: &State<T>);
+    // Gọi sau khi reducer đã chạy xong, kèm thời gian đo được của lần dispatch
+    fn on_dispatched(&self, _timing: &Timing, _state: &State<T>) {}
+    fn as_any(&self) -> &dyn Any;
+}
+
+// Thời gian đo được cho một lần dispatch
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    middleware: Duration,
+    reducer: Duration,
+}
+
+// Histogram với các bucket cố định (đơn vị giây), theo kiểu Prometheus
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: Vec<f64>,
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        let bounds = vec![0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0, 10.0];
+        Histogram {
+            buckets: vec![0; bounds.len()],
+            bounds,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter_mut()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn write(&self, name: &str, help: &str, out: &mut String) {
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} histogram\n",
+            name, help, name
+        ));
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            out.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, bucket));
+        }
+        out.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, self.count));
+        out.push_str(&format!(
+            "{}_sum {}\n{}_count {}\n",
+            name, self.sum, name, self.count
+        ));
+    }
+}
+
+// Bộ số liệu dùng chung giữa plugin và exporter
+#[derive(Debug)]
+struct Registry {
+    actions: HashMap<&'static str, u64>,
+    middleware: Histogram,
+    reducer: Histogram,
+    effect: Histogram,
+}
+
+// Plugin đo đạc: bộ đếm theo loại action và histogram thời gian. Span cho mỗi dispatch
+// được mở trong `Store::dispatch`.
+#[derive(Debug, Clone)]
+struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            registry: Arc::new(Mutex::new(Registry {
+                actions: HashMap::new(),
+                middleware: Histogram::new(),
+                reducer: Histogram::new(),
+                effect: Histogram::new(),
+            })),
+        }
+    }
+
+    // Chạy effect và ghi lại thời gian chạy vào histogram
+    fn apply<T: Actions + Clone + 'static>(
+        &self,
+        effect: &mut Effect<T>,
+        database: &mut Database<T>,
+        state: &mut State<T>,
+    ) {
+        let span = tracing::info_span!("effect");
+        let _guard = span.enter();
+        let started = Instant::now();
+        effect.apply(database, state);
+        let elapsed = started.elapsed();
+        tracing::debug!(duration_us = elapsed.as_micros() as u64, "effect applied");
+        self.registry.lock().unwrap().effect.observe(elapsed);
+    }
+
+    // Xuất số liệu theo định dạng văn bản của Prometheus
+    fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+        out.push_str("# HELP rim_actions_total Number of dispatched actions\n");
+        out.push_str("# TYPE rim_actions_total counter\n");
+        let mut kinds: Vec<_> = registry.actions.iter().collect();
+        kinds.sort();
+        for (kind, count) in kinds {
+            out.push_str(&format!(
+                "rim_actions_total{{kind=\"{}\"}} {}\n",
+                kind, count
+            ));
+        }
+        registry.middleware.write(
+            "rim_middleware_duration_seconds",
+            "Time spent in middlewares per dispatch",
+            &mut out,
+        );
+        registry.reducer.write(
+            "rim_reducer_duration_seconds",
+            "Time spent in the reducer per dispatch",
+            &mut out,
+        );
+        registry.effect.write(
+            "rim_effect_duration_seconds",
+            "Runtime of applied effects",
+            &mut out,
+        );
+        out
+    }
+}
+
+impl<T: Actions + Clone + 'static> Plugin<T> for Metrics {
+    fn name(&self) -> String {
+        "Metrics".to_string()
+    }
+
+    fn on_action(&self, action: &Action<T>, _state: &State<T>) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.actions.entry(action.kind()).or_insert(0) += 1;
+    }
+
+    // Chạy bên trong span `dispatch` do `Store::dispatch` mở
+    fn on_dispatched(&self, timing: &Timing, _state: &State<T>) {
+        tracing::debug!("action dispatched");
+        let mut registry = self.registry.lock().unwrap();
+        registry.middleware.observe(timing.middleware);
+        registry.reducer.observe(timing.reducer);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Exporter ghi số liệu ra file hoặc phục vụ qua một endpoint HTTP cục bộ
+struct Exporter {
+    metrics: Metrics,
+}
+
+impl Exporter {
+    fn new(metrics: Metrics) -> Self {
+        Exporter { metrics }
+    }
+
+    // Ghi ra file tạm rồi đổi tên để người đọc không thấy file ghi dở
+    fn write_to(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, self.metrics.render())?;
+        std::fs::rename(tmp, path)
+    }
+
+    // Phục vụ `GET /metrics` cho Prometheus scrape; lỗi của một kết nối không dừng vòng accept
+    async fn serve(self, listener: tokio::net::TcpListener) {
+        loop {
+            let (socket, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    // Thường là lỗi tạm thời (kết nối bị hủy, hết file descriptor)
+                    tracing::warn!(%error, "không thể accept kết nối metrics");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let metrics = self.metrics.clone();
+            tokio::spawn(async move {
+                if let Err(error) = Exporter::respond(&metrics, socket).await {
+                    tracing::warn!(%peer, %error, "không thể trả lời yêu cầu metrics");
+                }
+            });
+        }
+    }
+
+    async fn respond(metrics: &Metrics, mut socket: tokio::net::TcpStream) -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut request = [0u8; 1024];
+        let read = socket.read(&mut request).await?;
+        let response = if request[..read].starts_with(b"GET /metrics") {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+        };
+        socket.write_all(response.as_bytes()).await?;
+        socket.shutdown().await
+    }
+}
+
+// Hàm async để xử lý các tác vụ bất đồng bộ, thời gian chạy effect được ghi vào `metrics`
+async fn async_action_handler<T: Actions + Clone + 'static>(
+    mut effect: Effect<T>,
+    database: &mut Database<T>,
+    state: &mut State<T>,
+    metrics: &Metrics,
+) {
+    metrics.apply(&mut effect, database, state);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut store = Store::new(manager::account::Account {
+        username: "user".to_string(),
+        balance: 0.0,
+        password: String::new(),
+    });
+    let effect = Effect {
+        operation: Box::new(|_, _| {}),
+    }; // Khởi tạo effect tùy thuộc vào logic của bạn
+    let mut database = Database::new(); // Khởi tạo database
+    let mut state = State::new(manager::account::Account {
+        username: "user".to_string(),
+        balance: 0.0,
+        password: String::new(),
+    }); // Khởi tạo state
+
+    // Thêm middleware cho logging có cấu trúc, ghi ra file xoay vòng;
+    // mật khẩu luôn bị che, tên đăng nhập là dữ liệu cá nhân nên cũng không ghi ra
+    let sink = match Rotating::open("app.log", 10 * 1024 * 1024, 5) {
+        Ok(file) => Sink::File(Mutex::new(file)),
+        Err(_) => Sink::Stdout,
+    };
+    // Mức log tối thiểu và tỉ lệ lấy mẫu đọc từ biến môi trường, ví dụ RIM_LOG=debug RIM_LOG_SAMPLE=10
+    let min_level = std::env::var("RIM_LOG")
+        .ok()
+        .and_then(|name| Level::parse(&name))
+        .unwrap_or(Level::Info);
+    let sample = std::env::var("RIM_LOG_SAMPLE")
+        .ok()
+        .and_then(|every| every.parse().ok())
+        .unwrap_or(1);
+    let logger = Logger::new(sink)
+        .min_level(min_level)
+        .level("delete", Level::Warn)
+        .redact("username")
+        .sample(sample);
+    store.add_middleware(logger.into_middleware());
+
+    // Ngoài `authorize` mặc định: người không phải admin chỉ được cập nhật tài khoản của mình
+    store.add_guard(Box::new(|action, identity| match action {
+        Action::Update(account)
+            if account.username != identity.id && !identity.roles.contains(&Role::Admin) =>
+        {
+            Err(Unauthorized {
+                identity: identity.id.clone(),
+                action: action.kind(),
+            })
+        }
+        _ => Ok(()),
+    }));
+
+    // Thêm plugin đo đạc, giữ lại một bản sao để xuất số liệu
+    let metrics = Metrics::new();
+    store.add_plugin(metrics.clone());
+
+    // Phục vụ số liệu cho Prometheus trong lúc chương trình chạy
+    match tokio::net::TcpListener::bind("127.0.0.1:9898").await {
+        Ok(listener) => {
+            tokio::spawn(Exporter::new(metrics.clone()).serve(listener));
+        }
+        Err(error) => eprintln!("Không thể mở endpoint metrics: {}", error),
+    }
+
+    // Tạo các action và dispatch chúng
+    let action = Action::Create(manager::account::Account {
+        username: "new_user".to_string(),
+        balance: 0.0,
+        password: "hunter2".to_string(),
+    });
+
+    // Sử dụng async_action_handler để xử lý bất đồng bộ
+    async_action_handler(effect, &mut database, &mut state, &metrics).await;
+
+    let admin = Identity::new("admin", vec![Role::Admin]);
+    let editor = Identity::new("editor", vec![Role::Editor]);
+    if let Err(error) = store.dispatch(action.by(&editor)) {
+        eprintln!("{}", error);
+    }
+
+    // Viewer chỉ được xem, mọi action thay đổi state đều bị từ chối
+    let viewer = Identity::new("viewer", vec![Role::Viewer]);
+    let update = Action::Update(manager::account::Account {
+        username: "viewer".to_string(),
+        balance: 100.0,
+        password: String::new(),
+    });
+    if let Err(error) = store.dispatch(update.by(&viewer)) {
+        eprintln!("{}", error);
+    }
+
+    // Editor không được xóa tài khoản, lỗi được trả về cho presenter
+    let delete = || {
+        Action::Delete(manager::account::Account {
+            username: "new_user".to_string(),
+            balance: 0.0,
+            password: String::new(),
+        })
+    };
+    if let Err(error) = store.dispatch(delete().by(&editor)) {
+        eprintln!("{}", error);
+    }
+    if let Err(error) = store.dispatch(delete().by(&admin)) {
+        eprintln!("{}", error);
+    }
+
+    // Mỗi phiên có Store riêng nhưng dùng chung cấu hình với `store`; phiên bị loại được lưu ra đĩa
+    // Thư mục lưu phiên lấy từ RIM_SESSIONS, mặc định nằm trong thư mục tạm của hệ thống
+    let mut sessions = Sessions::new(store, "rim".to_string(), Duration::from_secs(15 * 60));
+    let directory = std::env::var_os("RIM_SESSIONS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("rim-sessions"));
+    match Directory::new(directory) {
+        Ok(directory) => sessions = sessions.storage(directory),
+        Err(error) => eprintln!("Không thể mở thư mục phiên: {}", error),
+    }
+    let opened = sessions.open("alice", || manager::account::Account {
+        username: "alice".to_string(),
+        balance: 0.0,
+        password: String::new(),
+    });
+    match opened {
+        Ok(session) => {
+            if let Err(error) = session.dispatch(delete().by(&editor)) {
+                eprintln!("{}: {}", session.global, error);
+            }
+        }
+        Err(error) => eprintln!("Không thể mở phiên: {}", error),
+    }
+    for (id, error) in sessions.evict_idle(Instant::now()).failed {
+        eprintln!("Không thể lưu phiên {}: {}", id, error);
+    }
+
+    // Bản sao (ví dụ tiến trình UI) đồng bộ với bản chính qua một kênh bất kỳ, ở đây là duplex;
+    // RIM_CONFLICT=serialize cho phép áp dụng cả đề xuất dựa trên state đã cũ
+    let conflict = match std::env::var("RIM_CONFLICT").as_deref() {
+        Ok("serialize") => Conflict::Serialize,
+        _ => Conflict::RejectStale,
+    };
+    let mut primary = Primary::new(
+        Store::new(manager::account::Account {
+            username: "user".to_string(),
+            balance: 0.0,
+            password: String::new(),
+        }),
+        64,
+        conflict,
+    );
+    let mut mirror = Mirror::new(Store::new(manager::account::Account {
+        username: "user".to_string(),
+        balance: 0.0,
+        password: String::new(),
+    }));
+    let (mut backend, mut ui) = tokio::io::duplex(4096);
+    let synced: std::io::Result<()> = async {
+        send(&mut ui, &mirror.hello()).await?;
+        let update = Action::Update(manager::account::Account {
+            username: "user".to_string(),
+            balance: 10.0,
+            password: String::new(),
+        });
+        send(&mut ui, &mirror.propose(update)).await?;
+        for _ in 0..2 {
+            if let Some(message) = recv(&mut backend).await? {
+                for reply in primary.handle(&editor, message) {
+                    send(&mut backend, &reply).await?;
+                }
+            }
+        }
+        drop(backend);
+        while let Some(message) = recv(&mut ui).await? {
+            if let Err(rejected) = mirror.handle(message) {
+                eprintln!("{}", rejected);
+            }
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(error) = synced {
+        eprintln!("Đồng bộ thất bại: {}", error);
+    }
+
+    if let Err(error) = Exporter::new(metrics).write_to(std::path::Path::new("metrics.prom")) {
+        eprintln!("Không thể ghi metrics: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::manager::account::Account;
+    use super::*;
+
+    fn account(username: &str, balance: f64) -> Account {
+        Account {
+            username: username.to_string(),
+            balance,
+            password: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirror_follows_primary_over_duplex() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let mut primary = Primary::new(Store::new(account("user", 0.0)), 16, Conflict::Serialize);
+        let mut mirror = Mirror::new(Store::new(account("user", 0.0)));
+        let (mut backend, mut ui) = tokio::io::duplex(4096);
+
+        let message = primary
+            .dispatch(Action::Update(account("user", 10.0)).by(&admin))
+            .unwrap();
+        send(&mut backend, &message).await.unwrap();
+        let message = recv::<Account, _>(&mut ui).await.unwrap().unwrap();
+        assert!(mirror.handle(message).unwrap().is_none());
+
+        // Mirror đề xuất, Primary áp dụng và phát lại cho Mirror
+        send(
+            &mut ui,
+            &mirror.propose(Action::Update(account("user", 25.0))),
+        )
+        .await
+        .unwrap();
+        let proposal = recv::<Account, _>(&mut backend).await.unwrap().unwrap();
+        for message in primary.handle(&admin, proposal) {
+            send(&mut backend, &message).await.unwrap();
+        }
+        let message = recv::<Account, _>(&mut ui).await.unwrap().unwrap();
+        mirror.handle(message).unwrap();
+
+        assert_eq!(mirror.last, 2);
+        assert_eq!(mirror.store.state.shared.balance, 25.0);
+        assert_eq!(primary.store.state.shared.balance, 25.0);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resyncs_from_snapshot() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let mut primary = Primary::new(Store::new(account("user", 0.0)), 2, Conflict::Serialize);
+        let mut mirror = Mirror::new(Store::new(account("user", 0.0)));
+
+        // Mirror mất kết nối trong khi Primary đã vượt quá log
+        for balance in 1..=5 {
+            primary
+                .dispatch(Action::Update(account("user", balance as f64)).by(&admin))
+                .unwrap();
+        }
+
+        let (mut backend, mut ui) = tokio::io::duplex(4096);
+        send(&mut ui, &mirror.hello()).await.unwrap();
+        let hello = recv::<Account, _>(&mut backend).await.unwrap().unwrap();
+        let replies = primary.handle(&admin, hello);
+        assert!(matches!(replies[..], [Message::Snapshot { seq: 5, .. }]));
+        for message in replies {
+            send(&mut backend, &message).await.unwrap();
+        }
+        let message = recv::<Account, _>(&mut ui).await.unwrap().unwrap();
+        mirror.handle(message).unwrap();
+
+        assert_eq!(mirror.last, 5);
+        assert_eq!(mirror.store.state.shared.balance, 5.0);
+
+        // Thiếu action ở giữa thì Mirror yêu cầu resync
+        primary
+            .dispatch(Action::Update(account("user", 6.0)).by(&admin))
+            .unwrap();
+        let gap = primary
+            .dispatch(Action::Update(account("user", 7.0)).by(&admin))
+            .unwrap();
+        assert!(matches!(
+            mirror.handle(gap),
+            Ok(Some(Message::Hello { last: 5 }))
+        ));
+        let replies = primary.handle(&admin, mirror.hello());
+        assert!(matches!(
+            replies[..],
+            [
+                Message::Action { seq: 6, .. },
+                Message::Action { seq: 7, .. }
+            ]
+        ));
+        for message in replies {
+            mirror.handle(message).unwrap();
+        }
+        assert_eq!(mirror.last, 7);
+        assert_eq!(mirror.store.state.shared.balance, 7.0);
+    }
+
+    #[test]
+    fn test_reject_stale_proposal() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let mut primary = Primary::new(Store::new(account("user", 0.0)), 16, Conflict::RejectStale);
+        let mut mirror = Mirror::new(Store::new(account("user", 0.0)));
+
+        primary
+            .dispatch(Action::Update(account("user", 1.0)).by(&admin))
+            .unwrap();
+        let mut replies =
+            primary.handle(&admin, mirror.propose(Action::Update(account("user", 2.0))));
+        assert!(matches!(replies[..], [Message::Reject { base: 0, .. }]));
+        assert_eq!(primary.store.state.shared.balance, 1.0);
+
+        // Lý do từ chối được trả về cho nơi gọi Mirror
+        let rejected = mirror.handle(replies.remove(0)).unwrap_err();
+        assert_eq!(rejected.base, 0);
+        assert_eq!(rejected.reason, "state đã thay đổi (seq 1)");
+        assert_eq!(mirror.last, 0);
+    }
+
+    #[test]
+    fn test_serialize_applies_stale_proposal() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let mut primary = Primary::new(Store::new(account("user", 0.0)), 16, Conflict::Serialize);
+        let mirror = Mirror::new(Store::new(account("user", 0.0)));
+
+        primary
+            .dispatch(Action::Update(account("user", 1.0)).by(&admin))
+            .unwrap();
+        let replies = primary.handle(&admin, mirror.propose(Action::Update(account("user", 2.0))));
+        assert!(matches!(replies[..], [Message::Action { seq: 2, .. }]));
+        assert_eq!(primary.store.state.shared.balance, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_recv_rejects_oversized_frame() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut backend, mut ui) = tokio::io::duplex(64);
+        backend
+            .write_all(&(MAX_FRAME as u32 + 1).to_be_bytes())
+            .await
+            .unwrap();
+        let error = recv::<Account, _>(&mut ui).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        // Frame có độ dài hợp lệ nhưng nội dung sai cũng bị từ chối
+        backend.write_all(&3u32.to_be_bytes()).await.unwrap();
+        backend.write_all(&[9, 9, 9]).await.unwrap();
+        let error = recv::<Account, _>(&mut ui).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        drop(backend);
+        assert!(recv::<Account, _>(&mut ui).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_crafted_sequence_numbers_do_not_overflow() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let mut primary = Primary::new(Store::new(account("user", 0.0)), 16, Conflict::Serialize);
+        primary
+            .dispatch(Action::Update(account("user", 1.0)).by(&admin))
+            .unwrap();
+        let replies = primary.handle(&admin, Message::Hello { last: u64::MAX });
+        assert!(matches!(replies[..], [Message::Snapshot { seq: 1, .. }]));
+
+        let mut mirror = Mirror::new(Store::new(account("user", 0.0)));
+        let snapshot = Message::Snapshot {
+            seq: u64::MAX,
+            state: account("user", 3.0),
+        };
+        assert!(mirror.handle(snapshot).unwrap().is_none());
+        let action = Message::Action {
+            seq: u64::MAX,
+            action: Action::Update(account("user", 4.0)),
+        };
+        assert!(mirror.handle(action).unwrap().is_none());
+        assert_eq!(mirror.last, u64::MAX);
+        assert_eq!(mirror.store.state.shared.balance, 3.0);
+    }
+
+    #[test]
+    fn test_default_store_rejects_delete_from_non_admin() {
+        let editor = Identity::new("editor", vec![Role::Editor]);
+        let viewer = Identity::new("viewer", vec![Role::Viewer]);
+        let mut store = Store::new(account("user", 5.0));
+        let seen = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&seen);
+        store.add_middleware(Box::new(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+        let before = Arc::clone(&store.state);
+
+        let error = store
+            .dispatch(Action::Delete(account("user", 0.0)).by(&editor))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Unauthorized {
+                identity: "editor".to_string(),
+                action: "delete",
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "'editor' không có quyền thực hiện 'delete'"
+        );
+        assert!(store
+            .dispatch(Action::Create(account("user", 0.0)).by(&viewer))
+            .is_err());
+
+        // State và middleware không bị chạm tới khi action bị từ chối
+        assert!(Arc::ptr_eq(&before, &store.state));
+        assert_eq!(store.state.shared.balance, 5.0);
+        assert_eq!(seen.load(Ordering::Relaxed), 0);
+
+        let admin = Identity::new("root", vec![Role::Viewer, Role::Admin]);
+        store
+            .dispatch(Action::Delete(account("user", 0.0)).by(&admin))
+            .unwrap();
+        assert_eq!(store.state.shared.balance, 0.0);
+        assert_eq!(seen.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_viewer_is_rejected_for_every_action() {
+        let viewer = Identity::new("viewer", vec![Role::Viewer]);
+        let mut store = Store::new(account("viewer", 5.0));
+        let before = Arc::clone(&store.state);
+
+        for action in [
+            Action::Create(account("viewer", 1.0)),
+            Action::Update(account("viewer", 2.0)),
+            Action::Delete(account("viewer", 0.0)),
+        ] {
+            let kind = action.kind();
+            assert_eq!(
+                store.dispatch(action.by(&viewer)).unwrap_err(),
+                Unauthorized {
+                    identity: "viewer".to_string(),
+                    action: kind,
+                }
+            );
+        }
+        assert!(Arc::ptr_eq(&before, &store.state));
+    }
+
+    #[test]
+    fn test_added_guard_runs_with_default_guard() {
+        let alice = Identity::new("alice", vec![Role::Editor]);
+        let mut store = Store::new(account("alice", 0.0));
+        store.add_guard(Box::new(|action, identity| match action {
+            Action::Update(data) if data.username != identity.id => Err(Unauthorized {
+                identity: identity.id.clone(),
+                action: action.kind(),
+            }),
+            _ => Ok(()),
+        }));
+
+        store
+            .dispatch(Action::Update(account("alice", 3.0)).by(&alice))
+            .unwrap();
+        assert!(store
+            .dispatch(Action::Update(account("bob", 4.0)).by(&alice))
+            .is_err());
+        assert!(store
+            .dispatch(Action::Delete(account("alice", 0.0)).by(&alice))
+            .is_err());
+        assert_eq!(store.state.shared.username, "alice");
+        assert_eq!(store.state.shared.balance, 3.0);
+
+        // Store con tạo bằng `fork` giữ nguyên các guard
+        let mut fork = store.fork(State::new(account("alice", 0.0)));
+        assert!(fork
+            .dispatch(Action::Update(account("bob", 1.0)).by(&alice))
+            .is_err());
+    }
+
+    #[test]
+    fn test_sessions_are_isolated() {
+        let alice = Identity::new("alice", vec![Role::Editor]);
+        let metrics = Metrics::new();
+        let mut template = Store::new(account("template", 0.0));
+        template.add_plugin(metrics.clone());
+        let mut sessions = Sessions::new(template, "catalog".to_string(), Duration::from_secs(60));
+
+        sessions
+            .open("alice", || account("alice", 1.0))
+            .unwrap()
+            .dispatch(Action::Update(account("alice", 10.0)).by(&alice))
+            .unwrap();
+        let bob = sessions.open("bob", || account("bob", 2.0)).unwrap();
+        assert_eq!(bob.store.state.shared.balance, 2.0);
+        // Guard mặc định của template áp dụng cho mọi phiên
+        assert!(bob
+            .dispatch(Action::Delete(account("bob", 0.0)).by(&alice))
+            .is_err());
+
+        assert!(Arc::ptr_eq(
+            &sessions.global,
+            &sessions.sessions["bob"].global
+        ));
+        assert_eq!(sessions.sessions["alice"].store.state.shared.balance, 10.0);
+        assert_eq!(sessions.template.state.shared.username, "template");
+        // Plugin được chia sẻ nên số liệu gộp lại từ mọi phiên
+        assert!(metrics
+            .render()
+            .contains("rim_actions_total{kind=\"update\"} 1\n"));
+    }
+
+    #[test]
+    fn test_dispatch_keeps_session_alive() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let idle = Duration::from_secs(60);
+        let mut sessions = Sessions::new(Store::new(account("user", 0.0)), (), idle);
+        sessions.open("active", || account("active", 0.0)).unwrap();
+        let opened = sessions
+            .open("idle", || account("idle", 0.0))
+            .unwrap()
+            .last_active;
+
+        std::thread::sleep(Duration::from_millis(5));
+        sessions
+            .sessions
+            .get_mut("active")
+            .unwrap()
+            .dispatch(Action::Update(account("active", 1.0)).by(&admin))
+            .unwrap();
+
+        assert!(sessions.evict_idle(opened).evicted.is_empty());
+        assert_eq!(sessions.evict_idle(opened + idle).evicted, vec!["idle"]);
+        assert!(sessions.sessions.contains_key("active"));
+        assert!(!sessions.sessions.contains_key("idle"));
+    }
+
+    #[test]
+    fn test_evicted_state_is_persisted() {
+        let dir = temp_dir("sessions");
+        let idle = Duration::from_secs(1);
+        let mut sessions = Sessions::new(Store::new(account("user", 0.0)), (), idle)
+            .storage(Directory::new(&dir).unwrap());
+        let session = sessions.open("../alice", || account("alice", 7.0)).unwrap();
+        let state = Arc::make_mut(&mut session.store.state);
+        state.is_loading = true;
+        state.error = Some("hết thời gian".to_string());
+
+        let now = Instant::now() + idle;
+        assert_eq!(sessions.evict_idle(now).evicted, vec!["../alice"]);
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+
+        let restored = sessions
+            .open("../alice", || unreachable!("state phải được khôi phục"))
+            .unwrap();
+        assert_eq!(restored.store.state.shared.balance, 7.0);
+        assert!(restored.store.state.is_loading);
+        assert_eq!(restored.store.state.error.as_deref(), Some("hết thời gian"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // File hỏng được báo lỗi thay vì âm thầm tạo state mới
+        std::fs::write(Directory::new(&dir).unwrap().file("bob"), b"\x07").unwrap();
+        assert!(sessions.open("bob", || account("bob", 0.0)).is_err());
+    }
+
+    // Lưu vào bộ nhớ nhưng báo lỗi với các id cho trước
+    struct Flaky {
+        inner: Memory<manager::account::Account>,
+        broken: Vec<&'static str>,
+    }
+
+    impl Persist<manager::account::Account> for Flaky {
+        fn save(
+            &mut self,
+            id: &str,
+            state: &State<manager::account::Account>,
+        ) -> std::io::Result<()> {
+            if self.broken.contains(&id) {
+                return Err(std::io::Error::other("đĩa đầy"));
+            }
+            self.inner.save(id, state)
+        }
+
+        fn take(&mut self, id: &str) -> std::io::Result<Option<State<manager::account::Account>>> {
+            self.inner.take(id)
+        }
+    }
+
+    #[test]
+    fn test_evict_idle_continues_after_failed_save() {
+        let idle = Duration::from_secs(1);
+        let mut sessions =
+            Sessions::new(Store::new(account("user", 0.0)), (), idle).storage(Flaky {
+                inner: Memory::new(),
+                broken: vec!["bob"],
+            });
+        for id in ["alice", "bob", "carol"] {
+            sessions.open(id, || account(id, 1.0)).unwrap();
+        }
+
+        let eviction = sessions.evict_idle(Instant::now() + idle);
+        assert_eq!(eviction.evicted, vec!["alice", "carol"]);
+        assert_eq!(eviction.failed.len(), 1);
+        assert_eq!(eviction.failed[0].0, "bob");
+        assert_eq!(eviction.failed[0].1.to_string(), "đĩa đầy");
+        // Phiên lưu lỗi vẫn còn trong bộ nhớ, các phiên khác đã được lưu và có thể khôi phục
+        assert_eq!(
+            sessions.sessions.keys().collect::<Vec<_>>(),
+            vec![&"bob".to_string()]
+        );
+        let alice = sessions
+            .open("alice", || unreachable!("state phải được khôi phục"))
+            .unwrap();
+        assert_eq!(alice.store.state.shared.username, "alice");
+    }
+
+    #[test]
+    fn test_metrics_counts_actions_per_kind() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let metrics = Metrics::new();
+        let mut store = Store::new(account("user", 0.0));
+        store.add_plugin(metrics.clone());
+
+        store
+            .dispatch(Action::Create(account("a", 0.0)).by(&admin))
+            .unwrap();
+        store
+            .dispatch(Action::Create(account("b", 0.0)).by(&admin))
+            .unwrap();
+        store
+            .dispatch(Action::Update(account("b", 1.0)).by(&admin))
+            .unwrap();
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE rim_actions_total counter\n"));
+        assert!(text.contains("rim_actions_total{kind=\"create\"} 2\n"));
+        assert!(text.contains("rim_actions_total{kind=\"update\"} 1\n"));
+        assert!(!text.contains("kind=\"delete\""));
+        assert!(text.contains("rim_reducer_duration_seconds_count 3\n"));
+        assert!(text.contains("rim_middleware_duration_seconds_count 3\n"));
+        assert!(text.contains("rim_effect_duration_seconds_count 0\n"));
+    }
+
+    // Subscriber ghi lại span, các trường đã có giá trị và span bao quanh mỗi log
+    #[derive(Default)]
+    struct Recorded {
+        spans: Vec<(&'static str, Vec<&'static str>)>,
+        stack: Vec<usize>,
+        events: Vec<Vec<&'static str>>,
+    }
+
+    struct Recorder(Arc<Mutex<Recorded>>);
+
+    struct Names<'a>(&'a mut Vec<&'static str>);
+
+    impl tracing::field::Visit for Names<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, _: &dyn std::fmt::Debug) {
+            self.0.push(field.name());
+        }
+    }
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut recorded = self.0.lock().unwrap();
+            let mut fields = Vec::new();
+            span.record(&mut Names(&mut fields));
+            recorded.spans.push((span.metadata().name(), fields));
+            tracing::span::Id::from_u64(recorded.spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut recorded = self.0.lock().unwrap();
+            let at = span.into_u64() as usize - 1;
+            values.record(&mut Names(&mut recorded.spans[at].1));
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {
+            let mut recorded = self.0.lock().unwrap();
+            let names = recorded
+                .stack
+                .iter()
+                .map(|&at| recorded.spans[at].0)
+                .collect();
+            recorded.events.push(names);
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            let mut recorded = self.0.lock().unwrap();
+            recorded.stack.push(span.into_u64() as usize - 1);
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.0.lock().unwrap().stack.pop();
+        }
+    }
+
+    #[test]
+    fn test_dispatch_span_wraps_middleware_and_reducer() {
+        let admin = Identity::new("admin", vec![Role::Admin]);
+        let mut store = Store::new(account("user", 0.0));
+        store.add_plugin(Metrics::new());
+        store.add_middleware(Box::new(|_, _| tracing::info!("middleware ran")));
+        let recorded = Arc::new(Mutex::new(Recorded::default()));
+        tracing::subscriber::with_default(Recorder(recorded.clone()), || {
+            store
+                .dispatch(Action::Create(account("a", 0.0)).by(&admin))
+                .unwrap();
+        });
+
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded.spans.len(), 1);
+        let (name, fields) = &recorded.spans[0];
+        assert_eq!(*name, "dispatch");
+        assert_eq!(*fields, ["kind", "middleware_us", "reducer_us"]);
+        // Log của middleware và của plugin Metrics đều nằm trong span
+        assert_eq!(recorded.events, [vec!["dispatch"], vec!["dispatch"]]);
+        assert!(recorded.stack.is_empty());
+    }
+
+    #[test]
+    fn test_rejected_dispatch_span_has_no_durations() {
+        let editor = Identity::new("editor", vec![Role::Editor]);
+        let mut store = Store::new(account("user", 0.0));
+        let recorded = Arc::new(Mutex::new(Recorded::default()));
+        tracing::subscriber::with_default(Recorder(recorded.clone()), || {
+            let result = store.dispatch(Action::Delete(account("user", 0.0)).by(&editor));
+            assert!(result.is_err());
+        });
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded.spans, [("dispatch", vec!["kind"])]);
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new();
+        histogram.observe(Duration::from_micros(5));
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_secs(20));
+        assert_eq!(histogram.buckets, vec![1, 1, 1, 1, 2, 2, 2]);
+        assert_eq!(histogram.count, 3);
+
+        let mut text = String::new();
+        histogram.write("latency", "Latency", &mut text);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "# HELP latency Latency");
+        assert_eq!(lines[1], "# TYPE latency histogram");
+        assert_eq!(lines[2], "latency_bucket{le=\"0.00001\"} 1");
+        assert_eq!(lines[6], "latency_bucket{le=\"0.1\"} 2");
+        assert_eq!(lines[9], "latency_bucket{le=\"+Inf\"} 3");
+        assert!(lines[10].starts_with("latency_sum 20.05"));
+        assert_eq!(lines[11], "latency_count 3");
+    }
+
+    #[tokio::test]
+    async fn test_effect_runtime_is_observed() {
+        let metrics = Metrics::new();
+        let mut database = Database::new();
+        let mut state = State::new(account("user", 0.0));
+        let effect = Effect {
+            operation: Box::new(
+                |database: &mut Database<Account>, state: &mut State<Account>| {
+                    database
+                        .storage
+                        .insert("user".to_string(), state.shared.clone());
+                    state.is_loading = true;
+                },
+            ),
+        };
+
+        async_action_handler(effect, &mut database, &mut state, &metrics).await;
+
+        assert!(state.is_loading);
+        assert!(database.storage.contains_key("user"));
+        assert_eq!(metrics.registry.lock().unwrap().effect.count, 1);
+        assert!(metrics
+            .render()
+            .contains("rim_effect_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_exporter_keeps_serving_after_client_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let metrics = Metrics::new();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Exporter::new(metrics.clone()).serve(listener));
+
+        // Client đóng kết nối ngay mà không gửi gì
+        drop(tokio::net::TcpStream::connect(addr).await.unwrap());
+
+        let request = |path: &'static str| async move {
+            let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+            socket
+                .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            socket.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = request("/other").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let response = request("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE rim_reducer_duration_seconds histogram"));
+    }
+
+    // Thư mục tạm riêng cho mỗi test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rim-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_logger(path: &std::path::Path) -> Logger {
+        Logger::new(Sink::File(Mutex::new(
+            Rotating::open(path, 1 << 20, 1).unwrap(),
+        )))
+    }
+
+    fn read_lines(path: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_logger_redacts_secret_and_named_fields() {
+        let logger = Logger::new(Sink::Stdout).redact("username");
+        let mut secret = account("alice \"a\"", 12.5);
+        secret.password = "hunter2".to_string();
+        let mut state = State::new(secret.clone());
+        state.error = Some("lỗi\nmạng".to_string());
+
+        let line = logger.line(Level::Info, &Action::Update(secret.clone()), &state);
+        assert!(line.starts_with("{\"ts\":"));
+        assert!(line.contains("\"level\":\"info\",\"action\":\"update\""));
+        assert!(line.contains(
+            "\"payload\":{\"username\":\"[REDACTED]\",\"balance\":12.5,\"password\":\"[REDACTED]\"}"
+        ));
+        assert!(line.contains("\"is_loading\":false,\"error\":\"lỗi\\nmạng\""));
+        assert!(!line.contains("hunter2"));
+        assert!(!line.contains("alice"));
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+
+        // Không cấu hình thêm thì chỉ trường `secret` bị che
+        let line = Logger::new(Sink::Stdout).render(&secret.fields());
+        assert_eq!(
+            line,
+            "{\"username\":\"alice \\\"a\\\"\",\"balance\":12.5,\"password\":\"[REDACTED]\"}"
+        );
+    }
+
+    #[test]
+    fn test_logger_levels_per_action_kind() {
+        let dir = temp_dir("levels");
+        let path = dir.join("app.log");
+        let logger = file_logger(&path)
+            .min_level(Level::Warn)
+            .level("delete", Level::Error);
+        let state = State::new(account("user", 0.0));
+
+        logger.log(&Action::Create(account("user", 1.0)), &state);
+        logger.log(&Action::Delete(account("user", 1.0)), &state);
+        logger.log(&Action::Update(account("user", 2.0)), &state);
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("\"level\":\"error\",\"action\":\"delete\""));
+    }
+
+    #[test]
+    fn test_level_parse() {
+        assert_eq!(Level::parse("trace"), Some(Level::Trace));
+        assert_eq!(Level::parse("DEBUG"), Some(Level::Debug));
+        assert_eq!(Level::parse("Error"), Some(Level::Error));
+        assert_eq!(Level::parse("verbose"), None);
+        assert!(Level::Trace < Level::Debug && Level::Warn < Level::Error);
+    }
+
+    #[test]
+    fn test_logger_sampling() {
+        let dir = temp_dir("sampling");
+        let path = dir.join("app.log");
+        let logger = file_logger(&path).sample(3);
+        let state = State::new(account("user", 0.0));
+
+        for balance in 0..7 {
+            logger.log(&Action::Update(account("user", balance as f64)), &state);
+        }
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("\"balance\":0"));
+        assert!(lines[1].contains("\"balance\":3"));
+        assert!(lines[2].contains("\"balance\":6"));
+    }
+
+    #[test]
+    fn test_rotating_file_keeps_max_files() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("app.log");
+        let mut file = Rotating::open(&path, 40, 2).unwrap();
+        for index in 1..=7 {
+            file.write_line(&format!("line {:09}", index)).unwrap();
+        }
+        drop(file);
+
+        let backup = |index: usize| dir.join(format!("app.log.{}", index));
+        assert_eq!(read_lines(&path), vec!["line 000000007"]);
+        assert_eq!(
+            read_lines(&backup(1)),
+            vec!["line 000000005", "line 000000006"]
+        );
+        assert_eq!(
+            read_lines(&backup(2)),
+            vec!["line 000000003", "line 000000004"]
+        );
+        assert!(!backup(3).exists());
+
+        // Mở lại tiếp tục đếm kích thước file hiện có
+        let mut file = Rotating::open(&path, 40, 2).unwrap();
+        file.write_line("line 000000008").unwrap();
+        file.write_line("line 000000009").unwrap();
+        assert_eq!(read_lines(&path), vec!["line 000000009"]);
+        assert_eq!(
+            read_lines(&backup(1)),
+            vec!["line 000000007", "line 000000008"]
+        );
+    }
+}
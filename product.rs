@@ -0,0 +1,8 @@
+// Định nghĩa cấu trúc dữ liệu cho thông tin sản phẩm
+#[derive(Debug)]
+pub struct Product {
+    pub name: String,
+    pub price: f64,
+    pub available: bool,
+    // Thêm các trường dữ liệu khác tùy thuộc vào yêu cầu của ứng dụng
+}
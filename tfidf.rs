@@ -1,166 +1,8968 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Một token cùng vị trí của nó trong văn bản gốc
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    // Thứ tự của token trong luồng token
+    position: usize,
+    // Vị trí byte [start, end) trong văn bản gốc
+    start: usize,
+    end: usize,
+}
 
 struct Tokenizer;
 
-impl Tokenizer {
-    fn tokenize(text: &str) -> HashSet<String> {
-        text.to_lowercase()
-            .split_whitespace()
-            .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric()))
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect()
+impl Tokenizer {
+    fn tokenize(text: &str) -> Vec<Token> {
+        Tokenizer::split(text, char::is_alphanumeric)
+    }
+
+    // Tách theo khoảng trắng, bỏ các ký tự đầu/cuối không thỏa `keep`, chuyển về chữ thường
+    fn split(text: &str, keep: impl Fn(char) -> bool) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut word_start = None;
+        for (index, c) in text
+            .char_indices()
+            .chain(std::iter::once((text.len(), ' ')))
+        {
+            match (c.is_whitespace(), word_start) {
+                (false, None) => word_start = Some(index),
+                (true, Some(start)) => {
+                    word_start = None;
+                    let word = &text[start..index];
+                    let leading = word.trim_start_matches(|c: char| !keep(c));
+                    let trimmed = leading.trim_end_matches(|c: char| !keep(c));
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    let offset = start + word.len() - leading.len();
+                    tokens.push(Token {
+                        text: trimmed.to_lowercase(),
+                        position: tokens.len(),
+                        start: offset,
+                        end: offset + trimmed.len(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        tokens
+    }
+
+    // Đếm số lần xuất hiện của mỗi term
+    fn term_counts(tokens: &[Token]) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for token in tokens {
+            *counts.entry(token.text.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+// Các nguyên âm tiếng Việt theo 6 thanh: ngang, huyền, sắc, hỏi, ngã, nặng
+const VOWELS: [[char; 6]; 12] = [
+    ['a', 'à', 'á', 'ả', 'ã', 'ạ'],
+    ['ă', 'ằ', 'ắ', 'ẳ', 'ẵ', 'ặ'],
+    ['â', 'ầ', 'ấ', 'ẩ', 'ẫ', 'ậ'],
+    ['e', 'è', 'é', 'ẻ', 'ẽ', 'ẹ'],
+    ['ê', 'ề', 'ế', 'ể', 'ễ', 'ệ'],
+    ['i', 'ì', 'í', 'ỉ', 'ĩ', 'ị'],
+    ['o', 'ò', 'ó', 'ỏ', 'õ', 'ọ'],
+    ['ô', 'ồ', 'ố', 'ổ', 'ỗ', 'ộ'],
+    ['ơ', 'ờ', 'ớ', 'ở', 'ỡ', 'ợ'],
+    ['u', 'ù', 'ú', 'ủ', 'ũ', 'ụ'],
+    ['ư', 'ừ', 'ứ', 'ử', 'ữ', 'ự'],
+    ['y', 'ỳ', 'ý', 'ỷ', 'ỹ', 'ỵ'],
+];
+
+// Dấu thanh dạng tổ hợp, theo thứ tự cột của VOWELS
+const TONE_MARKS: [char; 6] = ['\0', '\u{300}', '\u{301}', '\u{309}', '\u{303}', '\u{323}'];
+
+// Tìm (hàng, thanh) của một nguyên âm chữ thường trong VOWELS
+fn vowel(c: char) -> Option<(usize, usize)> {
+    VOWELS
+        .iter()
+        .enumerate()
+        .find_map(|(row, tones)| tones.iter().position(|&v| v == c).map(|tone| (row, tone)))
+}
+
+fn is_combining(c: char) -> bool {
+    ('\u{300}'..='\u{36f}').contains(&c)
+}
+
+// Ghép một dấu tổ hợp vào nguyên âm đứng trước; `None` nếu không ghép được
+fn compose(last: char, mark: char) -> Option<char> {
+    let upper = last.is_uppercase();
+    let (row, tone) = vowel(last.to_lowercase().next()?)?;
+    let (row, tone) = match mark {
+        '\u{302}' => (
+            match row {
+                0 => 2,
+                3 => 4,
+                6 => 7,
+                _ => return None,
+            },
+            tone,
+        ),
+        '\u{306}' if row == 0 => (1, tone),
+        '\u{31b}' => (
+            match row {
+                6 => 8,
+                9 => 10,
+                _ => return None,
+            },
+            tone,
+        ),
+        mark => match TONE_MARKS.iter().skip(1).position(|&m| m == mark) {
+            Some(index) if tone == 0 => (row, index + 1),
+            _ => return None,
+        },
+    };
+    let composed = VOWELS[row][tone];
+    Some(if upper {
+        composed.to_uppercase().next().unwrap_or(composed)
+    } else {
+        composed
+    })
+}
+
+// Chuẩn hóa NFC cho chữ tiếng Việt: ghép nguyên âm với dấu mũ/trăng/móc và dấu thanh tổ hợp
+fn nfc(text: &str) -> String {
+    let mut out: Vec<char> = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match out.last().and_then(|&last| compose(last, c)) {
+            Some(composed) => *out.last_mut().unwrap() = composed,
+            None => out.push(c),
+        }
+    }
+    out.into_iter().collect()
+}
+
+// Bỏ dấu tiếng Việt: "máy tính" -> "may tinh", "Đường" -> "Duong"
+fn fold(text: &str) -> String {
+    const BASES: [char; 12] = ['a', 'a', 'a', 'e', 'e', 'i', 'o', 'o', 'o', 'u', 'u', 'y'];
+    nfc(text)
+        .chars()
+        .filter(|&c| !is_combining(c))
+        .map(|c| {
+            let lower = c.to_lowercase().next().unwrap_or(c);
+            let base = match (lower, vowel(lower)) {
+                ('đ', _) => 'd',
+                (_, Some((row, _))) => BASES[row],
+                _ => return c,
+            };
+            if c.is_uppercase() {
+                base.to_ascii_uppercase()
+            } else {
+                base
+            }
+        })
+        .collect()
+}
+
+// Âm tiết tiếng Việt: [phụ âm đầu] vần [phụ âm cuối], cùng mô hình với Syllable trong job-1.rs
+#[derive(Debug, Clone, PartialEq)]
+struct Syllable {
+    consonant: Option<String>,
+    vowel: String,
+    consonant_ending: Option<String>,
+}
+
+impl Syllable {
+    // Phụ âm đầu và cuối, xếp từ dài đến ngắn để khớp dài nhất trước
+    const CONSONANTS: [&'static str; 27] = [
+        "ngh", "ng", "nh", "ch", "gh", "gi", "kh", "ph", "qu", "th", "tr", "b", "c", "d", "đ", "g",
+        "h", "k", "l", "m", "n", "p", "r", "s", "t", "v", "x",
+    ];
+    const ENDINGS: [&'static str; 8] = ["ng", "nh", "ch", "c", "m", "n", "p", "t"];
+
+    // Phân tích một âm tiết đã chuẩn hóa chữ thường; `None` nếu không phải âm tiết tiếng Việt
+    fn parse(text: &str) -> Option<Syllable> {
+        let consonant = Syllable::CONSONANTS
+            .iter()
+            .find(|c| text.starts_with(*c) && text.len() > c.len())
+            .map(|c| c.to_string());
+        let rest = &text[consonant.as_ref().map_or(0, String::len)..];
+        let consonant_ending = Syllable::ENDINGS
+            .iter()
+            .find(|c| rest.ends_with(*c) && rest.len() > c.len())
+            .map(|c| c.to_string());
+        let vowel = &rest[..rest.len() - consonant_ending.as_ref().map_or(0, String::len)];
+        if vowel.is_empty()
+            || vowel.chars().count() > 3
+            || !vowel.chars().all(|c| self::vowel(c).is_some())
+        {
+            return None;
+        }
+        Some(Syllable {
+            consonant,
+            vowel: vowel.to_string(),
+            consonant_ending,
+        })
+    }
+}
+
+// Bộ phân tích tiếng Việt: chuẩn hóa NFC, tùy chọn bỏ dấu và ghép từ nhiều âm tiết theo từ điển
+#[derive(Debug, Clone)]
+struct Vietnamese {
+    fold: bool,
+    // Các từ ghép như khi được thêm, để dựng lại từ điển khi đổi cách chuẩn hóa
+    words: Vec<String>,
+    // Các từ ghép đã chuẩn hóa, âm tiết cách nhau bởi khoảng trắng
+    dictionary: HashSet<String>,
+    max_syllables: usize,
+}
+
+impl Vietnamese {
+    fn new() -> Self {
+        let mut vietnamese = Vietnamese {
+            fold: false,
+            words: Vec::new(),
+            dictionary: HashSet::new(),
+            max_syllables: 1,
+        };
+        vietnamese.add_words(&[
+            "máy tính",
+            "máy ảnh",
+            "điện thoại",
+            "tai nghe",
+            "đồng hồ",
+            "quần áo",
+            "áo sơ mi",
+            "giày dép",
+            "túi xách",
+            "thời trang",
+            "sản phẩm",
+            "khuyến mãi",
+            "giỏ hàng",
+            "thanh toán",
+            "tài khoản",
+            "đơn hàng",
+            "tìm kiếm",
+            "vận chuyển",
+            "giao hàng",
+        ]);
+        vietnamese
+    }
+
+    // Bỏ dấu khi phân tích, để "may tinh" khớp với "máy tính"
+    fn with_folding(mut self, fold: bool) -> Self {
+        self.fold = fold;
+        self.dictionary.clear();
+        let words = std::mem::take(&mut self.words);
+        for word in &words {
+            self.insert(word);
+        }
+        self.words = words;
+        self
+    }
+
+    fn add_words(&mut self, words: &[&str]) {
+        for word in words {
+            self.add_word(word);
+        }
+    }
+
+    fn add_word(&mut self, word: &str) {
+        self.words.push(word.to_string());
+        self.insert(word);
+    }
+
+    // Thêm từ vào từ điển theo cách chuẩn hóa hiện tại
+    fn insert(&mut self, word: &str) {
+        let syllables: Vec<String> = word.split_whitespace().map(|s| self.normalize(s)).collect();
+        self.max_syllables = self.max_syllables.max(syllables.len());
+        self.dictionary.insert(syllables.join(" "));
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        let text = nfc(text).to_lowercase();
+        if self.fold {
+            fold(&text)
+        } else {
+            text
+        }
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut syllables = Tokenizer::split(text, |c| c.is_alphanumeric() || is_combining(c));
+        for token in &mut syllables {
+            token.text = self.normalize(&token.text);
+        }
+
+        // Ghép theo khớp dài nhất, chỉ ghép các âm tiết hợp lệ cách nhau bởi khoảng trắng
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < syllables.len() {
+            let mut length = 1;
+            for n in (2..=self.max_syllables.min(syllables.len() - i)).rev() {
+                let window = &syllables[i..i + n];
+                let joined = window
+                    .windows(2)
+                    .all(|pair| text[pair[0].end..pair[1].start].trim().is_empty())
+                    && window.iter().all(|t| Syllable::parse(&t.text).is_some());
+                let key: Vec<&str> = window.iter().map(|t| t.text.as_str()).collect();
+                if joined && self.dictionary.contains(&key.join(" ")) {
+                    length = n;
+                    break;
+                }
+            }
+            let window = &syllables[i..i + length];
+            let words: Vec<&str> = window.iter().map(|t| t.text.as_str()).collect();
+            tokens.push(Token {
+                text: words.join("_"),
+                position: tokens.len(),
+                start: window[0].start,
+                end: window[length - 1].end,
+            });
+            i += length;
+        }
+        tokens
+    }
+}
+
+// Bộ phân tích văn bản thành token, dùng chung cho fit, transform và truy vấn
+trait Analyzer: Send + Sync {
+    fn analyze(&self, text: &str) -> Vec<Token>;
+}
+
+impl Analyzer for Tokenizer {
+    fn analyze(&self, text: &str) -> Vec<Token> {
+        Tokenizer::tokenize(text)
+    }
+}
+
+impl Analyzer for Vietnamese {
+    fn analyze(&self, text: &str) -> Vec<Token> {
+        self.tokenize(text)
+    }
+}
+
+// Một ký tự sau char filter, kèm khoảng byte [start, end) tương ứng trong văn bản gốc
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Mapped {
+    c: char,
+    start: usize,
+    end: usize,
+}
+
+// Biến đổi chuỗi ký tự trước khi tách token, giữ ánh xạ về văn bản gốc
+trait CharFilter: Send + Sync {
+    fn filter(&self, chars: Vec<Mapped>) -> Vec<Mapped>;
+}
+
+// Tách chuỗi ký tự đã lọc thành token
+trait Tokenize: Send + Sync {
+    fn tokenize(&self, chars: &[Mapped]) -> Vec<Token>;
+}
+
+// Biến đổi, loại bỏ hoặc thêm token sau khi tách
+trait TokenFilter: Send + Sync {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token>;
+}
+
+// Bỏ thẻ HTML và thay bằng khoảng trắng để không dính hai từ vào nhau
+struct HtmlStrip;
+
+impl CharFilter for HtmlStrip {
+    fn filter(&self, chars: Vec<Mapped>) -> Vec<Mapped> {
+        let mut out = Vec::with_capacity(chars.len());
+        // Các ký tự từ dấu '<' chưa đóng; không có '>' theo sau (hoặc gặp '<' khác)
+        // thì đó không phải thẻ, ví dụ "giá < 100k", và được giữ lại
+        let mut tag: Vec<Mapped> = Vec::new();
+        for mapped in chars {
+            match mapped.c {
+                '<' => {
+                    out.append(&mut tag);
+                    tag.push(mapped);
+                }
+                '>' if !tag.is_empty() => {
+                    out.push(Mapped {
+                        c: ' ',
+                        start: tag[0].start,
+                        end: mapped.end,
+                    });
+                    tag.clear();
+                }
+                _ if !tag.is_empty() => tag.push(mapped),
+                _ => out.push(mapped),
+            }
+        }
+        out.append(&mut tag);
+        out
+    }
+}
+
+// Chuẩn hóa Unicode NFC cho chữ tiếng Việt
+struct Nfc;
+
+impl CharFilter for Nfc {
+    fn filter(&self, chars: Vec<Mapped>) -> Vec<Mapped> {
+        let mut out: Vec<Mapped> = Vec::with_capacity(chars.len());
+        for mapped in chars {
+            match out.last_mut() {
+                Some(last) => match compose(last.c, mapped.c) {
+                    Some(composed) => {
+                        last.c = composed;
+                        last.end = mapped.end;
+                    }
+                    None => out.push(mapped),
+                },
+                None => out.push(mapped),
+            }
+        }
+        out
+    }
+}
+
+// Gom các ký tự thỏa `keep` liên tiếp thành từ, trả về (vị trí bắt đầu, vị trí kết thúc) trong `chars`
+fn runs(chars: &[Mapped], keep: impl Fn(char) -> bool) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (index, mapped) in chars.iter().enumerate() {
+        match (keep(mapped.c), start) {
+            (true, None) => start = Some(index),
+            (false, Some(begin)) => {
+                runs.push((begin, index));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        runs.push((begin, chars.len()));
+    }
+    runs
+}
+
+fn token(chars: &[Mapped], position: usize) -> Token {
+    Token {
+        text: chars.iter().map(|m| m.c).collect(),
+        position,
+        start: chars[0].start,
+        end: chars[chars.len() - 1].end,
+    }
+}
+
+// Tách theo khoảng trắng, giữ nguyên dấu câu trong token
+struct Whitespace;
+
+impl Tokenize for Whitespace {
+    fn tokenize(&self, chars: &[Mapped]) -> Vec<Token> {
+        runs(chars, |c| !c.is_whitespace())
+            .into_iter()
+            .enumerate()
+            .map(|(position, (start, end))| token(&chars[start..end], position))
+            .collect()
+    }
+}
+
+// Tách theo ranh giới từ: chữ, số và dấu tổ hợp
+struct Words;
+
+impl Tokenize for Words {
+    fn tokenize(&self, chars: &[Mapped]) -> Vec<Token> {
+        runs(chars, |c| c.is_alphanumeric() || is_combining(c))
+            .into_iter()
+            .enumerate()
+            .map(|(position, (start, end))| token(&chars[start..end], position))
+            .collect()
+    }
+}
+
+// N-gram ký tự của từng từ, dùng cho tìm kiếm chuỗi con
+struct NGram {
+    min: usize,
+    max: usize,
+    // Chỉ lấy n-gram ở đầu từ (edge n-gram), dùng cho gợi ý khi gõ
+    edge: bool,
+}
+
+impl NGram {
+    fn new(min: usize, max: usize) -> Self {
+        NGram {
+            min: min.max(1),
+            max,
+            edge: false,
+        }
+    }
+
+    fn edge(min: usize, max: usize) -> Self {
+        NGram {
+            edge: true,
+            ..NGram::new(min, max)
+        }
+    }
+}
+
+impl Tokenize for NGram {
+    fn tokenize(&self, chars: &[Mapped]) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for (start, end) in runs(chars, |c| c.is_alphanumeric() || is_combining(c)) {
+            let word = &chars[start..end];
+            let last = if self.edge { 1 } else { word.len() };
+            for from in 0..last {
+                for n in self.min..=self.max.min(word.len() - from) {
+                    tokens.push(token(&word[from..from + n], tokens.len()));
+                }
+            }
+        }
+        tokens
+    }
+}
+
+struct Lowercase;
+
+impl TokenFilter for Lowercase {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            token.text = token.text.to_lowercase();
+        }
+        tokens
+    }
+}
+
+// Bỏ dấu tiếng Việt trên token
+struct Fold;
+
+impl TokenFilter for Fold {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            token.text = fold(&token.text);
+        }
+        tokens
+    }
+}
+
+// Loại bỏ stopword; vị trí của các token còn lại được giữ nguyên để truy vấn cụm từ vẫn đúng
+struct Stopwords {
+    words: HashSet<String>,
+}
+
+impl Stopwords {
+    fn new(words: &[&str]) -> Self {
+        Stopwords {
+            words: words.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    fn english() -> Self {
+        Stopwords::new(&[
+            "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "is", "it", "of",
+            "on", "or", "that", "the", "this", "to", "was", "with",
+        ])
+    }
+
+    fn vietnamese() -> Self {
+        Stopwords::new(&[
+            "và", "của", "là", "có", "cho", "các", "những", "được", "với", "trong", "này", "đó",
+            "thì", "mà", "một", "để", "khi", "từ",
+        ])
+    }
+
+    fn union(mut self, other: Stopwords) -> Self {
+        self.words.extend(other.words);
+        self
+    }
+
+    fn contains(&self, word: &str) -> bool {
+        self.words.contains(word)
+    }
+}
+
+impl TokenFilter for Stopwords {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|token| !self.words.contains(&token.text))
+            .collect()
+    }
+}
+
+// Stemmer tiếng Anh đơn giản: bỏ các hậu tố phổ biến
+struct Stemmer;
+
+impl Stemmer {
+    fn stem(word: &str) -> String {
+        const SUFFIXES: [&str; 7] = ["ations", "ation", "ings", "ing", "ies", "ed", "s"];
+        for suffix in SUFFIXES {
+            if let Some(stem) = word.strip_suffix(suffix) {
+                // Giữ lại gốc đủ dài để tránh "is" -> "i"
+                if stem.chars().count() >= 3 && !stem.ends_with('s') {
+                    let mut stem = stem.to_string();
+                    if suffix == "ies" {
+                        stem.push('y');
+                    }
+                    // "running" -> "runn" -> "run"
+                    let doubled: Vec<char> = stem.chars().rev().take(2).collect();
+                    if matches!(suffix, "ing" | "ings" | "ed")
+                        && doubled[0] == doubled[1]
+                        && !"aeiouylsz".contains(doubled[0])
+                    {
+                        stem.pop();
+                    }
+                    return stem;
+                }
+            }
+        }
+        word.to_string()
+    }
+}
+
+impl TokenFilter for Stemmer {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            token.text = Stemmer::stem(&token.text);
+        }
+        tokens
+    }
+}
+
+// Thêm từ đồng nghĩa tại cùng vị trí với token gốc
+struct Synonyms {
+    map: HashMap<String, Vec<String>>,
+}
+
+impl Synonyms {
+    fn new(groups: &[&[&str]]) -> Self {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for group in groups {
+            for word in group.iter() {
+                let others = group.iter().filter(|w| *w != word).map(|w| w.to_string());
+                map.entry(word.to_string()).or_default().extend(others);
+            }
+        }
+        Synonyms { map }
+    }
+}
+
+impl TokenFilter for Synonyms {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        let mut out = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let synonyms = self.map.get(&token.text).cloned().unwrap_or_default();
+            for synonym in synonyms {
+                out.push(Token {
+                    text: synonym,
+                    ..token.clone()
+                });
+            }
+            out.push(token);
+        }
+        out
+    }
+}
+
+// Giới hạn độ dài token (tính theo ký tự)
+struct Length {
+    min: usize,
+    max: usize,
+}
+
+impl TokenFilter for Length {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|token| (self.min..=self.max).contains(&token.text.chars().count()))
+            .collect()
+    }
+}
+
+// Chuỗi xử lý: char filter -> tokenizer -> token filter
+struct Pipeline {
+    char_filters: Vec<Box<dyn CharFilter>>,
+    tokenizer: Box<dyn Tokenize>,
+    token_filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl Pipeline {
+    fn new(tokenizer: impl Tokenize + 'static) -> Self {
+        Pipeline {
+            char_filters: Vec::new(),
+            tokenizer: Box::new(tokenizer),
+            token_filters: Vec::new(),
+        }
+    }
+
+    fn char_filter(mut self, filter: impl CharFilter + 'static) -> Self {
+        self.char_filters.push(Box::new(filter));
+        self
+    }
+
+    fn token_filter(mut self, filter: impl TokenFilter + 'static) -> Self {
+        self.token_filters.push(Box::new(filter));
+        self
+    }
+}
+
+impl Analyzer for Pipeline {
+    fn analyze(&self, text: &str) -> Vec<Token> {
+        let mut chars: Vec<Mapped> = text
+            .char_indices()
+            .map(|(start, c)| Mapped {
+                c,
+                start,
+                end: start + c.len_utf8(),
+            })
+            .collect();
+        for filter in &self.char_filters {
+            chars = filter.filter(chars);
+        }
+        let mut tokens = self.tokenizer.tokenize(&chars);
+        for filter in &self.token_filters {
+            tokens = filter.filter(tokens);
+        }
+        tokens
+    }
+}
+
+// Các cách tính term frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TfScheme {
+    // Số lần xuất hiện chia cho tổng số token của văn bản
+    Raw,
+    // Số lần xuất hiện, như CountVectorizer của scikit-learn
+    Count,
+    // 1 + ln(số lần xuất hiện), tức sublinear TF
+    Log,
+    // 0.5 + 0.5 * số lần xuất hiện / số lần xuất hiện lớn nhất
+    Augmented,
+    // 1 nếu term có mặt
+    Boolean,
+}
+
+impl TfScheme {
+    fn weight(&self, count: usize, total: usize, max: usize) -> f64 {
+        match self {
+            TfScheme::Raw => count as f64 / total as f64,
+            TfScheme::Count => count as f64,
+            TfScheme::Log => 1.0 + (count as f64).ln(),
+            TfScheme::Augmented => 0.5 + 0.5 * count as f64 / max as f64,
+            TfScheme::Boolean => 1.0,
+        }
+    }
+}
+
+// Các cách tính IDF; N là số văn bản, df là số văn bản chứa term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdfScheme {
+    // ln(N / df), bằng 0 với term có trong mọi văn bản
+    Standard,
+    // ln(1 + N / df), luôn dương
+    Smooth,
+    // ln((N - df) / df), lấy 0 khi term có trong quá nửa số văn bản
+    Probabilistic,
+    // ln(N / df) + 1 như scikit-learn; `smooth` cộng 1 vào N và df như thể có thêm
+    // một văn bản chứa mọi term (mặc định của scikit-learn)
+    Sklearn { smooth: bool },
+}
+
+impl IdfScheme {
+    fn weight(&self, df: usize, n: usize) -> f64 {
+        // Term không có trong văn bản nào thì không có trọng số, tránh chia cho 0
+        if df == 0 || n == 0 {
+            return 0.0;
+        }
+        let (df, n) = (df as f64, n as f64);
+        match self {
+            IdfScheme::Standard => (n / df).ln(),
+            IdfScheme::Smooth => (1.0 + n / df).ln(),
+            IdfScheme::Probabilistic => ((n - df) / df).ln().max(0.0),
+            IdfScheme::Sklearn { smooth: false } => (n / df).ln() + 1.0,
+            IdfScheme::Sklearn { smooth: true } => ((1.0 + n) / (1.0 + df)).ln() + 1.0,
+        }
+    }
+
+    // Phần chỉ phụ thuộc df khi IDF viết được dạng `base(df) + shift(N)`, `None` nếu không.
+    // Khi đó đổi N chỉ dịch IDF của mọi term đi cùng một lượng `shift(N)`.
+    fn base(&self, df: usize) -> Option<f64> {
+        let df = df as f64;
+        match self {
+            IdfScheme::Standard => Some(-df.ln()),
+            IdfScheme::Sklearn { smooth: false } => Some(1.0 - df.ln()),
+            IdfScheme::Sklearn { smooth: true } => Some(1.0 - (1.0 + df).ln()),
+            IdfScheme::Smooth | IdfScheme::Probabilistic => None,
+        }
+    }
+
+    fn shift(&self, n: usize) -> f64 {
+        match self {
+            IdfScheme::Sklearn { smooth: true } => (1.0 + n as f64).ln(),
+            _ => (n as f64).ln(),
+        }
+    }
+}
+
+// Chuẩn hóa vector TF-IDF của văn bản
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Normalization {
+    None,
+    // Tổng trị tuyệt đối bằng 1
+    L1,
+    // Độ dài Euclid bằng 1
+    L2,
+}
+
+// Giá trị của trường chỉ lưu trữ (không đánh chỉ mục), ví dụ giá hay danh mục
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    Number(f64),
+    Flag(bool),
+}
+
+#[derive(Debug, Clone)]
+struct Document {
+    // Mã định danh ổn định do ứng dụng đặt, ví dụ mã sản phẩm; rỗng nếu không cần
+    id: String,
+    content: String,
+    // Các trường văn bản được đánh chỉ mục ngoài `content`, ví dụ "title"
+    fields: BTreeMap<String, String>,
+    // Các trường chỉ lưu trữ, trả về cùng kết quả tìm kiếm
+    stored: BTreeMap<String, Value>,
+    // Trọng số TF-IDF theo id term trong `vocabulary`
+    tfidf_scores: SparseVector,
+    // Từ vựng dùng chung với mô hình đã `transform` văn bản; id không bao giờ đổi nên bản
+    // cũ vẫn đọc đúng. Văn bản tự tính TF-IDF có từ vựng riêng.
+    vocabulary: Arc<Vocabulary>,
+}
+
+impl Document {
+    fn new(content: &str) -> Self {
+        Document {
+            id: String::new(),
+            content: content.to_string(),
+            fields: BTreeMap::new(),
+            stored: BTreeMap::new(),
+            tfidf_scores: SparseVector::default(),
+            vocabulary: Arc::default(),
+        }
+    }
+
+    fn with_id(mut self, id: &str) -> Self {
+        self.id = id.to_string();
+        self
+    }
+
+    fn with_field(mut self, name: &str, text: &str) -> Self {
+        self.fields.insert(name.to_lowercase(), text.to_string());
+        self
+    }
+
+    fn with_stored(mut self, name: &str, value: Value) -> Self {
+        self.stored.insert(name.to_string(), value);
+        self
+    }
+
+    // Văn bản của một trường, `content` là trường mặc định
+    fn text(&self, field: &str) -> Option<&str> {
+        if field == DEFAULT_FIELD {
+            Some(&self.content)
+        } else {
+            self.fields.get(field).map(String::as_str)
+        }
+    }
+
+    fn compute_tfidf(&mut self, idf_scores: &HashMap<String, f64>) {
+        self.compute_tfidf_with(idf_scores, TfScheme::Raw);
+    }
+
+    fn compute_tfidf_with(&mut self, idf_scores: &HashMap<String, f64>, scheme: TfScheme) {
+        let tokens = Tokenizer::tokenize(&self.content);
+        self.compute_tfidf_tokens(&tokens, idf_scores, scheme);
+    }
+
+    // Tính TF-IDF từ các token đã được phân tích sẵn
+    fn compute_tfidf_tokens(
+        &mut self,
+        tokens: &[Token],
+        idf_scores: &HashMap<String, f64>,
+        scheme: TfScheme,
+    ) {
+        let counts = Tokenizer::term_counts(tokens);
+        let max = counts.values().copied().max().unwrap_or(0);
+
+        let mut pairs = Vec::new();
+        for (term, count) in counts {
+            if let Some(idf_score) = idf_scores.get(&term) {
+                let tfidf = scheme.weight(count, tokens.len(), max) * idf_score;
+                // Chỉ chép từ vựng dùng chung khi phải thêm term mới
+                let id = match self.vocabulary.id(&term) {
+                    Some(id) => id,
+                    None => Arc::make_mut(&mut self.vocabulary).insert(&term),
+                };
+                pairs.push((id, tfidf));
+            }
+        }
+        self.tfidf_scores = SparseVector::from_pairs(pairs);
+    }
+
+    // Trọng số TF-IDF của một term, `None` nếu văn bản không có term đó
+    fn score(&self, term: &str) -> Option<f64> {
+        self.tfidf_scores.get(self.vocabulary.id(term)?)
+    }
+
+    // Các cặp (term, trọng số) theo thứ tự id
+    fn weights(&self) -> impl Iterator<Item = (&str, f64)> + '_ {
+        self.tfidf_scores
+            .iter()
+            .map(|(id, score)| (self.vocabulary.term(id).unwrap(), score))
+    }
+
+    fn normalize_tfidf_scores(&mut self) {
+        self.normalize(Normalization::L2);
+    }
+
+    // Văn bản không có term nào đã biết (chuẩn bằng 0) được giữ nguyên thay vì thành NaN
+    fn normalize(&mut self, normalization: Normalization) {
+        let norm: f64 = match normalization {
+            Normalization::None => return,
+            Normalization::L1 => self.tfidf_scores.values.iter().map(|v| v.abs()).sum(),
+            Normalization::L2 => self.tfidf_scores.norm(),
+        };
+        if norm == 0.0 || !norm.is_finite() {
+            return;
+        }
+        for value in self.tfidf_scores.values.iter_mut() {
+            *value /= norm;
+        }
+    }
+
+    fn get_top_keywords(&self, k: usize) -> Vec<String> {
+        let mut keywords: Vec<_> = self.weights().collect();
+        keywords.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        keywords
+            .into_iter()
+            .take(k)
+            .map(|(term, _)| term.to_string())
+            .collect()
+    }
+}
+
+// Một phần tử trong danh sách postings của term
+#[derive(Debug, Clone, PartialEq)]
+struct Posting {
+    doc: usize,
+    tf: usize,
+    positions: Vec<usize>,
+}
+
+// Chỉ mục ngược: term -> postings, sắp xếp theo doc id tăng dần
+#[derive(Debug, Clone, Default)]
+struct Index {
+    postings: HashMap<String, Vec<Posting>>,
+    // Số văn bản còn trong chỉ mục
+    doc_count: usize,
+    // Các thông tin theo doc id; văn bản đã xóa vẫn giữ chỗ để doc id không đổi
+    lengths: Vec<usize>,
+    max_tf: Vec<usize>,
+    terms: Vec<Vec<String>>,
+    live: Vec<bool>,
+    // Chỉ mục nạp từ file: `postings` và `terms` để trống, được đọc từ vùng nhớ ánh xạ
+    // cho tới lần sửa đầu tiên
+    mapped: Option<Arc<MappedIndex>>,
+}
+
+impl Index {
+    fn new() -> Self {
+        Index::default()
+    }
+
+    // Chép toàn bộ postings từ vùng nhớ ánh xạ vào bộ nhớ để sửa được chỉ mục
+    fn materialize(&mut self) {
+        let Some(mapped) = self.mapped.take() else {
+            return;
+        };
+        self.terms = vec![Vec::new(); self.slots()];
+        for ordinal in 0..mapped.term_count {
+            let term = mapped.term(ordinal);
+            let postings = mapped.postings_at(ordinal).to_vec();
+            for posting in &postings {
+                self.terms[posting.doc].push(term.to_string());
+            }
+            self.postings.insert(term.to_string(), postings);
+        }
+    }
+
+    // Thêm văn bản vào chỉ mục với doc id mới hoặc doc id của một văn bản đã xóa
+    fn add(&mut self, doc: usize, tokens: &[Token]) {
+        assert!(!self.is_live(doc), "doc id {} đã tồn tại", doc);
+        self.materialize();
+        let mut positions: HashMap<&str, Vec<usize>> = HashMap::new();
+        for token in tokens {
+            positions
+                .entry(&token.text)
+                .or_default()
+                .push(token.position);
+        }
+        let max_tf = positions.values().map(Vec::len).max().unwrap_or(0);
+        let mut terms = Vec::with_capacity(positions.len());
+        for (term, positions) in positions {
+            let postings = self.postings.entry(term.to_string()).or_default();
+            let posting = Posting {
+                doc,
+                tf: positions.len(),
+                positions,
+            };
+            // Thường doc id lớn nhất nên chỉ cần push; cập nhật lại thì chèn đúng chỗ
+            match postings.last() {
+                Some(last) if last.doc > doc => {
+                    let at = postings.partition_point(|p| p.doc < doc);
+                    postings.insert(at, posting);
+                }
+                _ => postings.push(posting),
+            }
+            terms.push(term.to_string());
+        }
+        if doc >= self.lengths.len() {
+            self.lengths.resize(doc + 1, 0);
+            self.max_tf.resize(doc + 1, 0);
+            self.terms.resize(doc + 1, Vec::new());
+            self.live.resize(doc + 1, false);
+        }
+        self.lengths[doc] = tokens.len();
+        self.max_tf[doc] = max_tf;
+        self.terms[doc] = terms;
+        self.live[doc] = true;
+        self.doc_count += 1;
+    }
+
+    // Nối chỉ mục con vào cuối, doc id của `other` được dời thêm `slots()`
+    fn append(&mut self, mut other: Index) {
+        self.materialize();
+        other.materialize();
+        let shift = self.slots();
+        for (term, postings) in other.postings {
+            self.postings
+                .entry(term)
+                .or_default()
+                .extend(postings.into_iter().map(|posting| Posting {
+                    doc: posting.doc + shift,
+                    ..posting
+                }));
+        }
+        self.doc_count += other.doc_count;
+        self.lengths.extend(other.lengths);
+        self.max_tf.extend(other.max_tf);
+        self.terms.extend(other.terms);
+        self.live.extend(other.live);
+    }
+
+    // Chép các văn bản `docs` (tăng dần) của `other` vào cuối, văn bản thứ i nhận doc id
+    // `base + i`. Postings được chép thẳng nên không phải phân tích lại văn bản.
+    fn extend_from(&mut self, other: &Index, docs: &[usize], base: usize) {
+        assert!(base >= self.slots(), "doc id {} đã tồn tại", base);
+        self.materialize();
+        let slots = base + docs.len();
+        self.lengths.resize(slots, 0);
+        self.max_tf.resize(slots, 0);
+        self.terms.resize(slots, Vec::new());
+        self.live.resize(slots, false);
+        for (new, &doc) in (base..).zip(docs) {
+            if !other.is_live(doc) {
+                continue;
+            }
+            let terms = other.doc_terms(doc);
+            for &term in &terms {
+                let list = other.postings(term);
+                let posting = &list[list.binary_search_by_key(&doc, |p| p.doc).unwrap()];
+                self.postings
+                    .entry(term.to_string())
+                    .or_default()
+                    .push(Posting {
+                        doc: new,
+                        ..posting.clone()
+                    });
+            }
+            self.lengths[new] = other.lengths[doc];
+            self.max_tf[new] = other.max_tf[doc];
+            self.terms[new] = terms.into_iter().map(str::to_string).collect();
+            self.live[new] = true;
+            self.doc_count += 1;
+        }
+    }
+
+    // Xóa văn bản khỏi chỉ mục, trả về các term bị ảnh hưởng
+    fn remove(&mut self, doc: usize) -> Vec<String> {
+        if !self.is_live(doc) {
+            return Vec::new();
+        }
+        self.materialize();
+        let terms = std::mem::take(&mut self.terms[doc]);
+        for term in &terms {
+            if let Some(postings) = self.postings.get_mut(term) {
+                if let Ok(at) = postings.binary_search_by_key(&doc, |p| p.doc) {
+                    postings.remove(at);
+                }
+                if postings.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        self.lengths[doc] = 0;
+        self.max_tf[doc] = 0;
+        self.live[doc] = false;
+        self.doc_count -= 1;
+        terms
+    }
+
+    fn is_live(&self, doc: usize) -> bool {
+        self.live.get(doc).copied().unwrap_or(false)
+    }
+
+    // Doc id lớn nhất + 1, kể cả các văn bản đã xóa
+    fn slots(&self) -> usize {
+        self.live.len()
+    }
+
+    fn average_length(&self) -> f64 {
+        if self.doc_count == 0 {
+            return 0.0;
+        }
+        self.lengths.iter().sum::<usize>() as f64 / self.doc_count as f64
+    }
+
+    fn postings(&self, term: &str) -> &[Posting] {
+        match &self.mapped {
+            Some(mapped) => mapped.postings(term),
+            None => self.postings.get(term).map(Vec::as_slice).unwrap_or(&[]),
+        }
+    }
+
+    fn document_frequency(&self, term: &str) -> usize {
+        match &self.mapped {
+            Some(mapped) => mapped.document_frequency(term),
+            None => self.postings(term).len(),
+        }
+    }
+
+    // Các term của chỉ mục, không theo thứ tự nào
+    fn term_list(&self) -> Vec<&str> {
+        match &self.mapped {
+            Some(mapped) => (0..mapped.term_count).map(|t| mapped.term(t)).collect(),
+            None => self.postings.keys().map(String::as_str).collect(),
+        }
+    }
+
+    // Các term khác nhau của một văn bản
+    fn doc_terms(&self, doc: usize) -> Vec<&str> {
+        match &self.mapped {
+            Some(mapped) => mapped.doc_terms(doc),
+            None => self.terms.get(doc).map_or(Vec::new(), |terms| {
+                terms.iter().map(String::as_str).collect()
+            }),
+        }
+    }
+
+    fn lookup(&self, term: &str) -> Vec<usize> {
+        self.postings(term).iter().map(|p| p.doc).collect()
+    }
+
+    // Các văn bản chứa tất cả các term (AND)
+    fn lookup_all(&self, terms: &[&str]) -> Vec<usize> {
+        let mut lists: Vec<&[Posting]> = terms.iter().map(|term| self.postings(term)).collect();
+        // Bắt đầu từ danh sách ngắn nhất để giảm số lần tìm kiếm
+        lists.sort_by_key(|list| list.len());
+        let Some((first, rest)) = lists.split_first() else {
+            return Vec::new();
+        };
+        first
+            .iter()
+            .map(|p| p.doc)
+            .filter(|doc| {
+                rest.iter()
+                    .all(|list| list.binary_search_by_key(doc, |p| p.doc).is_ok())
+            })
+            .collect()
+    }
+
+    // Các văn bản chứa ít nhất một term (OR)
+    fn lookup_any(&self, terms: &[&str]) -> Vec<usize> {
+        let mut docs: Vec<usize> = terms
+            .iter()
+            .flat_map(|term| self.postings(term).iter().map(|p| p.doc))
+            .collect();
+        docs.sort_unstable();
+        docs.dedup();
+        docs
+    }
+}
+
+// Cách chấm điểm khi tìm kiếm nhiều term
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scoring {
+    // Cosine giữa vector TF-IDF của truy vấn và của văn bản
+    Cosine,
+    // Okapi BM25
+    Bm25 { k1: f64, b: f64 },
+}
+
+impl Scoring {
+    fn bm25() -> Self {
+        Scoring::Bm25 { k1: 1.2, b: 0.75 }
+    }
+}
+
+// Một kết quả tìm kiếm
+#[derive(Debug, Clone, PartialEq)]
+struct Hit {
+    doc: usize,
+    score: f64,
+}
+
+// Chỉ mục và thống kê của một trường văn bản ngoài `content`
+#[derive(Debug, Clone, Default)]
+struct FieldIndex {
+    index: Index,
+    idf_scores: HashMap<String, f64>,
+    norms: Vec<f64>,
+    // Theo doc id, để suy ra chuẩn khi chỉ số văn bản thay đổi
+    parts: Vec<NormParts>,
+    // Từ điển riêng của trường cho tìm kiếm gần đúng
+    lexicon: Lexicon,
+}
+
+// Các tổng của một văn bản, với w là trọng số TF và IDF = base + shift:
+// chuẩn² = Σ w²(base + shift)² = squares + 2·shift·linear + shift²·weights.
+// Chỉ đổi khi văn bản hoặc DF của một term trong nó thay đổi, không phụ thuộc N.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct NormParts {
+    weights: f64,
+    linear: f64,
+    squares: f64,
+}
+
+impl NormParts {
+    fn norm(&self, shift: f64) -> f64 {
+        (self.squares + 2.0 * shift * self.linear + shift * shift * self.weights)
+            .max(0.0)
+            .sqrt()
+    }
+}
+
+// Cách nhìn chung vào chỉ mục của một trường, kể cả `content`
+#[derive(Clone, Copy)]
+struct View<'a> {
+    name: &'a str,
+    index: &'a Index,
+    idf_scores: &'a HashMap<String, f64>,
+    norms: &'a [f64],
+    lexicon: &'a Lexicon,
+}
+
+// Kết quả tìm kiếm kèm mã định danh và đoạn trích có đánh dấu
+#[derive(Debug, Clone, PartialEq)]
+struct SearchResult {
+    doc: usize,
+    id: String,
+    score: f64,
+    snippet: String,
+    stored: BTreeMap<String, Value>,
+}
+
+// Một đoạn trích đã đánh dấu; `start`, `end` là vị trí byte trong văn bản gốc
+#[derive(Debug, Clone, PartialEq)]
+struct Fragment {
+    text: String,
+    start: usize,
+    end: usize,
+    score: f64,
+}
+
+// Chọn các đoạn trích tốt nhất của văn bản và bọc các term khớp bằng dấu đánh dấu
+#[derive(Debug, Clone)]
+struct Highlighter {
+    // Độ dài tối đa của mỗi đoạn, tính theo ký tự (không phải byte) để đúng với tiếng Việt
+    fragment_len: usize,
+    fragments: usize,
+    pre: String,
+    post: String,
+    separator: String,
+}
+
+impl Highlighter {
+    fn new() -> Self {
+        Highlighter {
+            fragment_len: 160,
+            fragments: 1,
+            pre: "<em>".to_string(),
+            post: "</em>".to_string(),
+            separator: " … ".to_string(),
+        }
+    }
+
+    fn with_fragment_len(mut self, fragment_len: usize) -> Self {
+        self.fragment_len = fragment_len.max(1);
+        self
+    }
+
+    fn with_fragments(mut self, fragments: usize) -> Self {
+        self.fragments = fragments.max(1);
+        self
+    }
+
+    fn with_markers(mut self, pre: &str, post: &str) -> Self {
+        self.pre = pre.to_string();
+        self.post = post.to_string();
+        self
+    }
+
+    fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    // Chia văn bản thành các cửa sổ token dài không quá `fragment_len` ký tự, mỗi cửa sổ
+    // bắt đầu tại một token khớp. Điểm là tổng trọng số các term khác nhau cộng một phần
+    // nhỏ cho mỗi lần lặp lại; chọn các cửa sổ tốt nhất không chồng nhau theo thứ tự văn bản.
+    fn highlight(
+        &self,
+        text: &str,
+        tokens: &[Token],
+        weights: &HashMap<String, f64>,
+    ) -> Vec<Fragment> {
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+        // Vị trí ký tự của đầu và cuối mỗi token, tra từ bảng byte → ký tự dựng một lượt
+        let mut chars = vec![0; text.len() + 1];
+        let mut count = 0;
+        for (byte, c) in text.char_indices() {
+            chars[byte..byte + c.len_utf8()].fill(count);
+            count += 1;
+        }
+        chars[text.len()] = count;
+        let starts: Vec<usize> = tokens.iter().map(|t| chars[t.start]).collect();
+        let ends: Vec<usize> = tokens.iter().map(|t| chars[t.end]).collect();
+        let span = |a: usize, b: usize| ends[b] - starts[a];
+        let matched = |i: usize| weights.contains_key(&tokens[i].text);
+
+        let mut windows: Vec<(usize, usize, f64)> = Vec::new();
+        for first in (0..tokens.len()).filter(|&i| matched(i)) {
+            let (mut a, mut b) = (first, first);
+            while b + 1 < tokens.len() && span(a, b + 1) <= self.fragment_len {
+                b += 1;
+            }
+            // Phần còn trống dùng để lấy thêm ngữ cảnh phía trước
+            while a > 0 && span(a - 1, b) <= self.fragment_len {
+                a -= 1;
+            }
+            let mut seen = HashSet::new();
+            let mut score = 0.0;
+            for token in &tokens[a..=b] {
+                if let Some(&weight) = weights.get(&token.text) {
+                    score += if seen.insert(&token.text) {
+                        weight
+                    } else {
+                        0.1 * weight
+                    };
+                }
+            }
+            windows.push((a, b, score));
+        }
+        if windows.is_empty() {
+            // Không có term nào khớp: trả về phần đầu văn bản không đánh dấu
+            let mut b = 0;
+            while b + 1 < tokens.len() && span(0, b + 1) <= self.fragment_len {
+                b += 1;
+            }
+            windows.push((0, b, 0.0));
+        }
+
+        windows.sort_by(|x, y| {
+            y.2.partial_cmp(&x.2)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(x.0.cmp(&y.0))
+        });
+        let mut chosen: Vec<(usize, usize, f64)> = Vec::new();
+        for window in windows {
+            if chosen.len() == self.fragments {
+                break;
+            }
+            if chosen.iter().all(|c| window.1 < c.0 || window.0 > c.1) {
+                chosen.push(window);
+            }
+        }
+        chosen.sort_by_key(|c| c.0);
+
+        chosen
+            .into_iter()
+            .map(|(a, b, score)| {
+                // Đoạn chạm đầu hoặc cuối văn bản thì giữ luôn dấu câu ở đó
+                let start = if a == 0 { 0 } else { tokens[a].start };
+                let end = if b + 1 == tokens.len() {
+                    text.len()
+                } else {
+                    tokens[b].end
+                };
+                let mut out = String::new();
+                let mut last = start;
+                for token in &tokens[a..=b] {
+                    // Bỏ qua token chồng lên token trước (ví dụ n-gram)
+                    if token.start < last || !weights.contains_key(&token.text) {
+                        continue;
+                    }
+                    out.push_str(&text[last..token.start]);
+                    out.push_str(&self.pre);
+                    out.push_str(&text[token.start..token.end]);
+                    out.push_str(&self.post);
+                    last = token.end;
+                }
+                out.push_str(&text[last..end]);
+                Fragment {
+                    text: out,
+                    start,
+                    end,
+                    score,
+                }
+            })
+            .collect()
+    }
+}
+
+// Khoảng cách Levenshtein tính theo ký tự
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + (ca != cb) as usize)
+                .min(above + 1)
+                .min(row[j] + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+// Cây BK: mỗi cạnh mang khoảng cách giữa hai term, cho phép bỏ qua các nhánh
+// chắc chắn xa hơn `max` khi tìm kiếm (bất đẳng thức tam giác)
+#[derive(Debug, Clone, Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+#[derive(Debug, Clone)]
+struct BkNode {
+    term: String,
+    children: Vec<(usize, usize)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, term: &str) {
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode {
+                term: term.to_string(),
+                children: Vec::new(),
+            });
+            return;
+        }
+        let mut node = 0;
+        loop {
+            let distance = levenshtein(&self.nodes[node].term, term);
+            if distance == 0 {
+                return;
+            }
+            match self.nodes[node].children.iter().find(|c| c.0 == distance) {
+                Some(&(_, child)) => node = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(BkNode {
+                        term: term.to_string(),
+                        children: Vec::new(),
+                    });
+                    self.nodes[node].children.push((distance, child));
+                    return;
+                }
+            }
+        }
+    }
+
+    // Các term cách `term` không quá `max` phép sửa, kèm khoảng cách
+    fn find(&self, term: &str, max: usize) -> Vec<(&str, usize)> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let distance = levenshtein(&node.term, term);
+            if distance <= max {
+                found.push((node.term.as_str(), distance));
+            }
+            for &(edge, child) in &node.children {
+                if edge + max >= distance && edge <= distance + max {
+                    stack.push(child);
+                }
+            }
+        }
+        found
+    }
+}
+
+// Từ điển term của chỉ mục, dùng cho tìm kiếm gần đúng và theo tiền tố.
+// Cây BK không xóa được nên term bị xóa chỉ bị bỏ khỏi `terms` và cây được
+// dựng lại khi số term cũ vượt quá số term còn dùng.
+#[derive(Debug, Clone, Default)]
+struct Lexicon {
+    terms: std::collections::BTreeSet<String>,
+    tree: BkTree,
+    stale: usize,
+}
+
+impl Lexicon {
+    fn insert(&mut self, term: &str) {
+        if self.terms.insert(term.to_string()) {
+            self.tree.insert(term);
+        }
+    }
+
+    fn remove(&mut self, term: &str) {
+        if self.terms.remove(term) {
+            self.stale += 1;
+        }
+        if self.stale > self.terms.len() {
+            self.tree = BkTree::default();
+            for term in &self.terms {
+                self.tree.insert(term);
+            }
+            self.stale = 0;
+        }
+    }
+
+    fn fuzzy(&self, term: &str, max: usize) -> Vec<(&str, usize)> {
+        self.tree
+            .find(term, max)
+            .into_iter()
+            .filter(|(found, _)| self.terms.contains(*found))
+            .collect()
+    }
+
+    fn prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.terms
+            .range::<str, _>((
+                std::ops::Bound::Included(prefix),
+                std::ops::Bound::Unbounded,
+            ))
+            .take_while(move |term| term.starts_with(prefix))
+    }
+}
+
+// Cấu hình tìm kiếm gần đúng
+#[derive(Debug, Clone, Copy)]
+struct Fuzzy {
+    max_distance: usize,
+    // Hệ số nhân điểm cho mỗi phép sửa, để khớp chính xác luôn xếp trên khớp gần đúng
+    penalty: f64,
+    // Hệ số nhân điểm cho term chỉ khớp theo tiền tố
+    prefix_penalty: f64,
+    // Số term tối đa mà một term của truy vấn được mở rộng thành
+    max_expansions: usize,
+}
+
+impl Fuzzy {
+    fn new(max_distance: usize) -> Self {
+        Fuzzy {
+            max_distance,
+            penalty: 0.5,
+            prefix_penalty: 0.8,
+            max_expansions: 20,
+        }
+    }
+
+    fn with_penalty(mut self, penalty: f64) -> Self {
+        self.penalty = penalty;
+        self
+    }
+
+    fn with_max_expansions(mut self, max_expansions: usize) -> Self {
+        self.max_expansions = max_expansions;
+        self
+    }
+
+    // Term ngắn dễ khớp nhầm nên được phép ít phép sửa hơn
+    fn distance_for(&self, term: &str) -> usize {
+        match term.chars().count() {
+            0..=3 => 0,
+            4..=6 => self.max_distance.min(1),
+            _ => self.max_distance,
+        }
+    }
+}
+
+// Số văn bản mỗi luồng xử lý trong một lô khi fit song song
+const BATCH_SIZE: usize = 1024;
+// Số thứ tự trong tên file tạm của `fit_stream`, để các lần fit đồng thời không trùng file
+static FIT_FILES: AtomicU64 = AtomicU64::new(0);
+
+struct TFIDF {
+    idf_scores: HashMap<String, f64>,
+    tf_scheme: TfScheme,
+    idf_scheme: IdfScheme,
+    // Chuẩn hóa kết quả của `transform`; tìm kiếm cosine luôn dùng chuẩn L2
+    normalization: Normalization,
+    scoring: Scoring,
+    index: Index,
+    // Chuẩn L2 của vector TF-IDF của mỗi văn bản đã fit
+    norms: Vec<f64>,
+    parts: Vec<NormParts>,
+    // Số văn bản lúc `refresh` gần nhất, IDF và chuẩn hiện có được tính theo nó
+    refreshed: usize,
+    pending: Pending,
+    analyzer: Box<dyn Analyzer>,
+    // Bộ phân tích riêng cho từng trường, mặc định dùng `analyzer`
+    field_analyzers: HashMap<String, Box<dyn Analyzer>>,
+    // Các trường văn bản khác và hệ số nhân điểm của từng trường (mặc định 1)
+    fields: BTreeMap<String, FieldIndex>,
+    boosts: HashMap<String, f64>,
+    // Văn bản gốc theo doc id và ánh xạ từ mã định danh sang doc id
+    store: DocStore,
+    ids: HashMap<String, usize>,
+    highlighter: Highlighter,
+    // Có giữ văn bản gốc để tạo đoạn trích hay không
+    store_content: bool,
+    vocabulary: Arc<Vocabulary>,
+    // Từ dừng không được đứng đầu hay cuối cụm từ khóa
+    stopwords: Stopwords,
+    lexicon: Lexicon,
+    fuzzy: Fuzzy,
+    // Thư mục chứa file tạm của `fit_stream`
+    spill_dir: std::path::PathBuf,
+}
+
+impl TFIDF {
+    fn new() -> Self {
+        TFIDF {
+            idf_scores: HashMap::new(),
+            tf_scheme: TfScheme::Raw,
+            idf_scheme: IdfScheme::Sklearn { smooth: false },
+            normalization: Normalization::None,
+            scoring: Scoring::Cosine,
+            index: Index::new(),
+            norms: Vec::new(),
+            parts: Vec::new(),
+            refreshed: 0,
+            pending: Pending::default(),
+            analyzer: Box::new(Tokenizer),
+            field_analyzers: HashMap::new(),
+            fields: BTreeMap::new(),
+            boosts: HashMap::new(),
+            store: DocStore::default(),
+            ids: HashMap::new(),
+            highlighter: Highlighter::new(),
+            store_content: true,
+            vocabulary: Arc::default(),
+            stopwords: Stopwords::english().union(Stopwords::vietnamese()),
+            lexicon: Lexicon::default(),
+            fuzzy: Fuzzy::new(2),
+            spill_dir: std::env::temp_dir(),
+        }
+    }
+
+    fn with_fuzzy(mut self, fuzzy: Fuzzy) -> Self {
+        self.fuzzy = fuzzy;
+        self
+    }
+
+    fn with_highlighter(mut self, highlighter: Highlighter) -> Self {
+        self.highlighter = highlighter;
+        self
+    }
+
+    fn with_store_content(mut self, store_content: bool) -> Self {
+        self.store_content = store_content;
+        self
+    }
+
+    // Thư mục cho file tạm của `fit_stream`, mặc định là thư mục tạm của hệ thống
+    fn with_spill_dir(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.spill_dir = dir.into();
+        self
+    }
+
+    fn with_stopwords(mut self, stopwords: Stopwords) -> Self {
+        self.stopwords = stopwords;
+        self
+    }
+
+    // Nhân điểm của một trường, ví dụ tiêu đề quan trọng hơn mô tả
+    fn with_boost(mut self, field: &str, boost: f64) -> Self {
+        self.boosts.insert(field.to_lowercase(), boost);
+        self
+    }
+
+    fn boost(&self, field: &str) -> f64 {
+        self.boosts.get(field).copied().unwrap_or(1.0)
+    }
+
+    fn view(&self, field: &str) -> Option<View<'_>> {
+        if field == DEFAULT_FIELD {
+            return Some(View {
+                name: DEFAULT_FIELD,
+                index: &self.index,
+                idf_scores: &self.idf_scores,
+                norms: &self.norms,
+                lexicon: &self.lexicon,
+            });
+        }
+        self.fields.get_key_value(field).map(|(name, field)| View {
+            name,
+            index: &field.index,
+            idf_scores: &field.idf_scores,
+            norms: &field.norms,
+            lexicon: &field.lexicon,
+        })
+    }
+
+    fn views(&self) -> impl Iterator<Item = View<'_>> {
+        std::iter::once(DEFAULT_FIELD)
+            .chain(self.fields.keys().map(String::as_str))
+            .filter_map(|field| self.view(field))
+    }
+
+    // Văn bản đã lưu theo doc id
+    fn get(&self, doc: usize) -> Option<&Document> {
+        self.store.get(doc)
+    }
+
+    // Doc id của văn bản có mã định danh `id`
+    fn find(&self, id: &str) -> Option<usize> {
+        self.ids.get(id).copied()
+    }
+
+    fn with_tf_scheme(mut self, scheme: TfScheme) -> Self {
+        self.tf_scheme = scheme;
+        self
+    }
+
+    fn with_idf_scheme(mut self, scheme: IdfScheme) -> Self {
+        self.idf_scheme = scheme;
+        self
+    }
+
+    // Chuẩn hóa áp dụng cho kết quả của `transform`
+    fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    fn with_scoring(mut self, scoring: Scoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    // Trọng số TF-IDF của term trong một văn bản đã fit, từ posting tương ứng
+    fn weight(&self, posting: &Posting, idf: f64) -> f64 {
+        self.weight_in(&self.index, posting, idf)
+    }
+
+    fn weight_in(&self, index: &Index, posting: &Posting, idf: f64) -> f64 {
+        let length = index.lengths[posting.doc];
+        let max_tf = index.max_tf[posting.doc];
+        self.tf_scheme.weight(posting.tf, length, max_tf) * idf
+    }
+
+    // Tìm kiếm văn bản tự do trên mọi trường, trả về tối đa `k` kết quả có điểm cao nhất
+    fn search(&self, query: &str, k: usize) -> Vec<Hit> {
+        top_k(self.scores(query), k)
+    }
+
+    // Điểm của mọi văn bản khớp truy vấn, chưa sắp xếp
+    fn scores(&self, query: &str) -> HashMap<usize, f64> {
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for view in self.views() {
+            let tokens = self.analyzer(view.name).analyze(query);
+            self.score_field(view, &tokens, &HashMap::new(), &mut scores);
+        }
+        scores
+    }
+
+    // Như `search` nhưng chấp nhận lỗi chính tả: mỗi term được mở rộng thành các term
+    // gần đúng trong từ điển, điểm bị giảm theo số phép sửa
+    fn search_fuzzy(&self, query: &str, k: usize) -> Vec<Hit> {
+        self.search_expanded(query, k, false)
+    }
+
+    // Như `search_fuzzy`, term cuối còn khớp các term bắt đầu bằng nó (gõ dở trong ô tìm kiếm)
+    fn search_prefix(&self, query: &str, k: usize) -> Vec<Hit> {
+        self.search_expanded(query, k, true)
+    }
+
+    // Mỗi trường được mở rộng theo từ điển và bộ phân tích của chính nó
+    fn search_expanded(&self, query: &str, k: usize, prefix: bool) -> Vec<Hit> {
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for view in self.views() {
+            let tokens = self.analyzer(view.name).analyze(query);
+            let mut expanded = Vec::new();
+            let mut factors: HashMap<String, f64> = HashMap::new();
+            for (i, token) in tokens.iter().enumerate() {
+                let last = prefix && i + 1 == tokens.len();
+                for (term, factor) in self.expand(view, &token.text, last) {
+                    let best = factors.entry(term.clone()).or_insert(0.0);
+                    *best = best.max(factor);
+                    expanded.push(Token {
+                        text: term,
+                        ..token.clone()
+                    });
+                }
+            }
+            self.score_field(view, &expanded, &factors, &mut scores);
+        }
+        top_k(scores, k)
+    }
+
+    // Các term trong từ điển của trường thay cho `term`, kèm hệ số nhân điểm (1 nếu khớp chính xác)
+    fn expand(&self, view: View<'_>, term: &str, prefix: bool) -> Vec<(String, f64)> {
+        let mut expansions: HashMap<&str, f64> = HashMap::new();
+        for (found, distance) in view.lexicon.fuzzy(term, self.fuzzy.distance_for(term)) {
+            expansions.insert(found, self.fuzzy.penalty.powi(distance as i32));
+        }
+        if prefix {
+            for found in view.lexicon.prefix(term) {
+                let factor = expansions.entry(found).or_insert(0.0);
+                *factor = factor.max(self.fuzzy.prefix_penalty);
+            }
+        }
+        // Giữ các term tốt nhất, ưu tiên term xuất hiện trong nhiều văn bản
+        let mut expansions: Vec<(String, f64)> = expansions
+            .into_iter()
+            .map(|(found, factor)| (found.to_string(), factor))
+            .collect();
+        expansions.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(
+                    view.index
+                        .document_frequency(&b.0)
+                        .cmp(&view.index.document_frequency(&a.0)),
+                )
+                .then(a.0.cmp(&b.0))
+        });
+        expansions.truncate(self.fuzzy.max_expansions.max(1));
+        expansions
+    }
+
+    // Cộng điểm của một trường (đã nhân boost) vào `scores`; `factors` giảm điểm
+    // của các term mở rộng gần đúng, term không có trong đó giữ nguyên
+    fn score_field(
+        &self,
+        view: View<'_>,
+        tokens: &[Token],
+        factors: &HashMap<String, f64>,
+        scores: &mut HashMap<usize, f64>,
+    ) {
+        let factor = |term: &str| factors.get(term).copied().unwrap_or(1.0);
+        let boost = self.boost(view.name);
+        match self.scoring {
+            Scoring::Cosine => {
+                let mut query = Document::new("");
+                query.compute_tfidf_tokens(tokens, view.idf_scores, self.tf_scheme);
+                query.normalize_tfidf_scores();
+                for (term, query_weight) in query.weights() {
+                    let idf = view.idf_scores[term];
+                    for posting in view.index.postings(term) {
+                        let norm = self.doc_norm(view, posting.doc);
+                        if norm > 0.0 {
+                            *scores.entry(posting.doc).or_insert(0.0) += boost
+                                * factor(term)
+                                * query_weight
+                                * self.weight_in(view.index, posting, idf)
+                                / norm;
+                        }
+                    }
+                }
+            }
+            Scoring::Bm25 { k1, b } => {
+                let n = self.index.doc_count as f64;
+                let average = view.index.average_length();
+                let mut terms: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+                terms.sort();
+                terms.dedup();
+                for term in &terms {
+                    let postings = view.index.postings(term);
+                    let df = postings.len() as f64;
+                    let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                    for posting in postings {
+                        let tf = posting.tf as f64;
+                        let length = view.index.lengths[posting.doc] as f64;
+                        let denominator = tf + k1 * (1.0 - b + b * length / average);
+                        *scores.entry(posting.doc).or_insert(0.0) +=
+                            boost * factor(term) * idf * tf * (k1 + 1.0) / denominator;
+                    }
+                }
+            }
+        }
+    }
+
+    // Tìm kiếm và trả về mã định danh, điểm, đoạn trích có đánh dấu và các trường lưu trữ
+    fn search_results(&self, query: &str, k: usize) -> Vec<SearchResult> {
+        self.search(query, k)
+            .into_iter()
+            .filter_map(|hit| self.result(query, hit))
+            .collect()
+    }
+
+    fn result(&self, query: &str, hit: Hit) -> Option<SearchResult> {
+        let document = self.get(hit.doc)?;
+        Some(SearchResult {
+            doc: hit.doc,
+            id: document.id.clone(),
+            score: hit.score,
+            snippet: self.snippet(query, &document.content),
+            stored: document.stored.clone(),
+        })
+    }
+
+    // Các đoạn trích tốt nhất của `text` cho truy vấn, trọng số term là IDF
+    fn highlight(&self, query: &str, text: &str) -> Vec<Fragment> {
+        let weights: HashMap<String, f64> = self
+            .tokenize(query)
+            .into_iter()
+            .map(|t| {
+                let idf = self.idf_scores.get(&t.text).copied().unwrap_or(1.0);
+                (t.text, idf)
+            })
+            .collect();
+        self.highlighter
+            .highlight(text, &self.tokenize(text), &weights)
+    }
+
+    // Nối các đoạn trích thành một chuỗi để hiển thị
+    fn snippet(&self, query: &str, text: &str) -> String {
+        let fragments: Vec<String> = self
+            .highlight(query, text)
+            .into_iter()
+            .map(|f| f.text)
+            .collect();
+        fragments.join(&self.highlighter.separator)
+    }
+
+    // Thay Tokenizer mặc định, ví dụ bằng `Vietnamese` hoặc một `Pipeline`
+    fn with_analyzer(mut self, analyzer: impl Analyzer + 'static) -> Self {
+        self.analyzer = Box::new(analyzer);
+        self
+    }
+
+    fn with_field_analyzer(mut self, field: &str, analyzer: impl Analyzer + 'static) -> Self {
+        self.field_analyzers
+            .insert(field.to_string(), Box::new(analyzer));
+        self
+    }
+
+    fn analyzer(&self, field: &str) -> &dyn Analyzer {
+        self.field_analyzers
+            .get(field)
+            .map(Box::as_ref)
+            .unwrap_or(self.analyzer.as_ref())
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        self.analyzer(DEFAULT_FIELD).analyze(text)
+    }
+
+    fn transform(&self, document: &str) -> Document {
+        let mut doc = Document::new(document);
+        doc.vocabulary = Arc::clone(&self.vocabulary);
+        doc.compute_tfidf_tokens(&self.tokenize(document), &self.idf_scores, self.tf_scheme);
+        doc.normalize(self.normalization);
+        doc
+    }
+
+    fn transform_all(&self, documents: &[&str]) -> Vec<Document> {
+        documents.iter().map(|doc| self.transform(doc)).collect()
+    }
+
+    // Như `transform_all` nhưng chia văn bản cho `threads` luồng, giữ nguyên thứ tự
+    fn transform_parallel(&self, documents: &[&str], threads: usize) -> Vec<Document> {
+        let chunk = documents.len().div_ceil(threads.max(1)).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = documents
+                .chunks(chunk)
+                .map(|chunk| scope.spawn(move || self.transform_all(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    // `documents` phải theo đúng thứ tự các văn bản đã `fit`, vì doc id là vị trí trong danh sách
+    fn search_by_keyword<'a>(
+        &'a self,
+        keyword: &str,
+        documents: &'a [Document],
+    ) -> Vec<&'a Document> {
+        self.index
+            .lookup(keyword)
+            .into_iter()
+            .filter_map(|doc| documents.get(doc))
+            .collect()
+    }
+}
+
+impl TFIDF {
+    // ... (các phần khác của impl không thay đổi)
+
+    fn fit(&mut self, documents: &[&str]) {
+        self.fit_documents(documents.iter().map(|text| Document::new(text)));
+    }
+
+    // Fit trên các văn bản có mã định danh, nhiều trường và trường lưu trữ
+    fn fit_documents(&mut self, documents: impl IntoIterator<Item = Document>) {
+        self.clear();
+        for document in documents {
+            self.add(document);
+        }
+        self.refresh();
+    }
+
+    // Fit bằng `threads` luồng: mỗi luồng tách token và dựng chỉ mục con cho một phần
+    // văn bản, sau đó các chỉ mục con được nối lại (DF là tổng DF của các phần). Chỉ mục
+    // nằm trọn trong bộ nhớ; corpus lớn hơn bộ nhớ dùng `fit_stream`.
+    fn fit_parallel(&mut self, documents: &[&str], threads: usize) {
+        self.clear();
+        for partial in self.index_parallel(documents, threads.max(1)) {
+            self.index.append(partial);
+        }
+        for &text in documents {
+            let content = if self.store_content { text } else { "" };
+            self.store.push(Some(Document::new(content)));
+        }
+        let terms: Vec<String> = self.index.postings.keys().cloned().collect();
+        self.pending.changed(DEFAULT_FIELD, terms);
+        self.pending.docs.extend(0..self.index.slots());
+        self.refresh();
+    }
+
+    // Fit từ một nguồn văn bản tuần tự cho corpus không vừa bộ nhớ. Mỗi lô được phân tích
+    // song song thành một chỉ mục con và ghi ra file tạm trong `spill_dir`, văn bản gốc được
+    // ghi thẳng vào file kết quả; cuối cùng các file tạm được trộn theo thứ tự term thành
+    // chỉ mục của file kết quả (cùng định dạng với `save`) và mô hình được ánh xạ từ file đó
+    // như `load`. Trong bộ nhớ chỉ còn một lô văn bản, từ điển và các mảng theo doc id; file
+    // kết quả bị xóa khi mô hình không còn dùng tới nó. Khi lỗi mô hình bị xóa trắng, cấu
+    // hình giữ nguyên.
+    fn fit_stream(
+        &mut self,
+        documents: impl IntoIterator<Item = String>,
+        threads: usize,
+    ) -> Result<(), PersistError> {
+        self.clear();
+        let threads = threads.max(1);
+        let name = format!(
+            "tfidf-fit-{}-{}",
+            std::process::id(),
+            FIT_FILES.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = self.spill_dir.join(format!("{}.idx", name));
+        let mut file = FileWriter::create(&path, MAGIC)?;
+        let mut offsets = Encoder::new();
+        let mut spills = Vec::new();
+        let mut documents = documents.into_iter();
+        loop {
+            let batch: Vec<String> = documents.by_ref().take(BATCH_SIZE * threads).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let mut index = Index::new();
+            for partial in self.index_parallel(&batch, threads) {
+                index.append(partial);
+            }
+            let spill = self
+                .spill_dir
+                .join(format!("{}-{}.spill", name, spills.len()));
+            spills.push(spill_index(&spill, &index)?);
+            for text in &batch {
+                let content = if self.store_content { text } else { "" };
+                let mut encoder = Encoder::new();
+                encode_document(&mut encoder, &Document::new(content));
+                offsets.fixed(file.write(&encoder.bytes)?);
+            }
+        }
+        let content = self.merge_spills(&mut file, &spills)?;
+        drop(spills);
+        let count = (offsets.bytes.len() / 8) as u64;
+        let store = (file.write(&offsets.bytes)?, count);
+        self.write_root(&mut file, content, Vec::new(), store)?;
+        file.finish()?;
+        let mapping = Mapping::temporary(&path, MAGIC)?;
+        self.read(&mapping).inspect_err(|_| self.clear())
+    }
+
+    // Trộn các chỉ mục con đã ghi ra file tạm thành một chỉ mục trong `file`: term được duyệt
+    // theo thứ tự tên trên mọi file cùng lúc, postings của một term được nối lại (doc id dời
+    // theo vị trí của lô) và ghi ngay, IDF và chuẩn được tính như `refresh`
+    fn merge_spills(
+        &self,
+        file: &mut FileWriter,
+        spills: &[MappedIndex],
+    ) -> Result<u64, PersistError> {
+        let mut bases = Vec::with_capacity(spills.len());
+        let (mut lengths, mut max_tf) = (Vec::new(), Vec::new());
+        for spill in spills {
+            bases.push(lengths.len());
+            for doc in 0..spill.slots {
+                lengths.push(spill.field(spill.doc_record(doc), 0) as usize);
+                max_tf.push(spill.field(spill.doc_record(doc), 1) as usize);
+            }
+        }
+        let n = lengths.len();
+        // Tổng bình phương trọng số của từng văn bản, cộng theo thứ tự term như `norm_in`
+        let mut squares = vec![0.0; n];
+        // Số thứ tự trong chỉ mục trộn của từng term của mỗi file tạm
+        let mut ordinals: Vec<Vec<u64>> = spills
+            .iter()
+            .map(|spill| Vec::with_capacity(spill.term_count))
+            .collect();
+        let mut heap: std::collections::BinaryHeap<_> = spills
+            .iter()
+            .enumerate()
+            .filter(|(_, spill)| spill.term_count > 0)
+            .map(|(at, spill)| std::cmp::Reverse((spill.term(0), at)))
+            .collect();
+        let mut section = IndexSection::default();
+        while let Some(&std::cmp::Reverse((term, _))) = heap.peek() {
+            let mut postings = Vec::new();
+            // Các file tạm cùng term được lấy theo thứ tự lô nên doc id vẫn tăng dần
+            while let Some(&std::cmp::Reverse((next, at))) = heap.peek() {
+                if next != term {
+                    break;
+                }
+                heap.pop();
+                let spill = &spills[at];
+                let ordinal = ordinals[at].len();
+                postings.extend(spill.decode(ordinal)?.into_iter().map(|posting| Posting {
+                    doc: posting.doc + bases[at],
+                    ..posting
+                }));
+                ordinals[at].push(section.terms.len() as u64);
+                if ordinal + 1 < spill.term_count {
+                    heap.push(std::cmp::Reverse((spill.term(ordinal + 1), at)));
+                }
+            }
+            let idf = self.idf_scheme.weight(postings.len(), n);
+            for posting in &postings {
+                let doc = posting.doc;
+                let weight = self.tf_scheme.weight(posting.tf, lengths[doc], max_tf[doc]) * idf;
+                squares[doc] += weight.powi(2);
+            }
+            section.term(file, term, idf, &postings)?;
+        }
+
+        let documents = spills
+            .iter()
+            .zip(&ordinals)
+            .flat_map(|(spill, ordinals)| {
+                // Thứ tự tên trong file tạm và trong chỉ mục trộn như nhau nên vẫn tăng dần
+                (0..spill.slots).map(move |doc| {
+                    spill
+                        .doc_ordinals(doc)
+                        .into_iter()
+                        .map(|ordinal| ordinals[ordinal])
+                        .collect()
+                })
+            })
+            .enumerate()
+            .map(|(doc, ordinals)| DocEntry {
+                live: true,
+                length: lengths[doc],
+                max_tf: max_tf[doc],
+                norm: squares[doc].sqrt(),
+                ordinals,
+            });
+        Ok(section.finish(file, documents)?)
+    }
+
+    // Fit từ một nguồn đọc, mỗi dòng là một văn bản, ví dụ file xuất toàn bộ danh mục sản phẩm.
+    // Fit vào một mô hình mới cùng cấu hình và chỉ thay mô hình hiện tại khi đọc hết không
+    // lỗi, nên lỗi đọc giữa chừng giữ nguyên mô hình cũ.
+    fn fit_reader(
+        &mut self,
+        reader: impl std::io::BufRead,
+        threads: usize,
+    ) -> Result<usize, PersistError> {
+        let mut fitted = TFIDF::new();
+        self.swap_config(&mut fitted);
+        let mut error = None;
+        let lines = reader.lines().map_while(|line| match line {
+            Ok(line) => Some(line),
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        });
+        let result = fitted.fit_stream(lines, threads);
+        match error.map(PersistError::Io).or(result.err()) {
+            Some(e) => {
+                self.swap_config(&mut fitted);
+                Err(e)
+            }
+            None => {
+                *self = fitted;
+                Ok(self.index.doc_count)
+            }
+        }
+    }
+
+    // Đổi cấu hình (không gồm dữ liệu đã fit) giữa hai mô hình
+    fn swap_config(&mut self, other: &mut TFIDF) {
+        std::mem::swap(&mut self.tf_scheme, &mut other.tf_scheme);
+        std::mem::swap(&mut self.idf_scheme, &mut other.idf_scheme);
+        std::mem::swap(&mut self.normalization, &mut other.normalization);
+        std::mem::swap(&mut self.scoring, &mut other.scoring);
+        std::mem::swap(&mut self.analyzer, &mut other.analyzer);
+        std::mem::swap(&mut self.field_analyzers, &mut other.field_analyzers);
+        std::mem::swap(&mut self.boosts, &mut other.boosts);
+        std::mem::swap(&mut self.highlighter, &mut other.highlighter);
+        std::mem::swap(&mut self.store_content, &mut other.store_content);
+        std::mem::swap(&mut self.stopwords, &mut other.stopwords);
+        std::mem::swap(&mut self.fuzzy, &mut other.fuzzy);
+        std::mem::swap(&mut self.spill_dir, &mut other.spill_dir);
+    }
+
+    // Dựng chỉ mục con cho từng phần của lô, doc id trong mỗi chỉ mục con bắt đầu từ 0
+    fn index_parallel(&self, batch: &[impl AsRef<str> + Sync], threads: usize) -> Vec<Index> {
+        let chunk = batch.len().div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = batch
+                .chunks(chunk)
+                .map(|texts| {
+                    scope.spawn(move || {
+                        let mut index = Index::new();
+                        for (doc, text) in texts.iter().enumerate() {
+                            index.add(doc, &self.tokenize(text.as_ref()));
+                        }
+                        index
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    fn clear(&mut self) {
+        self.index = Index::new();
+        self.idf_scores.clear();
+        self.norms.clear();
+        self.parts.clear();
+        self.refreshed = 0;
+        self.fields.clear();
+        self.store.clear();
+        self.ids.clear();
+        self.lexicon = Lexicon::default();
+        self.vocabulary = Arc::default();
+        self.pending = Pending::default();
+    }
+
+    // Thêm văn bản vào mô hình đã fit, trả về doc id; gọi `refresh` trước khi tìm kiếm
+    fn add_document(&mut self, document: &str) -> usize {
+        self.add(Document::new(document))
+    }
+
+    // Thêm văn bản có cấu trúc; nếu mã định danh đã tồn tại thì thay thế và giữ doc id cũ
+    fn add(&mut self, document: Document) -> usize {
+        let doc = match self.find(&document.id) {
+            Some(doc) => {
+                self.remove_document(doc);
+                doc
+            }
+            None => self.index.slots(),
+        };
+        self.place(doc, document);
+        doc
+    }
+
+    // Đánh chỉ mục văn bản vào doc id còn trống (mới hoặc vừa bị xóa) trên mọi trường
+    fn place(&mut self, doc: usize, mut document: Document) {
+        let tokens = self.tokenize(&document.content);
+        self.index.add(doc, &tokens);
+        self.pending
+            .changed(DEFAULT_FIELD, self.index.terms[doc].iter().cloned());
+        for name in document.fields.keys() {
+            self.fields.entry(name.clone()).or_default();
+        }
+        // Mọi trường đều có mặt mọi văn bản (có thể rỗng) để doc id và số văn bản khớp nhau
+        let names: Vec<String> = self.fields.keys().cloned().collect();
+        for name in names {
+            let text = document.fields.get(&name).map(String::as_str).unwrap_or("");
+            let tokens = self.analyzer(&name).analyze(text);
+            let field = self.fields.get_mut(&name).unwrap();
+            field.index.add(doc, &tokens);
+            self.pending
+                .changed(&name, field.index.terms[doc].iter().cloned());
+        }
+        if !document.id.is_empty() {
+            self.ids.insert(document.id.clone(), doc);
+        }
+        document.tfidf_scores = SparseVector::default();
+        document.vocabulary = Arc::default();
+        if !self.store_content {
+            document.content.clear();
+            document.fields.clear();
+        }
+        self.store.set(doc, document);
+        self.pending.docs.insert(doc);
+    }
+
+    // Xóa văn bản; doc id không được dùng lại cho văn bản khác ngoài `update_document`
+    fn remove_document(&mut self, doc: usize) -> bool {
+        if !self.index.is_live(doc) {
+            return false;
+        }
+        let removed = self.index.remove(doc);
+        self.pending.changed(DEFAULT_FIELD, removed);
+        for (name, field) in self.fields.iter_mut() {
+            let removed = field.index.remove(doc);
+            self.pending.changed(name, removed);
+        }
+        if let Some(document) = self.store.take(doc) {
+            self.ids.remove(&document.id);
+        }
+        self.pending.docs.remove(&doc);
+        true
+    }
+
+    // Thay văn bản (nội dung, mọi trường, mã định danh và dữ liệu lưu kèm) nhưng giữ doc id;
+    // số văn bản không đổi nên chỉ các term liên quan cần tính lại. Trả về false nếu doc id
+    // không tồn tại hoặc mã định danh mới đang thuộc về văn bản khác.
+    fn update_document(&mut self, doc: usize, document: Document) -> bool {
+        if !self.index.is_live(doc) {
+            return false;
+        }
+        if self.find(&document.id).is_some_and(|other| other != doc) {
+            return false;
+        }
+        self.remove_document(doc);
+        self.place(doc, document);
+        true
+    }
+
+    // Tính lại IDF của các term có DF thay đổi và chuẩn của các văn bản bị ảnh hưởng.
+    // Chuẩn của văn bản mới thêm được tính ngay khi tìm kiếm nên không bao giờ bị thiếu,
+    // nhưng kết quả chỉ chính xác sau khi gọi hàm này.
+    fn refresh(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending);
+        let n = self.index.doc_count;
+        let resized = n != self.refreshed;
+        self.refreshed = n;
+
+        let terms = pending.terms.remove(DEFAULT_FIELD).unwrap_or_default();
+        let mut content = FieldIndex {
+            index: std::mem::take(&mut self.index),
+            idf_scores: std::mem::take(&mut self.idf_scores),
+            norms: std::mem::take(&mut self.norms),
+            parts: std::mem::take(&mut self.parts),
+            lexicon: std::mem::take(&mut self.lexicon),
+        };
+        self.refresh_field(&mut content, &terms, &pending.docs, resized);
+        FieldIndex {
+            index: self.index,
+            idf_scores: self.idf_scores,
+            norms: self.norms,
+            parts: self.parts,
+            lexicon: self.lexicon,
+        } = content;
+
+        // Term mới được cấp id theo thứ tự term để cùng một chuỗi thay đổi luôn cho cùng
+        // các id; term đã có id giữ nguyên id đó
+        let mut terms: Vec<&String> = terms
+            .iter()
+            .filter(|term| {
+                self.idf_scores.contains_key(*term) && self.vocabulary.id(term).is_none()
+            })
+            .collect();
+        terms.sort();
+        if !terms.is_empty() {
+            let vocabulary = Arc::make_mut(&mut self.vocabulary);
+            for term in terms {
+                vocabulary.insert(term);
+            }
+        }
+
+        let mut fields = std::mem::take(&mut self.fields);
+        for (name, field) in fields.iter_mut() {
+            let terms = pending.terms.remove(name).unwrap_or_default();
+            self.refresh_field(field, &terms, &pending.docs, resized);
+        }
+        self.fields = fields;
+    }
+
+    // IDF chỉ cần DF đã lưu trong postings và N, nên khi N đổi thì IDF mọi term được tính
+    // lại mà không đọc postings. Chỉ duyệt lại các văn bản mới, văn bản chứa term có DF đổi
+    // và (nếu IDF không tách được thành base + shift) mọi văn bản khi N đổi; chuẩn của các
+    // văn bản còn lại được suy ra từ `NormParts`.
+    fn refresh_field(
+        &self,
+        field: &mut FieldIndex,
+        terms: &HashSet<String>,
+        docs: &HashSet<usize>,
+        resized: bool,
+    ) {
+        let n = self.refreshed;
+        let slots = field.index.slots();
+        let separable = self.idf_scheme.base(1).is_some();
+        let mut affected: HashSet<usize> = docs.clone();
+        // Văn bản chưa có `NormParts`, ví dụ sau khi `load`
+        affected.extend(field.parts.len().min(slots)..slots);
+        if resized {
+            field.idf_scores = field
+                .index
+                .term_list()
+                .into_iter()
+                .map(|term| {
+                    let df = field.index.document_frequency(term);
+                    (term.to_string(), self.idf_scheme.weight(df, n))
+                })
+                .collect();
+            if !separable {
+                affected.extend(0..slots);
+            }
+        } else {
+            for term in terms {
+                match field.index.document_frequency(term) {
+                    0 => {
+                        field.idf_scores.remove(term);
+                    }
+                    df => {
+                        field
+                            .idf_scores
+                            .insert(term.clone(), self.idf_scheme.weight(df, n));
+                    }
+                }
+            }
+        }
+        for term in terms {
+            affected.extend(field.index.postings(term).iter().map(|p| p.doc));
+            if field.idf_scores.contains_key(term) {
+                field.lexicon.insert(term);
+            } else {
+                field.lexicon.remove(term);
+            }
+        }
+
+        field.norms.resize(slots, 0.0);
+        field.parts.resize(slots, NormParts::default());
+        let shift = self.idf_scheme.shift(n);
+        for doc in 0..slots {
+            if !field.index.is_live(doc) {
+                field.norms[doc] = 0.0;
+                field.parts[doc] = NormParts::default();
+            } else if affected.contains(&doc) {
+                field.norms[doc] = self.norm_in(&field.index, &field.idf_scores, doc);
+                if separable {
+                    field.parts[doc] = self.parts(&field.index, doc);
+                }
+            } else if resized {
+                field.norms[doc] = field.parts[doc].norm(shift);
+            }
+        }
+    }
+
+    // Chuẩn L2 của vector TF-IDF của một văn bản, tính từ chỉ mục. Term chưa có IDF (văn bản thêm sau lần `refresh` cuối) không được tính
+    fn norm_in(&self, index: &Index, idf_scores: &HashMap<String, f64>, doc: usize) -> f64 {
+        index
+            .doc_terms(doc)
+            .into_iter()
+            .filter_map(|term| {
+                let postings = index.postings(term);
+                let at = postings.binary_search_by_key(&doc, |p| p.doc).unwrap();
+                Some(
+                    self.weight_in(index, &postings[at], *idf_scores.get(term)?)
+                        .powi(2),
+                )
+            })
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    fn parts(&self, index: &Index, doc: usize) -> NormParts {
+        let mut parts = NormParts::default();
+        for term in index.doc_terms(doc) {
+            let postings = index.postings(term);
+            let at = postings.binary_search_by_key(&doc, |p| p.doc).unwrap();
+            let weight = self.weight_in(index, &postings[at], 1.0).powi(2);
+            let base = self.idf_scheme.base(postings.len()).unwrap_or(0.0);
+            parts.weights += weight;
+            parts.linear += weight * base;
+            parts.squares += weight * base * base;
+        }
+        parts
+    }
+
+    // Chuẩn dùng khi tìm kiếm; văn bản thêm sau lần `refresh` cuối được tính tại chỗ
+    fn doc_norm(&self, view: View<'_>, doc: usize) -> f64 {
+        match view.norms.get(doc) {
+            Some(&norm) => norm,
+            None => self.norm_in(view.index, view.idf_scores, doc),
+        }
+    }
+}
+
+// Các thay đổi chưa được tính lại IDF và chuẩn
+#[derive(Debug, Clone, Default)]
+struct Pending {
+    // Các term có DF thay đổi, theo tên trường
+    terms: HashMap<String, HashSet<String>>,
+    // Các văn bản được thêm hoặc sửa
+    docs: HashSet<usize>,
+}
+
+impl Pending {
+    fn changed(&mut self, field: &str, terms: impl IntoIterator<Item = String>) {
+        self.terms
+            .entry(field.to_string())
+            .or_default()
+            .extend(terms);
+    }
+}
+
+// Cây truy vấn của ngôn ngữ truy vấn Boolean
+#[derive(Debug, Clone, PartialEq)]
+enum Query {
+    Term(String),
+    // Các term phải xuất hiện liên tiếp theo đúng thứ tự
+    Phrase(Vec<String>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    // Giới hạn truy vấn con vào một trường, ví dụ `content:áo`
+    Field(String, Box<Query>),
+}
+
+// Lỗi khi phân tích truy vấn
+#[derive(Debug, Clone, PartialEq)]
+enum ParseError {
+    UnexpectedEnd,
+    Unexpected(String),
+    UnclosedQuote,
+    // Toán hạng không còn term nào sau khi phân tích, ví dụ chỉ có dấu câu
+    Empty(String),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "truy vấn kết thúc đột ngột"),
+            ParseError::Unexpected(lexeme) => write!(f, "không mong đợi '{}'", lexeme),
+            ParseError::UnclosedQuote => write!(f, "thiếu dấu ngoặc kép đóng"),
+            ParseError::Empty(text) => write!(f, "'{}' không có term nào để tìm", text),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Field(String),
+    Word(String),
+    Quoted(String),
+}
+
+// Trường mặc định của văn bản
+const DEFAULT_FIELD: &str = "content";
+
+impl Query {
+    fn lex(input: &str) -> Result<Vec<Lexeme>, ParseError> {
+        let mut lexemes = Vec::new();
+        let mut chars = input.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            match c {
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                '(' => {
+                    chars.next();
+                    lexemes.push(Lexeme::Open);
+                }
+                ')' => {
+                    chars.next();
+                    lexemes.push(Lexeme::Close);
+                }
+                '"' => {
+                    chars.next();
+                    let mut phrase = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => phrase.push(c),
+                            None => return Err(ParseError::UnclosedQuote),
+                        }
+                    }
+                    lexemes.push(Lexeme::Quoted(phrase));
+                }
+                _ => {
+                    let mut word = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    match word.as_str() {
+                        "AND" => lexemes.push(Lexeme::And),
+                        "OR" => lexemes.push(Lexeme::Or),
+                        "NOT" => lexemes.push(Lexeme::Not),
+                        _ => match word.split_once(':') {
+                            Some((field, rest)) if !field.is_empty() => {
+                                lexemes.push(Lexeme::Field(field.to_lowercase()));
+                                if !rest.is_empty() {
+                                    lexemes.push(Lexeme::Word(rest.to_string()));
+                                }
+                            }
+                            _ => lexemes.push(Lexeme::Word(word)),
+                        },
+                    }
+                }
+            }
+        }
+        Ok(lexemes)
+    }
+
+    // Phân tích truy vấn; độ ưu tiên NOT > AND > OR, hai term liền nhau ngầm hiểu là AND
+    fn parse(input: &str) -> Result<Query, ParseError> {
+        Query::parse_with(input, &|_, text| Tokenizer::tokenize(text))
+    }
+
+    // Phân tích truy vấn, dùng `tokenize(field, text)` để tách term giống như khi đánh chỉ mục
+    fn parse_with(
+        input: &str,
+        tokenize: &dyn Fn(&str, &str) -> Vec<Token>,
+    ) -> Result<Query, ParseError> {
+        let lexemes = Query::lex(input)?;
+        let mut parser = Parser {
+            lexemes,
+            next: 0,
+            field: DEFAULT_FIELD.to_string(),
+            tokenize,
+        };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(lexeme) => Err(ParseError::Unexpected(format!("{:?}", lexeme))),
+        }
+    }
+
+    // Các cặp (trường, term) không nằm dưới NOT, dùng để xếp hạng kết quả
+    fn positive_terms<'a>(&'a self, field: &'a str, terms: &mut Vec<(&'a str, &'a str)>) {
+        match self {
+            Query::Term(term) => terms.push((field, term)),
+            Query::Phrase(phrase) => terms.extend(phrase.iter().map(|term| (field, term.as_str()))),
+            Query::And(left, right) | Query::Or(left, right) => {
+                left.positive_terms(field, terms);
+                right.positive_terms(field, terms);
+            }
+            Query::Field(field, inner) => inner.positive_terms(field, terms),
+            Query::Not(_) => {}
+        }
+    }
+}
+
+struct Parser<'a> {
+    lexemes: Vec<Lexeme>,
+    next: usize,
+    // Trường đang được phân tích, quyết định bộ phân tích dùng cho văn bản
+    field: String,
+    tokenize: &'a dyn Fn(&str, &str) -> Vec<Token>,
+}
+
+impl Parser<'_> {
+    fn terms(&self, text: &str) -> Vec<String> {
+        (self.tokenize)(&self.field, text)
+            .into_iter()
+            .map(|t| t.text)
+            .collect()
+    }
+
+    // Văn bản có thể chuyển thành Term hoặc Phrase tùy số token sau khi tách. Văn bản
+    // không có token nào là lỗi, vì coi nó là rỗng sẽ làm AND không khớp gì và NOT khớp mọi thứ.
+    fn text(&self, text: &str) -> Result<Query, ParseError> {
+        let mut terms = self.terms(text);
+        match terms.len() {
+            0 => Err(ParseError::Empty(text.to_string())),
+            1 => Ok(Query::Term(terms.remove(0))),
+            _ => Ok(Query::Phrase(terms)),
+        }
+    }
+
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.next).cloned();
+        self.next += 1;
+        lexeme
+    }
+
+    fn or(&mut self) -> Result<Query, ParseError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Lexeme::Or) {
+            self.advance();
+            left = Query::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Query, ParseError> {
+        let mut left = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Lexeme::And) => {
+                    self.advance();
+                }
+                Some(Lexeme::Or) | Some(Lexeme::Close) | None => break,
+                Some(_) => {}
+            }
+            left = Query::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Query, ParseError> {
+        match self.advance() {
+            Some(Lexeme::Not) => Ok(Query::Not(Box::new(self.unary()?))),
+            Some(Lexeme::Field(field)) => {
+                let outer = std::mem::replace(&mut self.field, field.clone());
+                let inner = self.unary();
+                self.field = outer;
+                Ok(Query::Field(field, Box::new(inner?)))
+            }
+            Some(Lexeme::Open) => {
+                let query = self.or()?;
+                match self.advance() {
+                    Some(Lexeme::Close) => Ok(query),
+                    Some(lexeme) => Err(ParseError::Unexpected(format!("{:?}", lexeme))),
+                    None => Err(ParseError::UnexpectedEnd),
+                }
+            }
+            Some(Lexeme::Word(word)) => self.text(&word),
+            Some(Lexeme::Quoted(phrase)) => self.text(&phrase),
+            Some(lexeme) => Err(ParseError::Unexpected(format!("{:?}", lexeme))),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
+
+impl Index {
+    // Các văn bản chứa cụm từ, kiểm tra vị trí liên tiếp trong postings
+    fn phrase(&self, terms: &[String]) -> Vec<usize> {
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let refs: Vec<&str> = terms.iter().map(String::as_str).collect();
+        self.lookup_all(&refs)
+            .into_iter()
+            .filter(|&doc| {
+                let postings: Vec<&Posting> = terms
+                    .iter()
+                    .map(|term| {
+                        let list = self.postings(term);
+                        &list[list.binary_search_by_key(&doc, |p| p.doc).unwrap()]
+                    })
+                    .collect();
+                postings[0].positions.iter().any(|&start| {
+                    postings
+                        .iter()
+                        .enumerate()
+                        .skip(1)
+                        .all(|(offset, p)| p.positions.binary_search(&(start + offset)).is_ok())
+                })
+            })
+            .collect()
+    }
+}
+
+impl TFIDF {
+    // Tìm kiếm bằng ngôn ngữ truy vấn Boolean, xếp hạng theo tổng trọng số TF-IDF đã chuẩn hóa
+    fn query(&self, input: &str, k: usize) -> Result<Vec<Hit>, ParseError> {
+        let query = Query::parse_with(input, &|field, text| self.analyzer(field).analyze(text))?;
+        let mut terms = Vec::new();
+        query.positive_terms(DEFAULT_FIELD, &mut terms);
+        terms.sort_unstable();
+        terms.dedup();
+
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for doc in self.evaluate(DEFAULT_FIELD, &query) {
+            scores.insert(doc, 0.0);
+        }
+        for (field, term) in terms {
+            let Some(view) = self.view(field) else {
+                continue;
+            };
+            let Some(&idf) = view.idf_scores.get(term) else {
+                continue;
+            };
+            let boost = self.boost(field);
+            for posting in view.index.postings(term) {
+                let norm = self.doc_norm(view, posting.doc);
+                if let Some(score) = scores.get_mut(&posting.doc) {
+                    if norm > 0.0 {
+                        *score += boost * self.weight_in(view.index, posting, idf) / norm;
+                    }
+                }
+            }
+        }
+        Ok(top_k(scores, k))
+    }
+
+    // Đánh giá truy vấn trên trường `field`, trả về doc id đã sắp xếp
+    fn evaluate(&self, field: &str, query: &Query) -> Vec<usize> {
+        match query {
+            Query::Term(term) => self
+                .view(field)
+                .map_or(Vec::new(), |v| v.index.lookup(term)),
+            Query::Phrase(terms) => self
+                .view(field)
+                .map_or(Vec::new(), |v| v.index.phrase(terms)),
+            Query::And(left, right) => {
+                let right = self.evaluate(field, right);
+                self.evaluate(field, left)
+                    .into_iter()
+                    .filter(|doc| right.binary_search(doc).is_ok())
+                    .collect()
+            }
+            Query::Or(left, right) => {
+                let mut docs = self.evaluate(field, left);
+                docs.extend(self.evaluate(field, right));
+                docs.sort_unstable();
+                docs.dedup();
+                docs
+            }
+            Query::Not(inner) => {
+                let excluded = self.evaluate(field, inner);
+                (0..self.index.slots())
+                    .filter(|&doc| self.index.is_live(doc))
+                    .filter(|doc| excluded.binary_search(doc).is_err())
+                    .collect()
+            }
+            Query::Field(field, inner) => self.evaluate(field, inner),
+        }
+    }
+}
+
+// Cụm từ khóa cùng điểm TF-IDF của nó trong văn bản
+#[derive(Debug, Clone, PartialEq)]
+struct Keyphrase {
+    phrase: String,
+    score: f64,
+}
+
+impl TFIDF {
+    // Trích `k` cụm từ khóa (1-3 từ) của văn bản đã đánh chỉ mục, dùng để gắn tag sản phẩm
+    fn extract_keyphrases(&self, doc: usize, k: usize, diversity: f64) -> Vec<Keyphrase> {
+        match self.get(doc) {
+            Some(document) => self.keyphrases(&document.content, k, diversity),
+            None => Vec::new(),
+        }
+    }
+
+    // Điểm của cụm từ = số lần xuất hiện / độ dài văn bản * IDF trung bình các từ, nên với
+    // một từ đơn thì bằng đúng điểm TF-IDF và cụm dài không được lợi chỉ vì nhiều từ. Sau đó xếp lại bằng Maximal Marginal Relevance:
+    // `diversity` = 0 chỉ theo điểm, càng gần 1 càng tránh các cụm trùng từ với cụm đã chọn.
+    fn keyphrases(&self, text: &str, k: usize, diversity: f64) -> Vec<Keyphrase> {
+        let tokens = self.tokenize(text);
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+        let mut counts: HashMap<Vec<&str>, usize> = HashMap::new();
+        for first in 0..tokens.len() {
+            for last in first..tokens.len().min(first + 3) {
+                // Cụm từ không vượt qua dấu câu hay khoảng trống do từ bị lọc
+                if last > first
+                    && (tokens[last].position != tokens[last - 1].position + 1
+                        || !text
+                            .get(tokens[last - 1].end..tokens[last].start)
+                            .is_some_and(|gap| gap.chars().all(char::is_whitespace)))
+                {
+                    break;
+                }
+                let words: Vec<&str> = tokens[first..=last]
+                    .iter()
+                    .map(|t| t.text.as_str())
+                    .collect();
+                if self.stopwords.contains(words[0])
+                    || self.stopwords.contains(words[words.len() - 1])
+                {
+                    continue;
+                }
+                *counts.entry(words).or_insert(0) += 1;
+            }
+        }
+
+        let length = tokens.len() as f64;
+        let mut candidates: Vec<(Vec<&str>, f64)> = counts
+            .into_iter()
+            .map(|(words, count)| {
+                let idf: f64 = words
+                    .iter()
+                    .map(|word| self.idf_scores.get(*word).copied().unwrap_or(0.0))
+                    .sum::<f64>()
+                    / words.len() as f64;
+                (words, count as f64 / length * idf)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+        let best = candidates.first().map_or(1.0, |c| c.1);
+
+        // Độ giống giữa hai cụm là hệ số Jaccard của tập từ
+        let similarity = |a: &[&str], b: &[&str]| {
+            let shared = a.iter().filter(|word| b.contains(word)).count() as f64;
+            shared / ((a.len() + b.len()) as f64 - shared)
+        };
+        let mut selected: Vec<(Vec<&str>, f64)> = Vec::new();
+        while selected.len() < k && !candidates.is_empty() {
+            let mut pick = (0, f64::NEG_INFINITY);
+            for (i, (words, score)) in candidates.iter().enumerate() {
+                let redundancy = selected
+                    .iter()
+                    .map(|(chosen, _)| similarity(words, chosen))
+                    .fold(0.0, f64::max);
+                let value = (1.0 - diversity) * score / best - diversity * redundancy;
+                if value > pick.1 {
+                    pick = (i, value);
+                }
+            }
+            selected.push(candidates.remove(pick.0));
+        }
+        selected
+            .into_iter()
+            .map(|(words, score)| Keyphrase {
+                phrase: words.join(" "),
+                score,
+            })
+            .collect()
+    }
+}
+
+// Ánh xạ term <-> id số nguyên. Id không bao giờ bị thu hồi khi term biến mất
+// khỏi chỉ mục, nên vector tính trước đó vẫn so sánh được với vector mới.
+#[derive(Debug, Clone, Default)]
+struct Vocabulary {
+    ids: HashMap<String, u32>,
+    terms: Vec<String>,
+}
+
+impl Vocabulary {
+    // Id của term, cấp id mới nếu term chưa có
+    fn insert(&mut self, term: &str) -> u32 {
+        if let Some(&id) = self.ids.get(term) {
+            return id;
+        }
+        let id = self.terms.len() as u32;
+        self.ids.insert(term.to_string(), id);
+        self.terms.push(term.to_string());
+        id
+    }
+
+    fn id(&self, term: &str) -> Option<u32> {
+        self.ids.get(term).copied()
+    }
+
+    fn term(&self, id: u32) -> Option<&str> {
+        self.terms.get(id as usize).map(String::as_str)
+    }
+
+    fn len(&self) -> usize {
+        self.terms.len()
+    }
+}
+
+// Vector thưa: các id tăng dần cùng giá trị tương ứng
+#[derive(Debug, Clone, Default, PartialEq)]
+struct SparseVector {
+    indices: Vec<u32>,
+    values: Vec<f64>,
+}
+
+impl SparseVector {
+    // Tạo từ các cặp (id, giá trị) bất kỳ thứ tự; id trùng được cộng dồn
+    fn from_pairs(mut pairs: Vec<(u32, f64)>) -> Self {
+        pairs.sort_by_key(|pair| pair.0);
+        let mut vector = SparseVector::default();
+        for (index, value) in pairs {
+            if vector.indices.last() == Some(&index) {
+                *vector.values.last_mut().unwrap() += value;
+            } else {
+                vector.indices.push(index);
+                vector.values.push(value);
+            }
+        }
+        vector
+    }
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn get(&self, index: u32) -> Option<f64> {
+        let at = self.indices.binary_search(&index).ok()?;
+        Some(self.values[at])
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u32, f64)> + '_ {
+        self.indices
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+    }
+
+    // Tích vô hướng bằng cách trộn hai danh sách id đã sắp xếp
+    fn dot(&self, other: &SparseVector) -> f64 {
+        let (mut i, mut j, mut sum) = (0, 0, 0.0);
+        while i < self.len() && j < other.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    sum += self.values[i] * other.values[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        sum
+    }
+
+    fn norm(&self) -> f64 {
+        self.values.iter().map(|v| v * v).sum::<f64>().sqrt()
+    }
+
+    // Độ tương đồng cosine, bằng 0 nếu một trong hai vector rỗng
+    fn cosine(&self, other: &SparseVector) -> f64 {
+        let norms = self.norm() * other.norm();
+        if norms > 0.0 {
+            self.dot(other) / norms
+        } else {
+            0.0
+        }
+    }
+}
+
+impl TFIDF {
+    // Vector TF-IDF (chưa chuẩn hóa) của một văn bản đã đánh chỉ mục
+    fn vector(&self, doc: usize) -> Option<SparseVector> {
+        if !self.index.is_live(doc) {
+            return None;
+        }
+        let pairs = self
+            .index
+            .doc_terms(doc)
+            .into_iter()
+            .filter_map(|term| {
+                let postings = self.index.postings(term);
+                let at = postings.binary_search_by_key(&doc, |p| p.doc).ok()?;
+                let weight = self.weight(&postings[at], *self.idf_scores.get(term)?);
+                Some((self.vocabulary.id(term)?, weight))
+            })
+            .collect();
+        Some(SparseVector::from_pairs(pairs))
+    }
+
+    // Vector TF-IDF của một văn bản bất kỳ, bỏ các term chưa có trong từ vựng
+    fn vectorize(&self, text: &str) -> SparseVector {
+        self.transform(text).tfidf_scores
+    }
+
+    // Vector của một văn bản theo id của từ vựng này: văn bản do mô hình `transform` dùng
+    // ngay `tfidf_scores`, văn bản khác được ánh xạ lại theo term
+    fn vector_of(&self, document: &Document) -> SparseVector {
+        if Arc::ptr_eq(&document.vocabulary, &self.vocabulary) {
+            return document.tfidf_scores.clone();
+        }
+        SparseVector::from_pairs(
+            document
+                .weights()
+                .filter_map(|(term, score)| Some((self.vocabulary.id(term)?, score)))
+                .collect(),
+        )
+    }
+
+    // `k` văn bản giống văn bản `doc` nhất theo cosine, không gồm chính nó.
+    // Chỉ duyệt postings của các term trong văn bản nên không phải so với mọi văn bản.
+    fn more_like_this(&self, doc: usize, k: usize) -> Vec<Hit> {
+        let Some(vector) = self.vector(doc) else {
+            return Vec::new();
+        };
+        let norm = vector.norm();
+        if norm == 0.0 {
+            return Vec::new();
+        }
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for (id, value) in vector.iter() {
+            let term = self.vocabulary.term(id).unwrap();
+            let idf = self.idf_scores[term];
+            for posting in self.index.postings(term) {
+                let other = self.doc_norm(self.view(DEFAULT_FIELD).unwrap(), posting.doc);
+                if posting.doc != doc && other > 0.0 {
+                    *scores.entry(posting.doc).or_insert(0.0) +=
+                        value * self.weight(posting, idf) / (norm * other);
+                }
+            }
+        }
+        top_k(scores, k)
+    }
+}
+
+// Sinh số ngẫu nhiên SplitMix64: đủ cho khởi tạo k-means++ và cho kết quả lặp lại được theo seed
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Số thực trong [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Kết quả phân cụm: nhãn cụm của từng văn bản và tâm cụm (vector đặc theo id từ vựng)
+#[derive(Debug, Clone)]
+struct Clusters {
+    labels: Vec<usize>,
+    centroids: Vec<Vec<f64>>,
+}
+
+impl Clusters {
+    // Tính tâm là trung bình các vector của cụm; nhãn được đánh lại theo thứ tự xuất hiện
+    fn from_labels(vectors: &[SparseVector], labels: &[usize], dimension: usize) -> Self {
+        let mut renumber: HashMap<usize, usize> = HashMap::new();
+        let labels: Vec<usize> = labels
+            .iter()
+            .map(|label| {
+                let next = renumber.len();
+                *renumber.entry(*label).or_insert(next)
+            })
+            .collect();
+        let mut centroids = vec![vec![0.0; dimension]; renumber.len()];
+        let mut sizes = vec![0usize; renumber.len()];
+        for (vector, &label) in vectors.iter().zip(&labels) {
+            sizes[label] += 1;
+            for (id, value) in vector.iter() {
+                centroids[label][id as usize] += value;
+            }
+        }
+        for (centroid, size) in centroids.iter_mut().zip(sizes) {
+            centroid.iter_mut().for_each(|value| *value /= size as f64);
+        }
+        Clusters { labels, centroids }
+    }
+
+    fn len(&self) -> usize {
+        self.centroids.len()
+    }
+
+    // Doc id (vị trí trong danh sách vector) của các văn bản thuộc cụm
+    fn members(&self, cluster: usize) -> Vec<usize> {
+        (0..self.labels.len())
+            .filter(|&i| self.labels[i] == cluster)
+            .collect()
+    }
+}
+
+// Tâm cụm đặc cùng chuẩn của nó, chuẩn chỉ tính lại khi tâm được cập nhật
+struct Centroid {
+    values: Vec<f64>,
+    norm: f64,
+}
+
+impl Centroid {
+    fn new(values: Vec<f64>) -> Self {
+        let norm = values.iter().map(|v| v * v).sum::<f64>().sqrt();
+        Centroid { values, norm }
+    }
+
+    // Đặt tâm là trung bình từ tổng `sum` của `size` vector, ghi đè lên buffer hiện có
+    fn set_mean(&mut self, sum: &[f64], size: usize) {
+        for (value, total) in self.values.iter_mut().zip(sum) {
+            *value = total / size as f64;
+        }
+        self.norm = self.values.iter().map(|v| v * v).sum::<f64>().sqrt();
+    }
+}
+
+// Khoảng cách cosine giữa vector thưa (chuẩn `norm`) và tâm cụm
+fn centroid_distance(vector: &SparseVector, norm: f64, centroid: &Centroid) -> f64 {
+    let norm = norm * centroid.norm;
+    if norm == 0.0 {
+        return 1.0;
+    }
+    let dot: f64 = vector
+        .iter()
+        .map(|(id, value)| value * centroid.values[id as usize])
+        .sum();
+    1.0 - dot / norm
+}
+
+// Phân cụm k-means theo khoảng cách cosine, khởi tạo bằng k-means++
+#[derive(Debug, Clone, Copy)]
+struct KMeans {
+    k: usize,
+    max_iterations: usize,
+    // Số lần chạy với các tâm khởi tạo khác nhau, giữ lần có tổng khoảng cách nhỏ nhất
+    restarts: usize,
+    seed: u64,
+}
+
+impl KMeans {
+    fn new(k: usize) -> Self {
+        KMeans {
+            k,
+            max_iterations: 100,
+            restarts: 10,
+            seed: 42,
+        }
+    }
+
+    fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations.max(1);
+        self
+    }
+
+    fn with_restarts(mut self, restarts: usize) -> Self {
+        self.restarts = restarts.max(1);
+        self
+    }
+
+    fn fit(&self, vectors: &[SparseVector], dimension: usize) -> Clusters {
+        if vectors.is_empty() {
+            return Clusters {
+                labels: Vec::new(),
+                centroids: Vec::new(),
+            };
+        }
+        let mut rng = SplitMix(self.seed);
+        let norms: Vec<f64> = vectors.iter().map(SparseVector::norm).collect();
+        let mut best: Option<(f64, Vec<usize>)> = None;
+        for _ in 0..self.restarts.max(1) {
+            let (labels, centroids) = self.run(vectors, &norms, dimension, &mut rng);
+            let inertia: f64 = (0..vectors.len())
+                .map(|i| centroid_distance(&vectors[i], norms[i], &centroids[labels[i]]))
+                .sum();
+            if best.as_ref().is_none_or(|(lowest, _)| inertia < *lowest) {
+                best = Some((inertia, labels));
+            }
+        }
+        Clusters::from_labels(vectors, &best.unwrap().1, dimension)
+    }
+
+    // Một lần chạy k-means, trả về nhãn và tâm cụm; `norms` là chuẩn của từng vector
+    fn run(
+        &self,
+        vectors: &[SparseVector],
+        norms: &[f64],
+        dimension: usize,
+        rng: &mut SplitMix,
+    ) -> (Vec<usize>, Vec<Centroid>) {
+        let k = self.k.clamp(1, vectors.len());
+        let dense = |vector: &SparseVector| {
+            let mut values = vec![0.0; dimension];
+            for (id, value) in vector.iter() {
+                values[id as usize] = value;
+            }
+            Centroid::new(values)
+        };
+
+        // k-means++: tâm đầu ngẫu nhiên, các tâm sau được chọn với xác suất tỷ lệ
+        // với bình phương khoảng cách tới tâm gần nhất
+        let first = (rng.next() % vectors.len() as u64) as usize;
+        let mut centroids = vec![dense(&vectors[first])];
+        let mut nearest: Vec<f64> = (0..vectors.len())
+            .map(|i| centroid_distance(&vectors[i], norms[i], &centroids[0]).powi(2))
+            .collect();
+        while centroids.len() < k {
+            let total: f64 = nearest.iter().sum();
+            let chosen = if total > 0.0 {
+                let mut target = rng.next_f64() * total;
+                nearest
+                    .iter()
+                    .position(|&d| {
+                        target -= d;
+                        target < 0.0
+                    })
+                    .unwrap_or(vectors.len() - 1)
+            } else {
+                // Mọi văn bản trùng với các tâm đã chọn
+                centroids.len() % vectors.len()
+            };
+            centroids.push(dense(&vectors[chosen]));
+            let last = centroids.last().unwrap();
+            for (i, d) in nearest.iter_mut().enumerate() {
+                *d = d.min(centroid_distance(&vectors[i], norms[i], last).powi(2));
+            }
+        }
+
+        // Buffer tổng của từng cụm được cấp một lần và dùng lại qua các vòng lặp
+        let mut labels = vec![usize::MAX; vectors.len()];
+        let mut sums = vec![vec![0.0; dimension]; centroids.len()];
+        let mut sizes = vec![0usize; centroids.len()];
+        for _ in 0..self.max_iterations {
+            let mut changed = false;
+            for (i, label) in labels.iter_mut().enumerate() {
+                let best = centroids
+                    .iter()
+                    .map(|centroid| centroid_distance(&vectors[i], norms[i], centroid))
+                    .enumerate()
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+                    .unwrap()
+                    .0;
+                changed |= *label != best;
+                *label = best;
+            }
+            if !changed {
+                break;
+            }
+            // Cụm rỗng giữ tâm cũ
+            sums.iter_mut().for_each(|sum| sum.fill(0.0));
+            sizes.fill(0);
+            for (vector, &label) in vectors.iter().zip(&labels) {
+                sizes[label] += 1;
+                for (id, value) in vector.iter() {
+                    sums[label][id as usize] += value;
+                }
+            }
+            for ((centroid, sum), &size) in centroids.iter_mut().zip(&sums).zip(&sizes) {
+                if size > 0 {
+                    centroid.set_mean(sum, size);
+                }
+            }
+        }
+        (labels, centroids)
+    }
+
+    // Thử các giá trị k và chọn k có silhouette cao nhất
+    fn choose_k(
+        &self,
+        vectors: &[SparseVector],
+        dimension: usize,
+        ks: std::ops::RangeInclusive<usize>,
+    ) -> Option<(usize, Clusters)> {
+        ks.filter(|&k| k >= 2 && k < vectors.len())
+            .map(|k| {
+                let clusters = KMeans { k, ..*self }.fit(vectors, dimension);
+                (silhouette(vectors, &clusters.labels), k, clusters)
+            })
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, k, clusters)| (k, clusters))
+    }
+}
+
+// Phân cụm phân cấp gộp dần (average linkage, khoảng cách cosine) cho tới khi còn `k` cụm.
+// Dùng chuỗi láng giềng gần nhất (nearest-neighbor chain) trên ma trận khoảng cách tam giác:
+// O(n²) thời gian và O(n²) bộ nhớ (n(n-1)/2 số f64, khoảng 100MB với 5000 văn bản), nên
+// hợp với vài nghìn văn bản; tập lớn hơn nên dùng `KMeans`.
+fn agglomerative(vectors: &[SparseVector], k: usize, dimension: usize) -> Clusters {
+    let n = vectors.len();
+    // Vị trí của cặp (a, b), a != b, trong ma trận tam giác trên
+    let at = |a: usize, b: usize| {
+        let (i, j) = if a < b { (a, b) } else { (b, a) };
+        i * n - i * (i + 1) / 2 + (j - i - 1)
+    };
+    let mut distance = vec![0.0; n * n.saturating_sub(1) / 2];
+    for i in 0..n {
+        for j in i + 1..n {
+            distance[at(i, j)] = 1.0 - vectors[i].cosine(&vectors[j]);
+        }
+    }
+    let mut sizes = vec![1usize; n];
+    let mut active: Vec<bool> = vec![true; n];
+    // Mỗi lần gộp (khoảng cách, cụm a, cụm b); cụm được đại diện bởi một văn bản của nó
+    let mut merges: Vec<(f64, usize, usize)> = Vec::with_capacity(n.saturating_sub(1));
+    let mut chain: Vec<usize> = Vec::new();
+    while merges.len() + 1 < n {
+        if chain.is_empty() {
+            chain.push(active.iter().position(|&alive| alive).unwrap());
+        }
+        let a = *chain.last().unwrap();
+        // Bằng khoảng cách thì ưu tiên phần tử trước đó trong chuỗi để chuỗi luôn dừng
+        let previous = chain.len().checked_sub(2).map(|i| chain[i]);
+        let mut nearest = previous.map(|p| (p, distance[at(a, p)]));
+        for x in (0..n).filter(|&x| active[x] && x != a) {
+            if nearest.is_none_or(|(_, d)| distance[at(a, x)] < d) {
+                nearest = Some((x, distance[at(a, x)]));
+            }
+        }
+        let (b, d) = nearest.unwrap();
+        if Some(b) != previous {
+            chain.push(b);
+            continue;
+        }
+        chain.truncate(chain.len() - 2);
+        // Cập nhật Lance-Williams: khoảng cách tới cụm gộp là trung bình có trọng số
+        for x in (0..n).filter(|&x| active[x] && x != a && x != b) {
+            distance[at(a, x)] = (sizes[a] as f64 * distance[at(a, x)]
+                + sizes[b] as f64 * distance[at(b, x)])
+                / (sizes[a] + sizes[b]) as f64;
+        }
+        sizes[a] += sizes[b];
+        active[b] = false;
+        merges.push((d, a, b));
+    }
+
+    // Average linkage không tạo đảo ngược khoảng cách, nên giữ n - k lần gộp gần nhất
+    // (sắp xếp ổn định) cho cùng kết quả với gộp tham lam từng cặp gần nhất
+    merges.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn root(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+    for &(_, a, b) in merges.iter().take(n.saturating_sub(k.max(1))) {
+        let (a, b) = (root(&mut parent, a), root(&mut parent, b));
+        parent[b] = a;
+    }
+    let labels: Vec<usize> = (0..n).map(|i| root(&mut parent, i)).collect();
+    Clusters::from_labels(vectors, &labels, dimension)
+}
+
+// Hệ số silhouette trung bình theo khoảng cách cosine, trong [-1, 1], càng cao càng tách biệt.
+// Văn bản nằm một mình trong cụm được tính là 0.
+fn silhouette(vectors: &[SparseVector], labels: &[usize]) -> f64 {
+    if vectors.is_empty() {
+        return 0.0;
+    }
+    let clusters = labels.iter().max().map_or(0, |max| max + 1);
+    let mut total = 0.0;
+    for (i, vector) in vectors.iter().enumerate() {
+        let mut sums = vec![0.0; clusters];
+        let mut counts = vec![0usize; clusters];
+        for (j, other) in vectors.iter().enumerate() {
+            if i != j {
+                sums[labels[j]] += 1.0 - vector.cosine(other);
+                counts[labels[j]] += 1;
+            }
+        }
+        if counts[labels[i]] == 0 {
+            continue;
+        }
+        let a = sums[labels[i]] / counts[labels[i]] as f64;
+        let b = (0..clusters)
+            .filter(|&c| c != labels[i] && counts[c] > 0)
+            .map(|c| sums[c] / counts[c] as f64)
+            .fold(f64::INFINITY, f64::min);
+        if b.is_finite() && a.max(b) > 0.0 {
+            total += (b - a) / a.max(b);
+        }
+    }
+    total / vectors.len() as f64
+}
+
+impl TFIDF {
+    // Vector thưa của các văn bản đã `transform`, dùng làm đầu vào phân cụm
+    fn vectors(&self, documents: &[Document]) -> Vec<SparseVector> {
+        documents
+            .iter()
+            .map(|document| self.vector_of(document))
+            .collect()
+    }
+
+    // Nhãn của cụm: `k` term có trọng số lớn nhất trong tâm cụm
+    fn cluster_keywords(&self, clusters: &Clusters, cluster: usize, k: usize) -> Vec<String> {
+        let mut weights: Vec<(usize, f64)> = clusters.centroids[cluster]
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        weights.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+        weights
+            .into_iter()
+            .take(k)
+            .filter_map(|(id, _)| self.vocabulary.term(id as u32).map(str::to_string))
+            .collect()
+    }
+}
+
+// `Product` dùng chung với chương trình mẫu product-bit.ra
+#[path = "product.rs"]
+mod product;
+
+use product::Product;
+
+impl Product {
+    // Tên được đánh chỉ mục, các thuộc tính còn lại và danh mục là trường lưu trữ dùng để lọc
+    fn to_document(&self, id: &str, category: &str) -> Document {
+        Document::new(&self.name)
+            .with_id(id)
+            .with_stored("category", Value::Text(category.to_string()))
+            .with_stored("price", Value::Number(self.price))
+            .with_stored("available", Value::Flag(self.available))
+    }
+}
+
+impl Value {
+    // So sánh hai giá trị cùng loại; khác loại thì không so sánh được
+    fn compare(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Flag(a), Value::Flag(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Flag(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+// Điều kiện lọc trên trường lưu trữ; văn bản thiếu trường thì không khớp
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Equals(String, Value),
+    // Trường số trong khoảng [min, max], `None` là không giới hạn
+    Range(String, Option<f64>, Option<f64>),
+    // Khớp một trong các giá trị
+    Any(String, Vec<Value>),
+}
+
+impl Filter {
+    fn matches(&self, document: &Document) -> bool {
+        match self {
+            Filter::Equals(field, value) => document.stored.get(field) == Some(value),
+            Filter::Range(field, min, max) => match document.stored.get(field) {
+                Some(Value::Number(number)) => {
+                    min.is_none_or(|min| *number >= min) && max.is_none_or(|max| *number <= max)
+                }
+                _ => false,
+            },
+            Filter::Any(field, values) => document
+                .stored
+                .get(field)
+                .is_some_and(|value| values.contains(value)),
+        }
+    }
+}
+
+// Facet cần đếm trên tập kết quả
+#[derive(Debug, Clone, PartialEq)]
+enum Facet {
+    // Đếm theo từng giá trị của trường
+    Terms(String),
+    // Đếm theo các khoảng giá trị số chia bởi `bounds` (tăng dần): (-∞, b0), [b0, b1), ..., [bn, +∞)
+    Ranges(String, Vec<f64>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FacetCount {
+    value: String,
+    count: usize,
+}
+
+// Thứ tự kết quả
+#[derive(Debug, Clone, PartialEq)]
+enum Sort {
+    Relevance,
+    // Theo trường lưu trữ, văn bản thiếu trường xếp cuối
+    Field { name: String, descending: bool },
+}
+
+// Một yêu cầu tìm kiếm đầy đủ: truy vấn văn bản, bộ lọc, facet và thứ tự
+#[derive(Debug, Clone)]
+struct SearchRequest {
+    // Truy vấn rỗng khớp mọi văn bản (duyệt theo danh mục)
+    query: String,
+    filters: Vec<Filter>,
+    facets: Vec<Facet>,
+    sort: Sort,
+    offset: usize,
+    limit: usize,
+}
+
+impl SearchRequest {
+    fn new(query: &str) -> Self {
+        SearchRequest {
+            query: query.to_string(),
+            filters: Vec::new(),
+            facets: Vec::new(),
+            sort: Sort::Relevance,
+            offset: 0,
+            limit: 10,
+        }
+    }
+
+    fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    fn facet(mut self, facet: Facet) -> Self {
+        self.facets.push(facet);
+        self
+    }
+
+    fn sort(mut self, sort: Sort) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    fn page(mut self, offset: usize, limit: usize) -> Self {
+        self.offset = offset;
+        self.limit = limit;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SearchResponse {
+    hits: Vec<SearchResult>,
+    // Số văn bản khớp truy vấn và bộ lọc, trước khi phân trang
+    total: usize,
+    facets: BTreeMap<String, Vec<FacetCount>>,
+}
+
+impl TFIDF {
+    // Tìm kiếm có lọc: văn bản bị loại trước khi xếp hạng, facet được đếm trên toàn bộ
+    // tập đã lọc, sau đó chỉ giữ `offset + limit` kết quả đầu bằng heap để phân trang
+    fn search_with(&self, request: &SearchRequest) -> SearchResponse {
+        let allowed = |doc: usize| {
+            self.get(doc)
+                .is_some_and(|document| request.filters.iter().all(|f| f.matches(document)))
+        };
+        let hits: Vec<Hit> = if self.tokenize(&request.query).is_empty() {
+            (0..self.index.slots())
+                .filter(|&doc| self.index.is_live(doc) && allowed(doc))
+                .map(|doc| Hit { doc, score: 0.0 })
+                .collect()
+        } else {
+            self.scores(&request.query)
+                .into_iter()
+                .filter(|&(doc, _)| allowed(doc))
+                .map(|(doc, score)| Hit { doc, score })
+                .collect()
+        };
+
+        respond(
+            request,
+            hits,
+            |doc| self.get(doc),
+            |hit| self.result(&request.query, hit),
+        )
+    }
+}
+
+// Phần chung của `search_with` trên `TFIDF` và `Searcher`: đếm facet trên toàn bộ tập đã
+// lọc, sắp xếp rồi chỉ giữ `offset + limit` kết quả đầu bằng heap để phân trang
+fn respond<'d>(
+    request: &SearchRequest,
+    hits: Vec<Hit>,
+    get: impl Fn(usize) -> Option<&'d Document>,
+    result: impl FnMut(Hit) -> Option<SearchResult>,
+) -> SearchResponse {
+    let mut facets = BTreeMap::new();
+    for facet in &request.facets {
+        let (name, counts) = facet_counts(facet, hits.iter().filter_map(|hit| get(hit.doc)));
+        facets.insert(name.to_string(), counts);
+    }
+
+    let total = hits.len();
+    let k = request.offset.saturating_add(request.limit);
+    let hits = match &request.sort {
+        Sort::Relevance => first_k(hits, k, by_relevance),
+        Sort::Field { name, descending } => {
+            let value = |hit: &Hit| get(hit.doc).and_then(|d| d.stored.get(name));
+            first_k(hits, k, |a, b| {
+                let order = match (value(a), value(b)) {
+                    (Some(x), Some(y)) => {
+                        let order = x.compare(y).unwrap_or(std::cmp::Ordering::Equal);
+                        if *descending {
+                            order.reverse()
+                        } else {
+                            order
+                        }
+                    }
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => std::cmp::Ordering::Equal,
+                };
+                order.then_with(|| by_relevance(a, b))
+            })
+        }
+    };
+    SearchResponse {
+        hits: hits
+            .into_iter()
+            .skip(request.offset)
+            .filter_map(result)
+            .collect(),
+        total,
+        facets,
+    }
+}
+
+// Đếm giá trị trường lưu trữ của các văn bản, theo từng giá trị hoặc theo khoảng số
+fn facet_counts<'a, 'd>(
+    facet: &'a Facet,
+    values: impl Iterator<Item = &'d Document>,
+) -> (&'a str, Vec<FacetCount>) {
+    match facet {
+        Facet::Terms(field) => {
+            let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+            for value in values.filter_map(|document| document.stored.get(field)) {
+                *counts.entry(value.to_string()).or_insert(0) += 1;
+            }
+            let mut counts: Vec<FacetCount> = counts
+                .into_iter()
+                .map(|(value, count)| FacetCount { value, count })
+                .collect();
+            // Nhiều nhất trước, cùng số lượng thì theo giá trị
+            counts.sort_by(|a, b| b.count.cmp(&a.count).then(a.value.cmp(&b.value)));
+            (field, counts)
+        }
+        Facet::Ranges(field, bounds) => {
+            let mut counts = vec![0; bounds.len() + 1];
+            for document in values {
+                if let Some(Value::Number(number)) = document.stored.get(field) {
+                    counts[bounds.partition_point(|bound| bound <= number)] += 1;
+                }
+            }
+            let label = |i: usize| {
+                let low = i
+                    .checked_sub(1)
+                    .map_or("*".to_string(), |i| bounds[i].to_string());
+                let high = bounds.get(i).map_or("*".to_string(), f64::to_string);
+                format!("{}-{}", low, high)
+            };
+            let counts = counts
+                .into_iter()
+                .enumerate()
+                .map(|(i, count)| FacetCount {
+                    value: label(i),
+                    count,
+                })
+                .collect();
+            (field, counts)
+        }
+    }
+}
+
+// Một gợi ý hoàn thành cùng trọng số của nó
+#[derive(Debug, Clone, PartialEq)]
+struct Suggestion {
+    text: String,
+    weight: f64,
+}
+
+// Nút của cây tiền tố; `top` giữ sẵn các gợi ý nặng nhất của cả nhánh nên tra cứu
+// chỉ tốn thời gian theo độ dài tiền tố, không phụ thuộc số mục trong cây
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: BTreeMap<char, usize>,
+    top: Vec<Suggestion>,
+}
+
+// Bộ gợi ý cho ô tìm kiếm, dựng từ từ vựng (trọng số theo DF) và các truy vấn đã gặp.
+// Khóa được bỏ dấu và viết thường nên gõ "ao" cũng gợi ý được "áo".
+#[derive(Debug, Clone)]
+struct Suggester {
+    nodes: Vec<TrieNode>,
+    weights: HashMap<String, f64>,
+    // Số gợi ý giữ ở mỗi nút, cũng là số gợi ý tối đa trả về
+    size: usize,
+    // Trọng số cộng thêm mỗi lần một truy vấn được ghi nhận
+    query_weight: f64,
+}
+
+impl Suggester {
+    fn new(size: usize) -> Self {
+        Suggester {
+            nodes: vec![TrieNode::default()],
+            weights: HashMap::new(),
+            size: size.max(1),
+            query_weight: 1.0,
+        }
+    }
+
+    fn with_query_weight(mut self, query_weight: f64) -> Self {
+        self.query_weight = query_weight;
+        self
+    }
+
+    fn key(text: &str) -> String {
+        fold(text).to_lowercase()
+    }
+
+    // Cộng thêm trọng số cho một mục. Trọng số chỉ tăng nên mỗi nút trên đường đi
+    // chỉ cần cập nhật danh sách `top` của nó.
+    fn insert(&mut self, text: &str, weight: f64) {
+        let text = text.trim().to_lowercase();
+        if text.is_empty() {
+            return;
+        }
+        let total = *self
+            .weights
+            .entry(text.clone())
+            .and_modify(|w| *w += weight)
+            .or_insert(weight);
+        let suggestion = Suggestion {
+            text: text.clone(),
+            weight: total,
+        };
+        let mut node = 0;
+        self.update(node, &suggestion);
+        for c in Suggester::key(&text).chars() {
+            node = match self.nodes[node].children.get(&c) {
+                Some(&child) => child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(c, child);
+                    child
+                }
+            };
+            self.update(node, &suggestion);
+        }
+    }
+
+    fn update(&mut self, node: usize, suggestion: &Suggestion) {
+        let top = &mut self.nodes[node].top;
+        match top.iter_mut().find(|s| s.text == suggestion.text) {
+            Some(existing) => existing.weight = suggestion.weight,
+            None => top.push(suggestion.clone()),
+        }
+        top.sort_by(|a, b| {
+            b.weight
+                .partial_cmp(&a.weight)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.text.cmp(&b.text))
+        });
+        top.truncate(self.size);
+    }
+
+    // Ghi nhận một truy vấn người dùng đã tìm để gợi ý lại cho người sau
+    fn record_query(&mut self, query: &str) {
+        self.insert(query, self.query_weight);
+    }
+
+    // Tối đa `n` gợi ý bắt đầu bằng `prefix`, nặng nhất trước
+    fn complete(&self, prefix: &str, n: usize) -> Vec<Suggestion> {
+        let mut node = 0;
+        for c in Suggester::key(prefix.trim_start()).chars() {
+            match self.nodes[node].children.get(&c) {
+                Some(&child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+        self.nodes[node].top.iter().take(n).cloned().collect()
+    }
+}
+
+impl TFIDF {
+    // Bộ gợi ý gồm mọi term của từ vựng, trọng số là số văn bản chứa term
+    fn suggester(&self, size: usize) -> Suggester {
+        let mut suggester = Suggester::new(size);
+        for term in self.index.term_list() {
+            suggester.insert(term, self.index.document_frequency(term) as f64);
+        }
+        suggester
+    }
+
+    // Sửa lỗi chính tả: term không có trong từ vựng được thay bằng term gần nhất,
+    // cùng khoảng cách thì chọn term phổ biến hơn (IDF thấp hơn). Trả về `None` nếu
+    // không có gì để sửa.
+    fn did_you_mean(&self, query: &str) -> Option<String> {
+        let mut changed = false;
+        let words: Vec<String> = self
+            .tokenize(query)
+            .into_iter()
+            .map(|token| {
+                if self.idf_scores.contains_key(&token.text) {
+                    return token.text;
+                }
+                let best = self
+                    .lexicon
+                    .fuzzy(&token.text, self.fuzzy.max_distance)
+                    .into_iter()
+                    .min_by(|a, b| {
+                        a.1.cmp(&b.1)
+                            .then(self.idf_scores[a.0].total_cmp(&self.idf_scores[b.0]))
+                            .then(a.0.cmp(b.0))
+                    });
+                match best {
+                    Some((term, _)) => {
+                        changed = true;
+                        term.to_string()
+                    }
+                    None => token.text,
+                }
+            })
+            .collect();
+        changed.then(|| words.join(" "))
+    }
+}
+
+// Cấu hình phân tích và xếp hạng của chỉ mục segment, dùng chung giữa writer, luồng gộp
+// và các searcher. Bộ phân tích không được lưu cùng chỉ mục nên phải đặt lại khi mở.
+#[derive(Clone)]
+struct Schema {
+    analyzer: Arc<dyn Analyzer>,
+    // Bộ phân tích riêng của từng trường, trường không có dùng `analyzer`
+    field_analyzers: HashMap<String, Arc<dyn Analyzer>>,
+    // Hệ số nhân điểm theo trường (mặc định 1)
+    boosts: HashMap<String, f64>,
+    // Tham số BM25
+    k1: f64,
+    b: f64,
+    highlighter: Highlighter,
+    fuzzy: Fuzzy,
+}
+
+impl Schema {
+    fn new(analyzer: impl Analyzer + 'static) -> Self {
+        Schema {
+            analyzer: Arc::new(analyzer),
+            field_analyzers: HashMap::new(),
+            boosts: HashMap::new(),
+            k1: 1.2,
+            b: 0.75,
+            highlighter: Highlighter::new(),
+            fuzzy: Fuzzy::new(2),
+        }
+    }
+
+    fn analyzer(&self, field: &str) -> &dyn Analyzer {
+        self.field_analyzers
+            .get(field)
+            .unwrap_or(&self.analyzer)
+            .as_ref()
+    }
+
+    fn boost(&self, field: &str) -> f64 {
+        self.boosts.get(field).copied().unwrap_or(1.0)
+    }
+}
+
+// Một segment bất biến: chỉ mục từng trường của một nhóm văn bản, doc id cục bộ bắt đầu
+// từ 0. Segment không bao giờ bị sửa sau khi ghi; xóa văn bản được ghi vào `Tombstones`.
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    // Chỉ mục theo tên trường, gồm cả `content`
+    indexes: BTreeMap<String, Index>,
+    // Từ điển của từng trường cho tìm kiếm gần đúng và theo tiền tố
+    lexicons: BTreeMap<String, Lexicon>,
+    // Theo trường: số văn bản có trường đó và tổng độ dài, tính một lần khi tạo segment
+    totals: BTreeMap<String, (usize, usize)>,
+    documents: DocStore,
+    ids: HashMap<String, usize>,
+}
+
+impl Segment {
+    fn new(id: u64, indexes: BTreeMap<String, Index>, documents: DocStore) -> Self {
+        let ids = (0..documents.len())
+            .filter_map(|doc| Some((documents.id(doc)?, doc)))
+            .filter(|(id, _)| !id.is_empty())
+            .map(|(id, doc)| (id.to_string(), doc))
+            .collect();
+        let lexicons = indexes
+            .iter()
+            .map(|(name, index)| {
+                let mut lexicon = Lexicon::default();
+                for term in index.term_list() {
+                    lexicon.insert(term);
+                }
+                (name.clone(), lexicon)
+            })
+            .collect();
+        let totals = indexes
+            .iter()
+            .map(|(name, index)| (name.clone(), (index.doc_count, index.lengths.iter().sum())))
+            .collect();
+        Segment {
+            id,
+            indexes,
+            lexicons,
+            totals,
+            documents,
+            ids,
+        }
+    }
+
+    fn build(id: u64, documents: Vec<Document>, schema: &Schema) -> Self {
+        let mut indexes: BTreeMap<String, Index> = BTreeMap::new();
+        for (doc, document) in documents.iter().enumerate() {
+            indexes.entry(DEFAULT_FIELD.to_string()).or_default().add(
+                doc,
+                &schema.analyzer(DEFAULT_FIELD).analyze(&document.content),
+            );
+            for (name, text) in &document.fields {
+                indexes
+                    .entry(name.clone())
+                    .or_default()
+                    .add(doc, &schema.analyzer(name).analyze(text));
+            }
+        }
+        Segment::new(id, indexes, documents.into())
+    }
+
+    // Gộp các segment thành một, bỏ văn bản đã xóa. Postings được chép thẳng từ segment
+    // nguồn nên không phải phân tích lại văn bản. Trả về kèm (segment nguồn, doc id cũ)
+    // của từng văn bản trong segment mới.
+    fn merge(id: u64, sources: &[SegmentReader]) -> (Segment, Vec<(u64, usize)>) {
+        let mut indexes: BTreeMap<String, Index> = BTreeMap::new();
+        let mut documents = Vec::new();
+        let mut moved = Vec::new();
+        for reader in sources {
+            let base = documents.len();
+            let live: Vec<usize> = (0..reader.segment.documents.len())
+                .filter(|&doc| reader.is_live(doc))
+                .collect();
+            for (name, index) in &reader.segment.indexes {
+                indexes
+                    .entry(name.clone())
+                    .or_default()
+                    .extend_from(index, &live, base);
+            }
+            for &doc in &live {
+                documents.push(reader.segment.documents[doc].clone());
+                moved.push((reader.segment.id, doc));
+            }
+        }
+        (Segment::new(id, indexes, documents.into()), moved)
+    }
+}
+
+// Bitset các văn bản đã xóa của một segment
+#[derive(Debug, Clone, Default)]
+struct Tombstones {
+    words: Vec<u64>,
+    count: usize,
+    // Theo trường: số văn bản đã xóa có trường đó và tổng độ dài của chúng, để snapshot
+    // trừ khỏi `Segment::totals` thay vì đếm lại mọi văn bản
+    removed: BTreeMap<String, (usize, usize)>,
+}
+
+impl Tombstones {
+    fn insert(&mut self, doc: usize) -> bool {
+        if doc / 64 >= self.words.len() {
+            self.words.resize(doc / 64 + 1, 0);
+        }
+        let bit = 1 << (doc % 64);
+        let fresh = self.words[doc / 64] & bit == 0;
+        self.words[doc / 64] |= bit;
+        self.count += fresh as usize;
+        fresh
+    }
+
+    fn contains(&self, doc: usize) -> bool {
+        self.words
+            .get(doc / 64)
+            .is_some_and(|word| word & (1 << (doc % 64)) != 0)
+    }
+}
+
+// Segment cùng tập văn bản đã xóa tại một thời điểm. Xóa thêm sẽ tạo `Tombstones` mới
+// (copy-on-write) nên các snapshot cũ không bị ảnh hưởng.
+#[derive(Debug, Clone)]
+struct SegmentReader {
+    segment: Arc<Segment>,
+    deleted: Arc<Tombstones>,
+}
+
+impl SegmentReader {
+    fn new(segment: Segment) -> Self {
+        SegmentReader {
+            segment: Arc::new(segment),
+            deleted: Arc::default(),
+        }
+    }
+
+    fn is_live(&self, doc: usize) -> bool {
+        !self.deleted.contains(doc)
+    }
+
+    fn live_count(&self) -> usize {
+        self.segment.documents.len() - self.deleted.count
+    }
+
+    // Đánh dấu xóa văn bản, trả về false nếu nó không tồn tại hoặc đã bị xóa
+    fn delete(&mut self, doc: usize) -> bool {
+        if doc >= self.segment.documents.len() || !self.is_live(doc) {
+            return false;
+        }
+        let deleted = Arc::make_mut(&mut self.deleted);
+        deleted.insert(doc);
+        for (name, index) in &self.segment.indexes {
+            if index.is_live(doc) {
+                let (docs, total) = deleted.removed.entry(name.clone()).or_default();
+                *docs += 1;
+                *total += index.lengths[doc];
+            }
+        }
+        true
+    }
+
+    // Văn bản còn sống có mã định danh `id`
+    fn find(&self, id: &str) -> Option<usize> {
+        let &doc = self.segment.ids.get(id)?;
+        self.is_live(doc).then_some(doc)
+    }
+}
+
+// Danh sách segment nhất quán mà một Searcher đọc, kèm thống kê toàn cục cho BM25
+#[derive(Debug, Clone, Default)]
+struct Snapshot {
+    generation: u64,
+    segments: Vec<SegmentReader>,
+    // Doc id toàn cục của văn bản đầu tiên trong mỗi segment
+    bases: Vec<usize>,
+    live_docs: usize,
+    // Theo trường: số văn bản còn sống có trường đó và tổng độ dài của chúng
+    lengths: BTreeMap<String, (usize, usize)>,
+}
+
+impl Snapshot {
+    fn new(generation: u64, segments: Vec<SegmentReader>) -> Self {
+        let mut bases = Vec::with_capacity(segments.len());
+        let mut slots = 0;
+        let mut live_docs = 0;
+        let mut lengths: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for reader in &segments {
+            bases.push(slots);
+            slots += reader.segment.documents.len();
+            live_docs += reader.live_count();
+            for (name, &(docs, total)) in &reader.segment.totals {
+                let (removed_docs, removed_total) = reader
+                    .deleted
+                    .removed
+                    .get(name)
+                    .copied()
+                    .unwrap_or_default();
+                let (all_docs, all_total) = lengths.entry(name.clone()).or_default();
+                *all_docs += docs - removed_docs;
+                *all_total += total - removed_total;
+            }
+        }
+        // Trường mà mọi văn bản đã bị xóa coi như không tồn tại
+        lengths.retain(|_, &mut (docs, _)| docs > 0);
+        Snapshot {
+            generation,
+            segments,
+            bases,
+            live_docs,
+            lengths,
+        }
+    }
+
+    // Vị trí segment và doc id cục bộ của doc id toàn cục
+    fn locate(&self, doc: usize) -> Option<(usize, usize)> {
+        let at = self
+            .bases
+            .partition_point(|&base| base <= doc)
+            .checked_sub(1)?;
+        let local = doc - self.bases[at];
+        (local < self.segments[at].segment.documents.len()).then_some((at, local))
+    }
+}
+
+// Chính sách gộp theo tầng: segment cùng bậc kích thước (log cơ số `merge_factor` của số
+// văn bản còn sống) được gộp khi đủ `merge_factor` segment; segment có tỷ lệ văn bản đã
+// xóa vượt `max_deleted_ratio` được viết lại một mình để thu hồi chỗ.
+#[derive(Debug, Clone, Copy)]
+struct MergePolicy {
+    merge_factor: usize,
+    max_deleted_ratio: f64,
+}
+
+impl MergePolicy {
+    fn new(merge_factor: usize) -> Self {
+        MergePolicy {
+            merge_factor: merge_factor.max(2),
+            max_deleted_ratio: 0.3,
+        }
+    }
+
+    fn with_max_deleted_ratio(mut self, max_deleted_ratio: f64) -> Self {
+        self.max_deleted_ratio = max_deleted_ratio;
+        self
+    }
+
+    // Id các segment nên gộp, hoặc `None` nếu chưa cần
+    fn select(&self, segments: &[SegmentReader]) -> Option<Vec<u64>> {
+        let mut tiers: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+        for reader in segments {
+            let size = reader.segment.documents.len();
+            if size > 0 && reader.deleted.count as f64 / size as f64 > self.max_deleted_ratio {
+                return Some(vec![reader.segment.id]);
+            }
+            let tier = reader.live_count().max(1).ilog(self.merge_factor);
+            tiers.entry(tier).or_default().push(reader.segment.id);
+        }
+        tiers
+            .into_values()
+            .find(|ids| ids.len() >= self.merge_factor)
+            .map(|ids| ids.into_iter().take(self.merge_factor).collect())
+    }
+}
+
+// Trạng thái dùng chung giữa writer, các luồng gộp và searcher
+#[derive(Default)]
+struct Shared {
+    current: Mutex<Arc<Snapshot>>,
+    next_segment: AtomicU64,
+    // Chỉ một lần gộp chạy tại một thời điểm
+    merging: AtomicBool,
+}
+
+impl Shared {
+    // Áp dụng thay đổi lên danh sách segment hiện tại; snapshot mới chỉ được công bố khi
+    // `change` trả về true, tức là thật sự có gì thay đổi
+    fn publish(&self, change: impl FnOnce(&mut Vec<SegmentReader>) -> bool) -> bool {
+        let mut current = self.current.lock().unwrap();
+        let mut segments = current.segments.clone();
+        let changed = change(&mut segments);
+        if changed {
+            *current = Arc::new(Snapshot::new(current.generation + 1, segments));
+        }
+        changed
+    }
+}
+
+// Đặt lại cờ `merging` khi luồng gộp kết thúc, kể cả khi nó panic
+struct MergeGuard(Arc<Shared>);
+
+impl Drop for MergeGuard {
+    fn drop(&mut self) {
+        self.0.merging.store(false, Ordering::SeqCst);
+    }
+}
+
+// Ghi chỉ mục dạng segment theo kiểu Lucene, thay cho mô hình `TFIDF` + `Vec<Document>`
+// trong bộ nhớ: văn bản được gom trong bộ đệm rồi ghi thành segment bất biến khi `commit`
+// hoặc khi bộ đệm đầy; xóa và cập nhật được ghi nhận ở writer rồi áp dụng cùng lúc khi
+// commit. `Searcher` mở từ writer có các chức năng tìm kiếm của `TFIDF` (theo trường, gần
+// đúng, Boolean, lọc, facet, sắp xếp, đoạn trích), chấm điểm bằng BM25.
+struct IndexWriter {
+    shared: Arc<Shared>,
+    schema: Arc<Schema>,
+    // Văn bản chưa commit; văn bản bị xóa hoặc thay thế trong bộ đệm để lại `None`
+    buffer: Vec<Option<Document>>,
+    // Vị trí trong bộ đệm theo mã định danh
+    buffered: HashMap<String, usize>,
+    // Mã định danh sẽ bị xóa khỏi các segment đã commit ở lần commit tới
+    deletes: HashSet<String>,
+    max_buffered: usize,
+    policy: MergePolicy,
+}
+
+impl IndexWriter {
+    fn new(analyzer: impl Analyzer + 'static) -> Self {
+        IndexWriter {
+            shared: Arc::default(),
+            schema: Arc::new(Schema::new(analyzer)),
+            buffer: Vec::new(),
+            buffered: HashMap::new(),
+            deletes: HashSet::new(),
+            max_buffered: 1000,
+            policy: MergePolicy::new(10),
+        }
+    }
+
+    fn with_max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered.max(1);
+        self
+    }
+
+    fn with_merge_policy(mut self, policy: MergePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn with_bm25(mut self, k1: f64, b: f64) -> Self {
+        let schema = Arc::make_mut(&mut self.schema);
+        schema.k1 = k1;
+        schema.b = b;
+        self
+    }
+
+    // Bộ phân tích riêng cho một trường; chỉ áp dụng cho segment ghi sau đó
+    fn with_field_analyzer(mut self, field: &str, analyzer: impl Analyzer + 'static) -> Self {
+        Arc::make_mut(&mut self.schema)
+            .field_analyzers
+            .insert(field.to_string(), Arc::new(analyzer));
+        self
+    }
+
+    fn with_boost(mut self, field: &str, boost: f64) -> Self {
+        Arc::make_mut(&mut self.schema)
+            .boosts
+            .insert(field.to_lowercase(), boost);
+        self
+    }
+
+    fn with_highlighter(mut self, highlighter: Highlighter) -> Self {
+        Arc::make_mut(&mut self.schema).highlighter = highlighter;
+        self
+    }
+
+    fn with_fuzzy(mut self, fuzzy: Fuzzy) -> Self {
+        Arc::make_mut(&mut self.schema).fuzzy = fuzzy;
+        self
+    }
+
+    // Thêm văn bản; văn bản cũ cùng mã định danh bị xóa (tức là cập nhật)
+    fn add(&mut self, document: Document) {
+        if !document.id.is_empty() {
+            self.delete(&document.id);
+            self.buffered.insert(document.id.clone(), self.buffer.len());
+        }
+        self.buffer.push(Some(document));
+        if self.buffer.len() >= self.max_buffered {
+            self.commit();
+        }
+    }
+
+    // Xóa văn bản theo mã định danh, kể cả văn bản còn trong bộ đệm. Văn bản đã commit
+    // vẫn được tìm thấy cho tới lần `commit` tiếp theo.
+    fn delete(&mut self, id: &str) -> bool {
+        let buffered = match self.buffered.remove(id) {
+            Some(at) => self.buffer[at].take().is_some(),
+            None => false,
+        };
+        let committed = !self.deletes.contains(id)
+            && self
+                .shared
+                .current
+                .lock()
+                .unwrap()
+                .segments
+                .iter()
+                .any(|reader| reader.find(id).is_some());
+        self.deletes.insert(id.to_string());
+        buffered || committed
+    }
+
+    // Áp dụng các lần xóa đang chờ và ghi bộ đệm thành một segment mới trong cùng một
+    // snapshot, công bố cho các searcher mở sau đó
+    fn commit(&mut self) {
+        let documents: Vec<Document> = self.buffer.drain(..).flatten().collect();
+        self.buffered.clear();
+        let deletes = std::mem::take(&mut self.deletes);
+        let segment = (!documents.is_empty()).then(|| {
+            let id = self.shared.next_segment.fetch_add(1, Ordering::SeqCst);
+            Segment::build(id, documents, &self.schema)
+        });
+        self.shared.publish(|segments| {
+            let mut changed = false;
+            for id in &deletes {
+                for reader in segments.iter_mut() {
+                    if let Some(doc) = reader.find(id) {
+                        changed |= reader.delete(doc);
+                    }
+                }
+            }
+            if let Some(segment) = segment {
+                segments.push(SegmentReader::new(segment));
+                changed = true;
+            }
+            changed
+        });
+    }
+
+    // Searcher đọc snapshot hiện tại; các thay đổi sau đó không ảnh hưởng tới nó
+    fn searcher(&self) -> Searcher {
+        Searcher {
+            snapshot: self.shared.current.lock().unwrap().clone(),
+            schema: self.schema.clone(),
+        }
+    }
+
+    // Gộp segment ở luồng nền nếu chính sách gộp chọn được; `None` nếu không cần gộp
+    // hoặc đang có lần gộp khác
+    fn maybe_merge(&self) -> Option<std::thread::JoinHandle<()>> {
+        let selected = self
+            .policy
+            .select(&self.shared.current.lock().unwrap().segments)?;
+        if self.shared.merging.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let guard = MergeGuard(self.shared.clone());
+        Some(std::thread::spawn(move || {
+            IndexWriter::merge(&guard.0, &selected);
+        }))
+    }
+
+    // Gộp các segment `selected` thành một segment mới, bỏ các văn bản đã xóa. Văn bản bị
+    // xóa trong lúc gộp được ghi lại vào tombstones của segment mới trước khi công bố.
+    fn merge(shared: &Shared, selected: &[u64]) {
+        let sources: Vec<SegmentReader> = shared
+            .current
+            .lock()
+            .unwrap()
+            .segments
+            .iter()
+            .filter(|reader| selected.contains(&reader.segment.id))
+            .cloned()
+            .collect();
+        let id = shared.next_segment.fetch_add(1, Ordering::SeqCst);
+        let (merged, moved) = Segment::merge(id, &sources);
+
+        shared.publish(|segments| {
+            let mut reader = SegmentReader::new(merged);
+            let current: HashMap<u64, &SegmentReader> =
+                segments.iter().map(|r| (r.segment.id, r)).collect();
+            for (new, (segment, doc)) in moved.iter().enumerate() {
+                if current.get(segment).is_some_and(|r| !r.is_live(*doc)) {
+                    reader.delete(new);
+                }
+            }
+            let at = segments
+                .iter()
+                .position(|r| selected.contains(&r.segment.id))
+                .unwrap_or(segments.len());
+            segments.retain(|r| !selected.contains(&r.segment.id));
+            if !reader.segment.documents.is_empty() {
+                segments.insert(at.min(segments.len()), reader);
+            }
+            true
+        });
+    }
+}
+
+// Tìm kiếm trên một snapshot cố định. Doc id trong `Hit` là doc id toàn cục (doc id cục bộ
+// cộng vị trí đầu segment trong snapshot) nên chỉ có nghĩa với searcher đã trả về nó.
+// DF và độ dài trung bình của BM25 được tính trên mọi segment, chỉ đếm văn bản còn sống.
+struct Searcher {
+    snapshot: Arc<Snapshot>,
+    schema: Arc<Schema>,
+}
+
+impl Searcher {
+    fn doc_count(&self) -> usize {
+        self.snapshot.live_docs
+    }
+
+    // Văn bản còn sống theo doc id toàn cục
+    fn document(&self, doc: usize) -> Option<&Document> {
+        let (at, local) = self.snapshot.locate(doc)?;
+        let reader = &self.snapshot.segments[at];
+        reader
+            .is_live(local)
+            .then(|| &reader.segment.documents[local])
+    }
+
+    fn get(&self, id: &str) -> Option<&Document> {
+        self.snapshot.segments.iter().find_map(|reader| {
+            let doc = reader.find(id)?;
+            Some(&reader.segment.documents[doc])
+        })
+    }
+
+    fn terms(&self, field: &str, text: &str) -> Vec<String> {
+        self.schema
+            .analyzer(field)
+            .analyze(text)
+            .into_iter()
+            .map(|t| t.text)
+            .collect()
+    }
+
+    // Postings của văn bản còn sống trên mọi segment, kèm doc id toàn cục và độ dài trường
+    fn postings<'a>(
+        &'a self,
+        field: &'a str,
+        term: &'a str,
+    ) -> impl Iterator<Item = (usize, usize, &'a Posting)> + 'a {
+        self.snapshot
+            .segments
+            .iter()
+            .zip(&self.snapshot.bases)
+            .filter_map(move |(reader, &base)| {
+                let index = reader.segment.indexes.get(field)?;
+                Some(
+                    index
+                        .postings(term)
+                        .iter()
+                        .filter(move |p| reader.is_live(p.doc))
+                        .map(move |p| (base + p.doc, index.lengths[p.doc], p)),
+                )
+            })
+            .flatten()
+    }
+
+    fn document_frequency(&self, field: &str, term: &str) -> usize {
+        self.postings(field, term).count()
+    }
+
+    // Cộng điểm BM25 của một trường (đã nhân boost) vào `scores`; `factors` giảm điểm
+    // của các term mở rộng gần đúng, term không có trong đó giữ nguyên
+    fn score_field(
+        &self,
+        field: &str,
+        terms: &[String],
+        factors: &HashMap<String, f64>,
+        scores: &mut HashMap<usize, f64>,
+    ) {
+        let Some(&(docs, total)) = self.snapshot.lengths.get(field) else {
+            return;
+        };
+        let (k1, b) = (self.schema.k1, self.schema.b);
+        let n = self.snapshot.live_docs as f64;
+        let average = total as f64 / docs as f64;
+        let boost = self.schema.boost(field);
+        let mut terms: Vec<&String> = terms.iter().collect();
+        terms.sort();
+        terms.dedup();
+        for term in terms {
+            let df = self.document_frequency(field, term) as f64;
+            let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+            let factor = factors.get(term).copied().unwrap_or(1.0);
+            for (doc, length, posting) in self.postings(field, term) {
+                let tf = posting.tf as f64;
+                let denominator = tf + k1 * (1.0 - b + b * length as f64 / average);
+                *scores.entry(doc).or_insert(0.0) +=
+                    boost * factor * idf * tf * (k1 + 1.0) / denominator;
+            }
+        }
+    }
+
+    fn scores(&self, query: &str) -> HashMap<usize, f64> {
+        let mut scores = HashMap::new();
+        for field in self.snapshot.lengths.keys() {
+            self.score_field(
+                field,
+                &self.terms(field, query),
+                &HashMap::new(),
+                &mut scores,
+            );
+        }
+        scores
+    }
+
+    // Tìm trên mọi trường, mỗi trường dùng bộ phân tích và boost của nó
+    fn search(&self, query: &str, k: usize) -> Vec<Hit> {
+        top_k(self.scores(query), k)
+    }
+
+    // Tìm trong một trường; trường không có văn bản nào cho kết quả rỗng
+    fn search_field(&self, field: &str, query: &str, k: usize) -> Vec<Hit> {
+        let mut scores = HashMap::new();
+        self.score_field(
+            field,
+            &self.terms(field, query),
+            &HashMap::new(),
+            &mut scores,
+        );
+        top_k(scores, k)
+    }
+
+    // Như `TFIDF::search_fuzzy`, term được mở rộng theo từ điển của mọi segment
+    fn search_fuzzy(&self, query: &str, k: usize) -> Vec<Hit> {
+        self.search_expanded(query, k, false)
+    }
+
+    fn search_prefix(&self, query: &str, k: usize) -> Vec<Hit> {
+        self.search_expanded(query, k, true)
+    }
+
+    fn search_expanded(&self, query: &str, k: usize, prefix: bool) -> Vec<Hit> {
+        let mut scores = HashMap::new();
+        for field in self.snapshot.lengths.keys() {
+            let terms = self.terms(field, query);
+            let mut expanded = Vec::new();
+            let mut factors: HashMap<String, f64> = HashMap::new();
+            for (i, term) in terms.iter().enumerate() {
+                let last = prefix && i + 1 == terms.len();
+                for (term, factor) in self.expand(field, term, last) {
+                    let best = factors.entry(term.clone()).or_insert(0.0);
+                    *best = best.max(factor);
+                    expanded.push(term);
+                }
+            }
+            self.score_field(field, &expanded, &factors, &mut scores);
+        }
+        top_k(scores, k)
+    }
+
+    // Các term của trường thay cho `term`, kèm hệ số nhân điểm (1 nếu khớp chính xác).
+    // Term chỉ còn trong văn bản đã xóa bị bỏ qua.
+    fn expand(&self, field: &str, term: &str, prefix: bool) -> Vec<(String, f64)> {
+        let fuzzy = self.schema.fuzzy;
+        let mut expansions: HashMap<&str, f64> = HashMap::new();
+        for reader in &self.snapshot.segments {
+            let Some(lexicon) = reader.segment.lexicons.get(field) else {
+                continue;
+            };
+            for (found, distance) in lexicon.fuzzy(term, fuzzy.distance_for(term)) {
+                expansions.insert(found, fuzzy.penalty.powi(distance as i32));
+            }
+            if prefix {
+                for found in lexicon.prefix(term) {
+                    let factor = expansions.entry(found).or_insert(0.0);
+                    *factor = factor.max(fuzzy.prefix_penalty);
+                }
+            }
+        }
+        // Giữ các term tốt nhất, ưu tiên term xuất hiện trong nhiều văn bản
+        let mut expansions: Vec<(String, f64, usize)> = expansions
+            .into_iter()
+            .map(|(found, factor)| {
+                let df = self.document_frequency(field, found);
+                (found.to_string(), factor, df)
+            })
+            .filter(|&(_, _, df)| df > 0)
+            .collect();
+        expansions.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.2.cmp(&a.2))
+                .then(a.0.cmp(&b.0))
+        });
+        expansions.truncate(fuzzy.max_expansions.max(1));
+        expansions
+            .into_iter()
+            .map(|(found, factor, _)| (found, factor))
+            .collect()
+    }
+
+    // Tìm bằng ngôn ngữ truy vấn Boolean; văn bản khớp được xếp hạng bằng điểm BM25 của
+    // các term không nằm dưới NOT
+    fn query(&self, input: &str, k: usize) -> Result<Vec<Hit>, ParseError> {
+        let query = Query::parse_with(input, &|field, text| {
+            self.schema.analyzer(field).analyze(text)
+        })?;
+        let mut terms = Vec::new();
+        query.positive_terms(DEFAULT_FIELD, &mut terms);
+        let mut by_field: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (field, term) in terms {
+            by_field.entry(field).or_default().push(term.to_string());
+        }
+        let mut ranked = HashMap::new();
+        for (field, terms) in by_field {
+            self.score_field(field, &terms, &HashMap::new(), &mut ranked);
+        }
+
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for (reader, &base) in self.snapshot.segments.iter().zip(&self.snapshot.bases) {
+            for doc in Searcher::evaluate(reader, DEFAULT_FIELD, &query) {
+                let score = ranked.get(&(base + doc)).copied().unwrap_or(0.0);
+                scores.insert(base + doc, score);
+            }
+        }
+        Ok(top_k(scores, k))
+    }
+
+    // Đánh giá truy vấn trên một segment, trả về doc id cục bộ còn sống đã sắp xếp
+    fn evaluate(reader: &SegmentReader, field: &str, query: &Query) -> Vec<usize> {
+        let index = reader.segment.indexes.get(field);
+        let live = |docs: Vec<usize>| -> Vec<usize> {
+            docs.into_iter()
+                .filter(|&doc| reader.is_live(doc))
+                .collect()
+        };
+        match query {
+            Query::Term(term) => live(index.map_or(Vec::new(), |index| index.lookup(term))),
+            Query::Phrase(terms) => live(index.map_or(Vec::new(), |index| index.phrase(terms))),
+            Query::And(left, right) => {
+                let right = Searcher::evaluate(reader, field, right);
+                Searcher::evaluate(reader, field, left)
+                    .into_iter()
+                    .filter(|doc| right.binary_search(doc).is_ok())
+                    .collect()
+            }
+            Query::Or(left, right) => {
+                let mut docs = Searcher::evaluate(reader, field, left);
+                docs.extend(Searcher::evaluate(reader, field, right));
+                docs.sort_unstable();
+                docs.dedup();
+                docs
+            }
+            Query::Not(inner) => {
+                let excluded = Searcher::evaluate(reader, field, inner);
+                (0..reader.segment.documents.len())
+                    .filter(|&doc| reader.is_live(doc))
+                    .filter(|doc| excluded.binary_search(doc).is_err())
+                    .collect()
+            }
+            Query::Field(field, inner) => Searcher::evaluate(reader, field, inner),
+        }
+    }
+
+    // Tìm kiếm có lọc, facet, sắp xếp và phân trang như `TFIDF::search_with`
+    fn search_with(&self, request: &SearchRequest) -> SearchResponse {
+        let allowed = |doc: usize| {
+            self.document(doc)
+                .is_some_and(|document| request.filters.iter().all(|f| f.matches(document)))
+        };
+        let hits: Vec<Hit> = if self.terms(DEFAULT_FIELD, &request.query).is_empty() {
+            let slots = self.snapshot.bases.last().copied().unwrap_or(0)
+                + self
+                    .snapshot
+                    .segments
+                    .last()
+                    .map_or(0, |reader| reader.segment.documents.len());
+            (0..slots)
+                .filter(|&doc| allowed(doc))
+                .map(|doc| Hit { doc, score: 0.0 })
+                .collect()
+        } else {
+            self.scores(&request.query)
+                .into_iter()
+                .filter(|&(doc, _)| allowed(doc))
+                .map(|(doc, score)| Hit { doc, score })
+                .collect()
+        };
+        respond(
+            request,
+            hits,
+            |doc| self.document(doc),
+            |hit| self.result(&request.query, hit),
+        )
+    }
+
+    // Tìm kiếm và trả về mã định danh, điểm, đoạn trích có đánh dấu và các trường lưu trữ
+    fn search_results(&self, query: &str, k: usize) -> Vec<SearchResult> {
+        self.search(query, k)
+            .into_iter()
+            .filter_map(|hit| self.result(query, hit))
+            .collect()
+    }
+
+    fn result(&self, query: &str, hit: Hit) -> Option<SearchResult> {
+        let document = self.document(hit.doc)?;
+        Some(SearchResult {
+            doc: hit.doc,
+            id: document.id.clone(),
+            score: hit.score,
+            snippet: self.snippet(query, &document.content),
+            stored: document.stored.clone(),
+        })
+    }
+
+    // Các đoạn trích tốt nhất của `text` cho truy vấn, trọng số term là IDF BM25 trên
+    // trường `content`
+    fn highlight(&self, query: &str, text: &str) -> Vec<Fragment> {
+        let n = self.snapshot.live_docs as f64;
+        let weights: HashMap<String, f64> = self
+            .terms(DEFAULT_FIELD, query)
+            .into_iter()
+            .map(|term| {
+                let df = self.document_frequency(DEFAULT_FIELD, &term) as f64;
+                let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                (term, idf)
+            })
+            .collect();
+        let tokens = self.schema.analyzer(DEFAULT_FIELD).analyze(text);
+        self.schema.highlighter.highlight(text, &tokens, &weights)
+    }
+
+    // Nối các đoạn trích thành một chuỗi để hiển thị
+    fn snippet(&self, query: &str, text: &str) -> String {
+        let fragments: Vec<String> = self
+            .highlight(query, text)
+            .into_iter()
+            .map(|f| f.text)
+            .collect();
+        fragments.join(&self.schema.highlighter.separator)
+    }
+}
+
+// Lỗi khi đọc file đánh giá độ liên quan
+#[derive(Debug)]
+enum JudgmentError {
+    Io(std::io::Error),
+    // Dòng (đếm từ 1) không đúng định dạng
+    Line(usize, String),
+}
+
+impl std::fmt::Display for JudgmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JudgmentError::Io(error) => write!(f, "lỗi vào/ra: {}", error),
+            JudgmentError::Line(line, content) => {
+                write!(f, "dòng {} không hợp lệ: {:?}", line, content)
+            }
+        }
+    }
+}
+
+impl std::error::Error for JudgmentError {}
+
+impl From<std::io::Error> for JudgmentError {
+    fn from(error: std::io::Error) -> Self {
+        JudgmentError::Io(error)
+    }
+}
+
+// Mức độ liên quan lớn nhất được chấp nhận; nDCG dùng 2^mức độ nên mức quá lớn sẽ tràn
+const MAX_GRADE: u32 = 10;
+
+// Đánh giá độ liên quan: truy vấn -> mã văn bản -> mức độ (0 là không liên quan)
+#[derive(Debug, Clone, Default)]
+struct Judgments {
+    queries: BTreeMap<String, HashMap<String, u32>>,
+}
+
+impl Judgments {
+    // Mỗi dòng "truy vấn<TAB>mã văn bản<TAB>mức độ" với mức độ từ 0 tới `MAX_GRADE`; bỏ qua
+    // dòng trống và dòng bắt đầu bằng '#'
+    fn parse(text: &str) -> Result<Judgments, JudgmentError> {
+        let mut judgments = Judgments::default();
+        for (number, line) in text.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let invalid = || JudgmentError::Line(number + 1, line.to_string());
+            let parts: Vec<&str> = line.split('\t').map(str::trim).collect();
+            let [query, id, grade] = parts[..] else {
+                return Err(invalid());
+            };
+            let grade: u32 = grade.parse().map_err(|_| invalid())?;
+            if grade > MAX_GRADE {
+                return Err(invalid());
+            }
+            judgments
+                .queries
+                .entry(query.to_string())
+                .or_default()
+                .insert(id.to_string(), grade);
+        }
+        Ok(judgments)
+    }
+
+    fn load(path: impl AsRef<std::path::Path>) -> Result<Judgments, JudgmentError> {
+        Judgments::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+// Các chỉ số tại ngưỡng k của một truy vấn, hoặc trung bình trên mọi truy vấn
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Relevance {
+    precision: f64,
+    recall: f64,
+    // Average precision; trung bình của nó trên các truy vấn là MAP
+    average_precision: f64,
+    reciprocal_rank: f64,
+    ndcg: f64,
+}
+
+impl Relevance {
+    // Tính các chỉ số cho `ranking` (mã văn bản theo thứ tự trả về) tại ngưỡng `k`
+    fn of(ranking: &[String], grades: &HashMap<String, u32>, k: usize) -> Self {
+        let k = k.max(1);
+        let ranking = &ranking[..ranking.len().min(k)];
+        let grade = |id: &String| grades.get(id).copied().unwrap_or(0);
+        let relevant = grades.values().filter(|&&g| g > 0).count();
+
+        let mut found = 0;
+        let mut precision_sum = 0.0;
+        let mut reciprocal_rank = 0.0;
+        let mut dcg = 0.0;
+        for (rank, id) in ranking.iter().enumerate() {
+            let gain = grade(id);
+            if gain > 0 {
+                found += 1;
+                precision_sum += found as f64 / (rank + 1) as f64;
+                if reciprocal_rank == 0.0 {
+                    reciprocal_rank = 1.0 / (rank + 1) as f64;
+                }
+            }
+            dcg += (2f64.powi(gain as i32) - 1.0) / (rank as f64 + 2.0).log2();
+        }
+        let mut ideal: Vec<u32> = grades.values().copied().filter(|&g| g > 0).collect();
+        ideal.sort_unstable_by(|a, b| b.cmp(a));
+        let idcg: f64 = ideal
+            .iter()
+            .take(k)
+            .enumerate()
+            .map(|(rank, &gain)| (2f64.powi(gain as i32) - 1.0) / (rank as f64 + 2.0).log2())
+            .sum();
+
+        // Truy vấn không có văn bản liên quan nào được tính là 0 cho mọi chỉ số
+        let ratio = |value: f64, total: f64| if total > 0.0 { value / total } else { 0.0 };
+        Relevance {
+            precision: found as f64 / k as f64,
+            recall: ratio(found as f64, relevant as f64),
+            average_precision: ratio(precision_sum, relevant.min(k) as f64),
+            reciprocal_rank,
+            ndcg: ratio(dcg, idcg),
+        }
+    }
+
+    fn mean<'a>(values: impl IntoIterator<Item = &'a Relevance>) -> Self {
+        let mut sum = Relevance::default();
+        let mut count = 0;
+        for value in values {
+            sum.precision += value.precision;
+            sum.recall += value.recall;
+            sum.average_precision += value.average_precision;
+            sum.reciprocal_rank += value.reciprocal_rank;
+            sum.ndcg += value.ndcg;
+            count += 1;
+        }
+        if count > 0 {
+            let n = count as f64;
+            sum.precision /= n;
+            sum.recall /= n;
+            sum.average_precision /= n;
+            sum.reciprocal_rank /= n;
+            sum.ndcg /= n;
+        }
+        sum
+    }
+}
+
+// Kết quả đánh giá một cấu hình xếp hạng
+#[derive(Debug, Clone)]
+struct Evaluation {
+    k: usize,
+    queries: BTreeMap<String, Relevance>,
+    // P@k, R@k, MAP, MRR và nDCG@k trung bình
+    mean: Relevance,
+}
+
+impl TFIDF {
+    // Chạy mọi truy vấn trong `judgments` qua `search` và tính các chỉ số tại ngưỡng `k`.
+    // Văn bản không có mã định danh được nhận diện bằng doc id.
+    fn evaluate_judgments(&self, judgments: &Judgments, k: usize) -> Evaluation {
+        let queries: BTreeMap<String, Relevance> = judgments
+            .queries
+            .iter()
+            .map(|(query, grades)| {
+                let ranking: Vec<String> = self
+                    .search(query, k)
+                    .into_iter()
+                    .map(|hit| match self.get(hit.doc) {
+                        Some(document) if !document.id.is_empty() => document.id.clone(),
+                        _ => hit.doc.to_string(),
+                    })
+                    .collect();
+                (query.clone(), Relevance::of(&ranking, grades, k))
+            })
+            .collect();
+        let mean = Relevance::mean(queries.values());
+        Evaluation { k, queries, mean }
+    }
+}
+
+// So sánh hai cấu hình xếp hạng trên cùng bộ đánh giá
+#[derive(Debug, Clone)]
+struct Comparison {
+    baseline: Evaluation,
+    candidate: Evaluation,
+}
+
+impl Comparison {
+    fn new(baseline: &TFIDF, candidate: &TFIDF, judgments: &Judgments, k: usize) -> Self {
+        Comparison {
+            baseline: baseline.evaluate_judgments(judgments, k),
+            candidate: candidate.evaluate_judgments(judgments, k),
+        }
+    }
+}
+
+// Bảng so sánh các chỉ số trung bình, sau đó là nDCG của từng truy vấn
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let k = self.baseline.k;
+        let (a, b) = (&self.baseline.mean, &self.candidate.mean);
+        writeln!(
+            f,
+            "{:<12} {:>9} {:>9} {:>9}",
+            "metric", "baseline", "candidate", "delta"
+        )?;
+        for (name, x, y) in [
+            (format!("P@{}", k), a.precision, b.precision),
+            (format!("R@{}", k), a.recall, b.recall),
+            ("MAP".to_string(), a.average_precision, b.average_precision),
+            ("MRR".to_string(), a.reciprocal_rank, b.reciprocal_rank),
+            (format!("nDCG@{}", k), a.ndcg, b.ndcg),
+        ] {
+            writeln!(f, "{:<12} {:>9.4} {:>9.4} {:>+9.4}", name, x, y, y - x)?;
+        }
+        for (query, x) in &self.baseline.queries {
+            let y = self.candidate.queries[query].ndcg;
+            writeln!(
+                f,
+                "  {:<10} {:>9.4} {:>9.4} {:>+9.4}",
+                query,
+                x.ndcg,
+                y,
+                y - x.ndcg
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Lỗi khi lưu hoặc nạp mô hình
+#[derive(Debug)]
+enum PersistError {
+    Io(std::io::Error),
+    // Không phải file chỉ mục
+    Magic,
+    // Phiên bản định dạng không được hỗ trợ (thường là chỉ mục cũ)
+    Version(u32),
+    Checksum,
+    Corrupt,
+}
+
+impl std::fmt::Display for PersistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistError::Io(error) => write!(f, "lỗi vào/ra: {}", error),
+            PersistError::Magic => write!(f, "không phải file chỉ mục TF-IDF"),
+            PersistError::Version(version) => write!(
+                f,
+                "phiên bản định dạng {} không được hỗ trợ (cần {})",
+                version, FORMAT_VERSION
+            ),
+            PersistError::Checksum => write!(f, "checksum không khớp, file bị hỏng"),
+            PersistError::Corrupt => write!(f, "dữ liệu chỉ mục không hợp lệ"),
+        }
+    }
+}
+
+impl std::error::Error for PersistError {}
+
+impl From<std::io::Error> for PersistError {
+    fn from(error: std::io::Error) -> Self {
+        PersistError::Io(error)
+    }
+}
+
+const MAGIC: &[u8; 8] = b"RIMTFIDF";
+// File segment của `IndexWriter`
+const SEGMENTS_MAGIC: &[u8; 8] = b"RIMSEGMT";
+// File tạm chứa chỉ mục của một lô trong `fit_stream`
+const SPILL_MAGIC: &[u8; 8] = b"RIMSPILL";
+const FORMAT_VERSION: u32 = 5;
+// magic + version + checksum + độ dài payload
+const HEADER_LEN: usize = 8 + 4 + 8 + 8;
+// Bản ghi của bảng văn bản: độ dài, tf lớn nhất, chuẩn, vị trí danh sách term
+const DOC_RECORD: usize = 32;
+// Bản ghi của bảng term: vị trí và độ dài tên, DF, IDF, vị trí postings
+const TERM_RECORD: usize = 40;
+// Phần mô tả một chỉ mục: số slot, vị trí bảng văn bản, số term, vị trí bảng term
+const INDEX_FOOTER: usize = 32;
+// Vị trí của văn bản đã xóa trong bảng văn bản và kho văn bản
+const ABSENT: u64 = u64::MAX;
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+// FNV-1a 64 bit, cộng dồn từ `hash` để tính được theo từng khối
+fn checksum(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Kiểm tra header (magic, phiên bản, checksum, độ dài) và trả về payload
+fn unframe<'a>(magic: &[u8; 8], bytes: &'a [u8]) -> Result<&'a [u8], PersistError> {
+    if bytes.len() < HEADER_LEN || &bytes[..8] != magic {
+        return Err(PersistError::Magic);
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(PersistError::Version(version));
+    }
+    let expected = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+    let len = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() as u64 != len {
+        return Err(PersistError::Corrupt);
+    }
+    if checksum(FNV_OFFSET, payload) != expected {
+        return Err(PersistError::Checksum);
+    }
+    Ok(payload)
+}
+
+// Ghi file chỉ mục tuần tự: header được chừa chỗ rồi điền khi xong, checksum tính dần theo
+// từng khối nên không phải giữ cả file trong bộ nhớ. Ghi vào file tạm và chỉ đổi tên khi
+// `finish` thành công để không để lại file ghi dở (file tạm bị xóa nếu bỏ dở), và để file
+// đang được ánh xạ bởi một mô hình đã nạp không bị ghi đè tại chỗ.
+struct FileWriter {
+    file: std::io::BufWriter<std::fs::File>,
+    tmp: std::path::PathBuf,
+    path: std::path::PathBuf,
+    magic: [u8; 8],
+    // Vị trí tiếp theo trong payload
+    position: u64,
+    hash: u64,
+    finished: bool,
+}
+
+impl FileWriter {
+    fn create(path: &std::path::Path, magic: &[u8; 8]) -> std::io::Result<FileWriter> {
+        let tmp = path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        std::io::Write::write_all(&mut file, &[0; HEADER_LEN])?;
+        Ok(FileWriter {
+            file,
+            tmp,
+            path: path.to_path_buf(),
+            magic: *magic,
+            position: 0,
+            hash: FNV_OFFSET,
+            finished: false,
+        })
+    }
+
+    // Ghi một khối, trả về vị trí của nó trong payload
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<u64> {
+        std::io::Write::write_all(&mut self.file, bytes)?;
+        self.hash = checksum(self.hash, bytes);
+        let offset = self.position;
+        self.position += bytes.len() as u64;
+        Ok(offset)
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        use std::io::{Seek, Write};
+        self.file.flush()?;
+        let file = self.file.get_mut();
+        file.seek(std::io::SeekFrom::Start(0))?;
+        file.write_all(&self.magic)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&self.hash.to_le_bytes())?;
+        file.write_all(&self.position.to_le_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&self.tmp, &self.path)?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+// File chỉ mục được ánh xạ vào bộ nhớ. Chỉ mục và kho văn bản nạp từ file giữ `Arc` tới
+// nó nên vùng nhớ còn sống chừng nào còn được dùng.
+#[derive(Debug)]
+struct Mapping {
+    map: std::mem::ManuallyDrop<memmap2::Mmap>,
+    // File tạm của `fit_stream`, bị xóa khi không còn được dùng
+    temporary: Option<std::path::PathBuf>,
+}
+
+impl Mapping {
+    // Ánh xạ file và kiểm tra header, phiên bản và checksum
+    fn open(path: &std::path::Path, magic: &[u8; 8]) -> Result<Arc<Mapping>, PersistError> {
+        Mapping::map_file(path, magic, None)
+    }
+
+    // Như `open` nhưng file bị xóa khi bỏ ánh xạ, kể cả khi kiểm tra lỗi
+    fn temporary(path: &std::path::Path, magic: &[u8; 8]) -> Result<Arc<Mapping>, PersistError> {
+        Mapping::map_file(path, magic, Some(path.to_path_buf()))
+    }
+
+    fn map_file(
+        path: &std::path::Path,
+        magic: &[u8; 8],
+        temporary: Option<std::path::PathBuf>,
+    ) -> Result<Arc<Mapping>, PersistError> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: vùng nhớ chỉ được đọc. `FileWriter` luôn ghi file mới rồi đổi tên nên
+        // không ghi đè file đang được ánh xạ; file bị chương trình khác sửa tại chỗ trong
+        // lúc đang ánh xạ thì không được hỗ trợ.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let mapping = Mapping {
+            map: std::mem::ManuallyDrop::new(map),
+            temporary,
+        };
+        unframe(magic, &mapping.map)?;
+        Ok(Arc::new(mapping))
+    }
+
+    fn payload(&self) -> &[u8] {
+        &self.map[HEADER_LEN..]
+    }
+
+    // Vị trí phần gốc, ghi ở 8 byte cuối payload
+    fn root(&self) -> Result<Decoder<'_>, PersistError> {
+        let payload = self.payload();
+        let end = payload.len().checked_sub(8).ok_or(PersistError::Corrupt)?;
+        let root = to_usize(fixed(payload, end)?)?;
+        Ok(Decoder {
+            bytes: payload.get(root..end).ok_or(PersistError::Corrupt)?,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Bỏ ánh xạ trước khi xóa file vì có hệ điều hành không cho xóa file đang ánh xạ
+        // SAFETY: `map` không được dùng lại sau đây
+        unsafe { std::mem::ManuallyDrop::drop(&mut self.map) };
+        if let Some(path) = &self.temporary {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Số nguyên 8 byte tại `offset`
+fn fixed(bytes: &[u8], offset: usize) -> Result<u64, PersistError> {
+    let end = offset.checked_add(8).ok_or(PersistError::Corrupt)?;
+    let bytes = bytes.get(offset..end).ok_or(PersistError::Corrupt)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn to_usize(value: u64) -> Result<usize, PersistError> {
+    usize::try_from(value).map_err(|_| PersistError::Corrupt)
+}
+
+// Kiểm tra bảng `count` bản ghi rộng `width` bắt đầu tại `offset` nằm trọn trong payload
+fn table(
+    bytes: &[u8],
+    offset: u64,
+    count: u64,
+    width: usize,
+) -> Result<(usize, usize), PersistError> {
+    let (offset, count) = (to_usize(offset)?, to_usize(count)?);
+    let end = count
+        .checked_mul(width)
+        .and_then(|len| len.checked_add(offset))
+        .ok_or(PersistError::Corrupt)?;
+    if end > bytes.len() {
+        return Err(PersistError::Corrupt);
+    }
+    Ok((offset, count))
+}
+
+// Ghi số nguyên dạng varint (LEB128) và các kiểu cơ bản
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder { bytes: Vec::new() }
+    }
+
+    fn uint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn float(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.uint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn fixed(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PersistError> {
+        if self.bytes.len() < len {
+            return Err(PersistError::Corrupt);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn uint(&mut self) -> Result<u64, PersistError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(PersistError::Corrupt)
+    }
+
+    fn usize(&mut self) -> Result<usize, PersistError> {
+        to_usize(self.uint()?)
+    }
+
+    fn float(&mut self) -> Result<f64, PersistError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, PersistError> {
+        let len = self.usize()?;
+        std::str::from_utf8(self.take(len)?).map_err(|_| PersistError::Corrupt)
+    }
+
+    fn string(&mut self) -> Result<String, PersistError> {
+        Ok(self.str()?.to_string())
+    }
+}
+
+// Một văn bản của phần chỉ mục đang ghi
+struct DocEntry {
+    live: bool,
+    length: usize,
+    max_tf: usize,
+    norm: f64,
+    // Số thứ tự (theo tên) của các term trong văn bản, tăng dần
+    ordinals: Vec<u64>,
+}
+
+// Ghi một chỉ mục theo định dạng đọc thẳng được từ vùng nhớ ánh xạ: postings của từng term
+// theo thứ tự tên, danh sách term của từng văn bản, tên các term, rồi bảng term và bảng văn
+// bản kích thước cố định để tra theo vị trí. Postings được ghi ngay khi nhận từng term; chỉ
+// tên và thông tin term (cỡ từ điển) được giữ lại tới cuối.
+#[derive(Default)]
+struct IndexSection {
+    // Tên, DF, IDF, vị trí postings
+    terms: Vec<(String, u64, f64, u64)>,
+}
+
+impl IndexSection {
+    // Ghi postings của term tiếp theo; term phải được đưa vào theo thứ tự tăng dần
+    fn term(
+        &mut self,
+        file: &mut FileWriter,
+        term: &str,
+        idf: f64,
+        postings: &[Posting],
+    ) -> std::io::Result<()> {
+        let mut encoder = Encoder::new();
+        let mut previous = 0;
+        for posting in postings {
+            // Lưu khoảng cách doc id và vị trí để số nhỏ, varint ngắn hơn
+            encoder.uint((posting.doc - previous) as u64);
+            previous = posting.doc;
+            encoder.uint(posting.positions.len() as u64);
+            let mut last = 0;
+            for &position in &posting.positions {
+                encoder.uint((position - last) as u64);
+                last = position;
+            }
+        }
+        let offset = file.write(&encoder.bytes)?;
+        self.terms
+            .push((term.to_string(), postings.len() as u64, idf, offset));
+        Ok(())
+    }
+
+    // Ghi danh sách term của từng văn bản cùng các bảng, trả về vị trí phần mô tả
+    fn finish(
+        self,
+        file: &mut FileWriter,
+        documents: impl Iterator<Item = DocEntry>,
+    ) -> std::io::Result<u64> {
+        let mut docs = Encoder::new();
+        let mut slots = 0;
+        for document in documents {
+            let forward = if document.live {
+                let mut encoder = Encoder::new();
+                encoder.uint(document.ordinals.len() as u64);
+                let mut previous = 0;
+                for &ordinal in &document.ordinals {
+                    encoder.uint(ordinal - previous);
+                    previous = ordinal;
+                }
+                file.write(&encoder.bytes)?
+            } else {
+                ABSENT
+            };
+            docs.fixed(document.length as u64);
+            docs.fixed(document.max_tf as u64);
+            docs.float(document.norm);
+            docs.fixed(forward);
+            slots += 1;
+        }
+        let mut terms = Encoder::new();
+        for (name, df, idf, postings) in &self.terms {
+            terms.fixed(file.write(name.as_bytes())?);
+            terms.fixed(name.len() as u64);
+            terms.fixed(*df);
+            terms.float(*idf);
+            terms.fixed(*postings);
+        }
+        let term_table = file.write(&terms.bytes)?;
+        let doc_table = file.write(&docs.bytes)?;
+        let mut footer = Encoder::new();
+        for value in [slots, doc_table, self.terms.len() as u64, term_table] {
+            footer.fixed(value);
+        }
+        file.write(&footer.bytes)
+    }
+}
+
+// Ghi một chỉ mục trong bộ nhớ (hoặc đã nạp từ file) bằng `IndexSection`
+fn write_index(
+    file: &mut FileWriter,
+    index: &Index,
+    idf_scores: &HashMap<String, f64>,
+    norms: &[f64],
+) -> std::io::Result<u64> {
+    // Sắp xếp term để cùng một mô hình luôn cho ra cùng một file
+    let mut terms = index.term_list();
+    terms.sort_unstable();
+    let mut section = IndexSection::default();
+    for &term in &terms {
+        let idf = idf_scores.get(term).copied().unwrap_or(0.0);
+        section.term(file, term, idf, index.postings(term))?;
+    }
+    let documents = (0..index.slots()).map(|doc| {
+        let mut ordinals: Vec<u64> = index
+            .doc_terms(doc)
+            .into_iter()
+            .map(|term| terms.binary_search(&term).unwrap() as u64)
+            .collect();
+        ordinals.sort_unstable();
+        DocEntry {
+            live: index.is_live(doc),
+            length: index.lengths[doc],
+            max_tf: index.max_tf[doc],
+            norm: norms.get(doc).copied().unwrap_or(0.0),
+            ordinals,
+        }
+    });
+    section.finish(file, documents)
+}
+
+// Ghi chỉ mục của một lô ra file tạm rồi ánh xạ lại; file bị xóa khi chỉ mục trả về không
+// còn được dùng
+fn spill_index(path: &std::path::Path, index: &Index) -> Result<MappedIndex, PersistError> {
+    let mut file = FileWriter::create(path, SPILL_MAGIC)?;
+    let footer = write_index(&mut file, index, &HashMap::new(), &[])?;
+    let mut root = Encoder::new();
+    root.uint(footer);
+    let at = file.write(&root.bytes)?;
+    file.write(&at.to_le_bytes())?;
+    file.finish()?;
+    let mapping = Mapping::temporary(path, SPILL_MAGIC)?;
+    let footer = mapping.root()?.usize()?;
+    MappedIndex::open(&mapping, footer)
+}
+
+// Đọc postings của một term: `df` phần tử, doc id tăng dần và nhỏ hơn `slots`
+fn decode_postings(
+    decoder: &mut Decoder<'_>,
+    df: usize,
+    slots: usize,
+) -> Result<Vec<Posting>, PersistError> {
+    let mut postings = Vec::with_capacity(df.min(slots));
+    let mut doc: usize = 0;
+    for i in 0..df {
+        // Doc id tăng dần: delta 0 sau posting đầu là trùng văn bản
+        let delta = decoder.usize()?;
+        if i > 0 && delta == 0 {
+            return Err(PersistError::Corrupt);
+        }
+        doc = doc.checked_add(delta).ok_or(PersistError::Corrupt)?;
+        if doc >= slots {
+            return Err(PersistError::Corrupt);
+        }
+        let tf = decoder.usize()?;
+        let mut positions = Vec::with_capacity(tf.min(decoder.bytes.len()));
+        let mut position: usize = 0;
+        for _ in 0..tf {
+            position = position
+                .checked_add(decoder.usize()?)
+                .ok_or(PersistError::Corrupt)?;
+            positions.push(position);
+        }
+        postings.push(Posting { doc, tf, positions });
+    }
+    Ok(postings)
+}
+
+// Chỉ mục nạp từ file: postings và danh sách term của văn bản được đọc thẳng từ vùng nhớ
+// ánh xạ khi cần. Postings của mỗi term chỉ được giải mã một lần, ở lần tra đầu tiên.
+#[derive(Debug)]
+struct MappedIndex {
+    mapping: Arc<Mapping>,
+    slots: usize,
+    doc_table: usize,
+    term_count: usize,
+    term_table: usize,
+    cache: Vec<std::sync::OnceLock<Vec<Posting>>>,
+}
+
+impl MappedIndex {
+    // Đọc phần mô tả tại `footer` và kiểm tra hai bảng nằm trọn trong file; nội dung các bảng
+    // được `map_index` kiểm tra
+    fn open(mapping: &Arc<Mapping>, footer: usize) -> Result<MappedIndex, PersistError> {
+        let bytes = mapping.payload();
+        let footer: Vec<u64> = (0..INDEX_FOOTER / 8)
+            .map(|at| fixed(bytes, footer + at * 8))
+            .collect::<Result<_, _>>()?;
+        let (doc_table, slots) = table(bytes, footer[1], footer[0], DOC_RECORD)?;
+        let (term_table, term_count) = table(bytes, footer[3], footer[2], TERM_RECORD)?;
+        Ok(MappedIndex {
+            mapping: mapping.clone(),
+            slots,
+            doc_table,
+            term_count,
+            term_table,
+            cache: (0..term_count)
+                .map(|_| std::sync::OnceLock::new())
+                .collect(),
+        })
+    }
+
+    // Trường thứ `at` (8 byte) của bản ghi; vị trí đã được kiểm tra khi nạp
+    fn field(&self, record: usize, at: usize) -> u64 {
+        let offset = record + at * 8;
+        u64::from_le_bytes(
+            self.mapping.payload()[offset..offset + 8]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    fn term_record(&self, ordinal: usize) -> usize {
+        self.term_table + ordinal * TERM_RECORD
+    }
+
+    fn term(&self, ordinal: usize) -> &str {
+        let record = self.term_record(ordinal);
+        let offset = self.field(record, 0) as usize;
+        let len = self.field(record, 1) as usize;
+        std::str::from_utf8(&self.mapping.payload()[offset..offset + len]).unwrap()
+    }
+
+    // Tìm nhị phân trên bảng term đã sắp xếp
+    fn ordinal(&self, term: &str) -> Option<usize> {
+        let (mut low, mut high) = (0, self.term_count);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.term(middle).cmp(term) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    fn doc_record(&self, doc: usize) -> usize {
+        self.doc_table + doc * DOC_RECORD
+    }
+
+    // Giải mã postings của term mà không giữ lại trong bộ nhớ đệm
+    fn decode(&self, ordinal: usize) -> Result<Vec<Posting>, PersistError> {
+        let record = self.term_record(ordinal);
+        let mut decoder = Decoder {
+            bytes: &self.mapping.payload()[self.field(record, 4) as usize..],
+        };
+        decode_postings(&mut decoder, self.field(record, 2) as usize, self.slots)
+    }
+
+    fn postings_at(&self, ordinal: usize) -> &[Posting] {
+        // Đã giải mã thử khi nạp nên không thể lỗi
+        self.cache[ordinal].get_or_init(|| self.decode(ordinal).unwrap())
+    }
+
+    fn postings(&self, term: &str) -> &[Posting] {
+        match self.ordinal(term) {
+            Some(ordinal) => self.postings_at(ordinal),
+            None => &[],
+        }
+    }
+
+    // DF đọc từ bảng term, không cần giải mã postings
+    fn document_frequency(&self, term: &str) -> usize {
+        self.ordinal(term).map_or(0, |ordinal| {
+            self.field(self.term_record(ordinal), 2) as usize
+        })
+    }
+
+    // Số thứ tự các term của văn bản, tăng dần
+    fn doc_ordinals(&self, doc: usize) -> Vec<usize> {
+        if doc >= self.slots {
+            return Vec::new();
+        }
+        let forward = self.field(self.doc_record(doc), 3);
+        if forward == ABSENT {
+            return Vec::new();
+        }
+        let mut decoder = Decoder {
+            bytes: &self.mapping.payload()[forward as usize..],
+        };
+        let count = decoder.usize().unwrap();
+        let mut ordinal = 0;
+        (0..count)
+            .map(|_| {
+                ordinal += decoder.usize().unwrap();
+                ordinal
+            })
+            .collect()
+    }
+
+    fn doc_terms(&self, doc: usize) -> Vec<&str> {
+        self.doc_ordinals(doc)
+            .into_iter()
+            .map(|ordinal| self.term(ordinal))
+            .collect()
+    }
+}
+
+// Nạp chỉ mục có phần mô tả tại `footer`. Mọi bảng, tên term, postings và danh sách term
+// được kiểm tra một lượt tuần tự nhưng không được giữ lại: trong bộ nhớ chỉ còn các mảng
+// theo doc id và từ điển (IDF, cây tìm gần đúng).
+fn map_index(mapping: &Arc<Mapping>, footer: usize) -> Result<FieldIndex, PersistError> {
+    let bytes = mapping.payload();
+    let mapped = MappedIndex::open(mapping, footer)?;
+    let (doc_table, slots) = (mapped.doc_table, mapped.slots);
+    let (term_table, term_count) = (mapped.term_table, mapped.term_count);
+
+    let mut index = Index::new();
+    let mut norms = Vec::with_capacity(slots);
+    let mut forwards = Vec::with_capacity(slots);
+    for doc in 0..slots {
+        let record = doc_table + doc * DOC_RECORD;
+        let forward = fixed(bytes, record + 24)?;
+        let live = forward != ABSENT;
+        index.live.push(live);
+        index.lengths.push(to_usize(fixed(bytes, record)?)?);
+        index.max_tf.push(to_usize(fixed(bytes, record + 8)?)?);
+        norms.push(f64::from_bits(fixed(bytes, record + 16)?));
+        index.doc_count += live as usize;
+        forwards.push(forward);
+    }
+
+    // Số term và tổng băm số thứ tự term của từng văn bản theo postings, để so với danh
+    // sách term của văn bản mà không phải giữ lại postings
+    let mut seen = vec![(0usize, 0u64); slots];
+    let mut idf_scores = HashMap::with_capacity(term_count);
+    let mut previous: Option<&str> = None;
+    for ordinal in 0..term_count {
+        let record = term_table + ordinal * TERM_RECORD;
+        let offset = to_usize(fixed(bytes, record)?)?;
+        let len = to_usize(fixed(bytes, record + 8)?)?;
+        let name = offset
+            .checked_add(len)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(PersistError::Corrupt)?;
+        let name = std::str::from_utf8(name).map_err(|_| PersistError::Corrupt)?;
+        // Tên tăng dần nghiêm ngặt để tìm nhị phân được và không trùng term
+        if previous.is_some_and(|previous| previous >= name) {
+            return Err(PersistError::Corrupt);
+        }
+        previous = Some(name);
+        let df = to_usize(fixed(bytes, record + 16)?)?;
+        let idf = f64::from_bits(fixed(bytes, record + 24)?);
+        let postings = to_usize(fixed(bytes, record + 32)?)?;
+        if df == 0 {
+            return Err(PersistError::Corrupt);
+        }
+        let mut decoder = Decoder {
+            bytes: bytes.get(postings..).ok_or(PersistError::Corrupt)?,
+        };
+        let hash = SplitMix(ordinal as u64).next();
+        for posting in decode_postings(&mut decoder, df, slots)? {
+            if !index.live[posting.doc] {
+                return Err(PersistError::Corrupt);
+            }
+            let (count, sum) = &mut seen[posting.doc];
+            *count += 1;
+            *sum = sum.wrapping_add(hash);
+        }
+        idf_scores.insert(name.to_string(), idf);
+    }
+
+    for (doc, &forward) in forwards.iter().enumerate() {
+        if forward == ABSENT {
+            continue;
+        }
+        let mut decoder = Decoder {
+            bytes: bytes
+                .get(to_usize(forward)?..)
+                .ok_or(PersistError::Corrupt)?,
+        };
+        let count = decoder.usize()?;
+        let (mut ordinal, mut sum) = (0usize, 0u64);
+        for i in 0..count {
+            let delta = decoder.usize()?;
+            if i > 0 && delta == 0 {
+                return Err(PersistError::Corrupt);
+            }
+            ordinal = ordinal.checked_add(delta).ok_or(PersistError::Corrupt)?;
+            if ordinal >= term_count {
+                return Err(PersistError::Corrupt);
+            }
+            sum = sum.wrapping_add(SplitMix(ordinal as u64).next());
+        }
+        if (count, sum) != seen[doc] {
+            return Err(PersistError::Corrupt);
+        }
+    }
+
+    let mut lexicon = Lexicon::default();
+    for term in idf_scores.keys() {
+        lexicon.insert(term);
+    }
+    index.mapped = Some(Arc::new(mapped));
+    Ok(FieldIndex {
+        index,
+        idf_scores,
+        lexicon,
+        norms,
+        parts: Vec::new(),
+    })
+}
+
+// Văn bản gốc theo doc id. Kho nạp từ file đọc văn bản từ vùng nhớ ánh xạ ở lần hỏi đầu
+// tiên thay vì giải mã cả kho khi nạp; lần sửa đầu tiên chép cả kho vào bộ nhớ.
+#[derive(Debug, Clone, Default)]
+struct DocStore {
+    documents: Vec<Option<Document>>,
+    mapped: Option<Arc<MappedStore>>,
+}
+
+#[derive(Debug)]
+struct MappedStore {
+    mapping: Arc<Mapping>,
+    table: usize,
+    count: usize,
+    cache: Vec<std::sync::OnceLock<Option<Document>>>,
+}
+
+impl MappedStore {
+    fn decoder(&self, doc: usize) -> Option<Decoder<'_>> {
+        let payload = self.mapping.payload();
+        let offset = fixed(payload, self.table + doc * 8).unwrap();
+        (offset != ABSENT).then(|| Decoder {
+            bytes: &payload[offset as usize..],
+        })
+    }
+
+    fn get(&self, doc: usize) -> Option<&Document> {
+        self.cache
+            .get(doc)?
+            .get_or_init(|| {
+                // Đã giải mã thử khi nạp nên không thể lỗi
+                let mut decoder = self.decoder(doc)?;
+                Some(decode_document(&mut decoder).unwrap())
+            })
+            .as_ref()
+    }
+}
+
+impl DocStore {
+    fn len(&self) -> usize {
+        match &self.mapped {
+            Some(mapped) => mapped.count,
+            None => self.documents.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, doc: usize) -> Option<&Document> {
+        match &self.mapped {
+            Some(mapped) => mapped.get(doc),
+            None => self.documents.get(doc).and_then(Option::as_ref),
+        }
+    }
+
+    // Mã định danh của văn bản, đọc thẳng từ file mà không giải mã cả văn bản
+    fn id(&self, doc: usize) -> Option<&str> {
+        match &self.mapped {
+            Some(mapped) if doc < mapped.count => mapped.decoder(doc)?.str().ok(),
+            Some(_) => None,
+            None => self.get(doc).map(|document| document.id.as_str()),
+        }
+    }
+
+    fn materialize(&mut self) {
+        if let Some(mapped) = self.mapped.take() {
+            self.documents = (0..mapped.count)
+                .map(|doc| mapped.get(doc).cloned())
+                .collect();
+        }
+    }
+
+    fn push(&mut self, document: Option<Document>) {
+        self.materialize();
+        self.documents.push(document);
+    }
+
+    fn set(&mut self, doc: usize, document: Document) {
+        self.materialize();
+        if doc >= self.documents.len() {
+            self.documents.resize(doc + 1, None);
+        }
+        self.documents[doc] = Some(document);
+    }
+
+    fn take(&mut self, doc: usize) -> Option<Document> {
+        self.materialize();
+        self.documents.get_mut(doc)?.take()
+    }
+
+    fn clear(&mut self) {
+        self.documents.clear();
+        self.mapped = None;
+    }
+
+    // Ghi từng văn bản rồi bảng vị trí, trả về (vị trí bảng, số văn bản)
+    fn write(&self, file: &mut FileWriter) -> std::io::Result<(u64, u64)> {
+        let mut offsets = Encoder::new();
+        for doc in 0..self.len() {
+            let offset = match self.get(doc) {
+                Some(document) => {
+                    let mut encoder = Encoder::new();
+                    encode_document(&mut encoder, document);
+                    file.write(&encoder.bytes)?
+                }
+                None => ABSENT,
+            };
+            offsets.fixed(offset);
+        }
+        Ok((file.write(&offsets.bytes)?, self.len() as u64))
+    }
+
+    // Nạp kho đã ghi bằng `write`; mỗi văn bản được giải mã thử một lần để kiểm tra
+    fn map(mapping: &Arc<Mapping>, offset: u64, count: u64) -> Result<DocStore, PersistError> {
+        let payload = mapping.payload();
+        let (table, count) = table(payload, offset, count, 8)?;
+        for doc in 0..count {
+            let offset = fixed(payload, table + doc * 8)?;
+            if offset != ABSENT {
+                let mut decoder = Decoder {
+                    bytes: payload
+                        .get(to_usize(offset)?..)
+                        .ok_or(PersistError::Corrupt)?,
+                };
+                decode_document(&mut decoder)?;
+            }
+        }
+        Ok(DocStore {
+            documents: Vec::new(),
+            mapped: Some(Arc::new(MappedStore {
+                mapping: mapping.clone(),
+                table,
+                count,
+                cache: (0..count).map(|_| std::sync::OnceLock::new()).collect(),
+            })),
+        })
+    }
+}
+
+impl From<Vec<Document>> for DocStore {
+    fn from(documents: Vec<Document>) -> Self {
+        DocStore {
+            documents: documents.into_iter().map(Some).collect(),
+            mapped: None,
+        }
+    }
+}
+
+// Văn bản của segment luôn có mặt nên tra trực tiếp như một mảng
+impl std::ops::Index<usize> for DocStore {
+    type Output = Document;
+
+    fn index(&self, doc: usize) -> &Document {
+        self.get(doc).unwrap()
+    }
+}
+
+impl TFIDF {
+    // Lưu mô hình đã fit (nên gọi `refresh` trước). Bộ phân tích không được lưu,
+    // khi nạp lại phải truyền cùng bộ phân tích đã dùng lúc fit.
+    fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), PersistError> {
+        let mut file = FileWriter::create(path.as_ref(), MAGIC)?;
+        let content = write_index(&mut file, &self.index, &self.idf_scores, &self.norms)?;
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for (name, field) in &self.fields {
+            let footer = write_index(&mut file, &field.index, &field.idf_scores, &field.norms)?;
+            fields.push((name, footer));
+        }
+        let store = self.store.write(&mut file)?;
+        self.write_root(&mut file, content, fields, store)?;
+        file.finish()?;
+        Ok(())
+    }
+
+    // Ghi phần gốc: cấu hình, vị trí các chỉ mục, từ vựng và kho văn bản
+    fn write_root(
+        &self,
+        file: &mut FileWriter,
+        content: u64,
+        fields: Vec<(&String, u64)>,
+        (store, count): (u64, u64),
+    ) -> std::io::Result<()> {
+        let mut encoder = Encoder::new();
+        encoder.uint(match self.tf_scheme {
+            TfScheme::Raw => 0,
+            TfScheme::Log => 1,
+            TfScheme::Augmented => 2,
+            TfScheme::Boolean => 3,
+            TfScheme::Count => 4,
+        });
+        encoder.uint(match self.idf_scheme {
+            IdfScheme::Standard => 0,
+            IdfScheme::Smooth => 1,
+            IdfScheme::Probabilistic => 2,
+            IdfScheme::Sklearn { smooth: false } => 3,
+            IdfScheme::Sklearn { smooth: true } => 4,
+        });
+        encoder.uint(match self.normalization {
+            Normalization::None => 0,
+            Normalization::L1 => 1,
+            Normalization::L2 => 2,
+        });
+        match self.scoring {
+            Scoring::Cosine => encoder.uint(0),
+            Scoring::Bm25 { k1, b } => {
+                encoder.uint(1);
+                encoder.float(k1);
+                encoder.float(b);
+            }
+        }
+        encoder.uint(content);
+        encoder.uint(fields.len() as u64);
+        for (name, footer) in fields {
+            encoder.string(name);
+            encoder.uint(footer);
+        }
+        let mut boosts: Vec<(&String, &f64)> = self.boosts.iter().collect();
+        boosts.sort_by(|a, b| a.0.cmp(b.0));
+        encoder.uint(boosts.len() as u64);
+        for (name, &boost) in boosts {
+            encoder.string(name);
+            encoder.float(boost);
+        }
+        encoder.uint(self.vocabulary.len() as u64);
+        for term in &self.vocabulary.terms {
+            encoder.string(term);
+        }
+        encoder.uint(store);
+        encoder.uint(count);
+        let root = file.write(&encoder.bytes)?;
+        file.write(&root.to_le_bytes())?;
+        Ok(())
+    }
+
+    // Nạp mô hình đã lưu bằng `save` với bộ phân tích đã dùng lúc fit; bộ phân tích riêng
+    // của từng trường đặt lại bằng `with_field_analyzer`. File được ánh xạ vào bộ nhớ:
+    // postings và văn bản được đọc thẳng từ file khi cần, chỉ từ điển và các mảng theo doc
+    // id được nạp vào bộ nhớ. Mô hình chỉ chép chỉ mục vào bộ nhớ khi bị sửa lần đầu.
+    fn load(
+        path: impl AsRef<std::path::Path>,
+        analyzer: impl Analyzer + 'static,
+    ) -> Result<TFIDF, PersistError> {
+        let mapping = Mapping::open(path.as_ref(), MAGIC)?;
+        let mut tfidf = TFIDF::new().with_analyzer(analyzer);
+        tfidf.read(&mapping)?;
+        Ok(tfidf)
+    }
+
+    // Đọc cấu hình đã lưu và dữ liệu từ file đã ánh xạ vào mô hình rỗng; phần cấu hình không
+    // được lưu (bộ phân tích, bộ tô sáng...) giữ nguyên
+    fn read(&mut self, mapping: &Arc<Mapping>) -> Result<(), PersistError> {
+        let mut decoder = mapping.root()?;
+        self.tf_scheme = match decoder.uint()? {
+            0 => TfScheme::Raw,
+            1 => TfScheme::Log,
+            2 => TfScheme::Augmented,
+            3 => TfScheme::Boolean,
+            4 => TfScheme::Count,
+            _ => return Err(PersistError::Corrupt),
+        };
+        self.idf_scheme = match decoder.uint()? {
+            0 => IdfScheme::Standard,
+            1 => IdfScheme::Smooth,
+            2 => IdfScheme::Probabilistic,
+            3 => IdfScheme::Sklearn { smooth: false },
+            4 => IdfScheme::Sklearn { smooth: true },
+            _ => return Err(PersistError::Corrupt),
+        };
+        self.normalization = match decoder.uint()? {
+            0 => Normalization::None,
+            1 => Normalization::L1,
+            2 => Normalization::L2,
+            _ => return Err(PersistError::Corrupt),
+        };
+        self.scoring = match decoder.uint()? {
+            0 => Scoring::Cosine,
+            1 => Scoring::Bm25 {
+                k1: decoder.float()?,
+                b: decoder.float()?,
+            },
+            _ => return Err(PersistError::Corrupt),
+        };
+
+        let content = map_index(mapping, decoder.usize()?)?;
+        (self.index, self.idf_scores, self.norms, self.lexicon) = (
+            content.index,
+            content.idf_scores,
+            content.norms,
+            content.lexicon,
+        );
+        self.refreshed = self.index.doc_count;
+        for _ in 0..decoder.usize()? {
+            let name = decoder.string()?;
+            let field = map_index(mapping, decoder.usize()?)?;
+            if field.index.slots() != self.index.slots() {
+                return Err(PersistError::Corrupt);
+            }
+            self.fields.insert(name, field);
+        }
+        for _ in 0..decoder.usize()? {
+            let name = decoder.string()?;
+            self.boosts.insert(name, decoder.float()?);
+        }
+
+        // Từ vựng theo đúng thứ tự id đã cấp, gồm cả term không còn trong chỉ mục
+        let mut vocabulary = Vocabulary::default();
+        for id in 0..decoder.usize()? {
+            if vocabulary.insert(decoder.str()?) as usize != id {
+                return Err(PersistError::Corrupt);
+            }
+        }
+        // Term chưa có id khi lưu (lưu trước `refresh`) được cấp tiếp theo thứ tự term
+        let mut terms: Vec<&String> = self
+            .idf_scores
+            .keys()
+            .filter(|term| vocabulary.id(term).is_none())
+            .collect();
+        terms.sort();
+        for term in terms {
+            vocabulary.insert(term);
+        }
+        self.vocabulary = Arc::new(vocabulary);
+
+        let (store, count) = (decoder.uint()?, decoder.uint()?);
+        self.store = DocStore::map(mapping, store, count)?;
+        for doc in 0..self.store.len() {
+            if let Some(id) = self.store.id(doc).filter(|id| !id.is_empty()) {
+                self.ids.insert(id.to_string(), doc);
+            }
+        }
+        if !decoder.bytes.is_empty() {
+            return Err(PersistError::Corrupt);
+        }
+        Ok(())
+    }
+}
+
+impl IndexWriter {
+    // Lưu các segment đã commit (văn bản còn trong bộ đệm và các lần xóa chưa commit thì
+    // không) cùng tombstones của chúng. Postings được lưu sẵn nên khi mở lại không phải
+    // phân tích lại văn bản.
+    fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), PersistError> {
+        let snapshot = self.shared.current.lock().unwrap().clone();
+        let mut file = FileWriter::create(path.as_ref(), SEGMENTS_MAGIC)?;
+        let mut encoder = Encoder::new();
+        encoder.uint(self.shared.next_segment.load(Ordering::SeqCst));
+        encoder.uint(snapshot.segments.len() as u64);
+        for reader in &snapshot.segments {
+            let segment = &reader.segment;
+            let (store, count) = segment.documents.write(&mut file)?;
+            encoder.uint(segment.id);
+            encoder.uint(store);
+            encoder.uint(count);
+            encoder.uint(segment.indexes.len() as u64);
+            for (name, index) in &segment.indexes {
+                encoder.string(name);
+                encoder.uint(write_index(&mut file, index, &HashMap::new(), &[])?);
+            }
+            encoder.uint(reader.deleted.count as u64);
+            for doc in (0..segment.documents.len()).filter(|&doc| !reader.is_live(doc)) {
+                encoder.uint(doc as u64);
+            }
+        }
+        let root = file.write(&encoder.bytes)?;
+        file.write(&root.to_le_bytes())?;
+        file.finish()?;
+        Ok(())
+    }
+
+    // Mở lại chỉ mục đã lưu bằng `save`. Giống `TFIDF::load`, file được ánh xạ vào bộ nhớ
+    // và bộ phân tích không được lưu nên phải truyền đúng bộ đã dùng lúc ghi; các tùy chọn
+    // khác đặt lại bằng `with_*`.
+    fn open(
+        path: impl AsRef<std::path::Path>,
+        analyzer: impl Analyzer + 'static,
+    ) -> Result<IndexWriter, PersistError> {
+        let mapping = Mapping::open(path.as_ref(), SEGMENTS_MAGIC)?;
+        let mut decoder = mapping.root()?;
+        let next_segment = decoder.uint()?;
+        let mut segments: Vec<SegmentReader> = Vec::new();
+        for _ in 0..decoder.usize()? {
+            let id = decoder.uint()?;
+            if id >= next_segment || segments.iter().any(|r| r.segment.id == id) {
+                return Err(PersistError::Corrupt);
+            }
+            let (store, count) = (decoder.uint()?, decoder.uint()?);
+            let documents = DocStore::map(&mapping, store, count)?;
+            let mut indexes = BTreeMap::new();
+            for _ in 0..decoder.usize()? {
+                let name = decoder.string()?;
+                let index = map_index(&mapping, decoder.usize()?)?.index;
+                if index.slots() > documents.len() {
+                    return Err(PersistError::Corrupt);
+                }
+                indexes.insert(name, index);
+            }
+            // Văn bản của segment luôn có mặt
+            if (0..documents.len()).any(|doc| documents.id(doc).is_none()) {
+                return Err(PersistError::Corrupt);
+            }
+            let mut reader = SegmentReader::new(Segment::new(id, indexes, documents));
+            for _ in 0..decoder.usize()? {
+                if !reader.delete(decoder.usize()?) {
+                    return Err(PersistError::Corrupt);
+                }
+            }
+            segments.push(reader);
+        }
+        if !decoder.bytes.is_empty() {
+            return Err(PersistError::Corrupt);
+        }
+        let writer = IndexWriter::new(analyzer);
+        writer
+            .shared
+            .next_segment
+            .store(next_segment, Ordering::SeqCst);
+        *writer.shared.current.lock().unwrap() = Arc::new(Snapshot::new(0, segments));
+        Ok(writer)
+    }
+}
+
+// Ghi một văn bản: mã định danh, nội dung, các trường và trường lưu trữ
+fn encode_document(encoder: &mut Encoder, document: &Document) {
+    encoder.string(&document.id);
+    encoder.string(&document.content);
+    encoder.uint(document.fields.len() as u64);
+    for (name, text) in &document.fields {
+        encoder.string(name);
+        encoder.string(text);
+    }
+    encoder.uint(document.stored.len() as u64);
+    for (name, value) in &document.stored {
+        encoder.string(name);
+        match value {
+            Value::Text(text) => {
+                encoder.uint(0);
+                encoder.string(text);
+            }
+            Value::Number(number) => {
+                encoder.uint(1);
+                encoder.float(*number);
+            }
+            Value::Flag(flag) => {
+                encoder.uint(2);
+                encoder.uint(*flag as u64);
+            }
+        }
+    }
+}
+
+// Đọc lại văn bản đã ghi bằng `encode_document`
+fn decode_document(decoder: &mut Decoder<'_>) -> Result<Document, PersistError> {
+    let mut document = Document::new("").with_id(decoder.str()?);
+    document.content = decoder.string()?;
+    for _ in 0..decoder.usize()? {
+        let name = decoder.string()?;
+        document.fields.insert(name, decoder.string()?);
+    }
+    for _ in 0..decoder.usize()? {
+        let name = decoder.string()?;
+        let value = match decoder.uint()? {
+            0 => Value::Text(decoder.string()?),
+            1 => Value::Number(decoder.float()?),
+            2 => Value::Flag(decoder.uint()? == 1),
+            _ => return Err(PersistError::Corrupt),
+        };
+        document.stored.insert(name, value);
+    }
+    Ok(document)
+}
+
+// Chọn `k` văn bản có điểm cao nhất, cùng điểm thì ưu tiên doc id nhỏ hơn
+fn top_k(scores: HashMap<usize, f64>, k: usize) -> Vec<Hit> {
+    let hits = scores.into_iter().map(|(doc, score)| Hit { doc, score });
+    first_k(hits, k, by_relevance)
+}
+
+// Điểm cao trước, cùng điểm thì doc id nhỏ trước
+fn by_relevance(a: &Hit, b: &Hit) -> std::cmp::Ordering {
+    b.score
+        .partial_cmp(&a.score)
+        .unwrap_or(std::cmp::Ordering::Equal)
+        .then(a.doc.cmp(&b.doc))
+}
+
+// Phần tử của heap, so sánh bằng thứ tự `order` truyền vào
+struct Ranked<'a, T, F> {
+    item: T,
+    order: &'a F,
+}
+
+impl<T, F: Fn(&T, &T) -> std::cmp::Ordering> Ord for Ranked<'_, T, F> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.order)(&self.item, &other.item)
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> std::cmp::Ordering> PartialOrd for Ranked<'_, T, F> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> std::cmp::Ordering> PartialEq for Ranked<'_, T, F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> std::cmp::Ordering> Eq for Ranked<'_, T, F> {}
+
+// `k` phần tử đứng đầu theo `order`, đã sắp xếp. Heap giữ tối đa `k` phần tử với phần
+// tử đứng sau cùng ở đỉnh, nên tốn O(n log k) thay vì sắp xếp cả `n` phần tử.
+fn first_k<T>(
+    items: impl IntoIterator<Item = T>,
+    k: usize,
+    order: impl Fn(&T, &T) -> std::cmp::Ordering,
+) -> Vec<T> {
+    let mut heap = std::collections::BinaryHeap::new();
+    for item in items {
+        if heap.len() < k {
+            heap.push(Ranked {
+                item,
+                order: &order,
+            });
+        } else if let Some(mut last) = heap.peek_mut() {
+            if order(&item, &last.item) == std::cmp::Ordering::Less {
+                last.item = item;
+            }
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|ranked| ranked.item)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let text = "The quick brown fox.";
+        let tokens = Tokenizer::tokenize(text);
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, ["the", "quick", "brown", "fox"]);
+        assert_eq!(tokens[3].position, 3);
+        assert_eq!(&text[tokens[3].start..tokens[3].end], "fox");
+    }
+
+    #[test]
+    fn test_tokenize_skips_punctuation_only_words() {
+        let text = "-- Hello, world! --";
+        let tokens = Tokenizer::tokenize(text);
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, ["hello", "world"]);
+        // Vị trí liên tiếp dù có từ bị bỏ
+        assert_eq!(tokens[1].position, 1);
+        assert_eq!(&text[tokens[1].start..tokens[1].end], "world");
+        assert!(Tokenizer::tokenize("  ...  ").is_empty());
+    }
+
+    // IDF = 1 cho mọi term để điểm TF-IDF bằng đúng TF
+    fn unit_idf(terms: &[&str]) -> HashMap<String, f64> {
+        terms.iter().map(|term| (term.to_string(), 1.0)).collect()
+    }
+
+    #[test]
+    fn test_term_frequency_counts_repetition() {
+        let mut doc = Document::new("This document is the second document.");
+        doc.compute_tfidf(&unit_idf(&["document", "second"]));
+        assert!((doc.score("document").unwrap() - 2.0 / 6.0).abs() < 1e-12);
+        assert!((doc.score("second").unwrap() - 1.0 / 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_log_tf_is_sublinear() {
+        let mut doc = Document::new("This document is the second document.");
+        doc.compute_tfidf_with(&unit_idf(&["document", "second"]), TfScheme::Log);
+        assert!((doc.score("document").unwrap() - (1.0 + 2f64.ln())).abs() < 1e-12);
+        assert_eq!(doc.score("second").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_augmented_tf_is_relative_to_most_frequent_term() {
+        let mut doc = Document::new("This document is the second document.");
+        doc.compute_tfidf_with(&unit_idf(&["document", "second"]), TfScheme::Augmented);
+        assert_eq!(doc.score("document").unwrap(), 1.0);
+        assert!((doc.score("second").unwrap() - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_boolean_tf_ignores_repetition() {
+        let mut doc = Document::new("This document is the second document.");
+        doc.compute_tfidf_with(&unit_idf(&["document", "second"]), TfScheme::Boolean);
+        assert_eq!(doc.score("document").unwrap(), 1.0);
+        assert_eq!(doc.score("second").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_terms_without_idf_are_not_scored() {
+        let mut doc = Document::new("Unknown words only.");
+        doc.compute_tfidf(&unit_idf(&["document"]));
+        assert!(doc.tfidf_scores.is_empty());
+
+        let mut doc = Document::new("");
+        doc.compute_tfidf_with(&unit_idf(&["document"]), TfScheme::Augmented);
+        assert!(doc.tfidf_scores.is_empty());
+    }
+
+    #[test]
+    fn test_compute_tfidf() {
+        let mut tfidf = TFIDF::new();
+        let documents = vec![
+            "Blue house.",
+            "Red house.",
+            "Blue window."
+        ];
+        tfidf.fit(&documents);
+
+        let mut doc = Document::new("Blue house.");
+        doc.compute_tfidf(&tfidf.idf_scores);
+
+        let blue_score = doc.score("blue").unwrap();
+        let house_score = doc.score("house").unwrap();
+
+        let expected_blue_score = (1.0 / 2.0) * (f64::ln(3.0 / 2.0) + 1.0);
+        let expected_house_score = (1.0 / 2.0) * (f64::ln(3.0 / 2.0) + 1.0);
+
+        assert!((blue_score - expected_blue_score).abs() < f64::EPSILON);
+        assert!((house_score - expected_house_score).abs() < f64::EPSILON);
+    }
+
+    fn fitted(documents: &[&str]) -> TFIDF {
+        let mut tfidf = TFIDF::new();
+        tfidf.fit(documents);
+        tfidf
+    }
+
+    const CORPUS: [&str; 3] = [
+        "This is the first document.",
+        "This document is the second document.",
+        "And this is the third one.",
+    ];
+
+    #[test]
+    fn test_postings_record_tf_and_positions() {
+        let tfidf = fitted(&CORPUS);
+        let postings = tfidf.index.postings("document");
+        assert_eq!(postings.len(), 2);
+        assert_eq!(
+            postings[1],
+            Posting {
+                doc: 1,
+                tf: 2,
+                positions: vec![1, 5]
+            }
+        );
+        assert_eq!(tfidf.index.document_frequency("this"), 3);
+        assert_eq!(tfidf.index.lengths, [5, 6, 6]);
+    }
+
+    #[test]
+    fn test_lookup_all_intersects_postings() {
+        let tfidf = fitted(&CORPUS);
+        assert_eq!(tfidf.index.lookup_all(&["document", "first"]), vec![0]);
+        assert_eq!(tfidf.index.lookup_all(&["this", "is"]), vec![0, 1, 2]);
+        assert!(tfidf.index.lookup_all(&["document", "missing"]).is_empty());
+        assert!(tfidf.index.lookup_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_any_merges_postings() {
+        let tfidf = fitted(&CORPUS);
+        assert_eq!(tfidf.index.lookup_any(&["second", "third"]), vec![1, 2]);
+        assert_eq!(tfidf.index.lookup_any(&["document", "first"]), vec![0, 1]);
+        assert!(tfidf.index.lookup_any(&["missing"]).is_empty());
+        assert!(tfidf.index.lookup("missing").is_empty());
+    }
+
+    #[test]
+    fn test_search_by_keyword_uses_index() {
+        let tfidf = fitted(&CORPUS);
+        let transformed = tfidf.transform_all(&CORPUS);
+        let results = tfidf.search_by_keyword("document", &transformed);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].content, CORPUS[1]);
+        assert!(tfidf.search_by_keyword("missing", &transformed).is_empty());
+        // Danh sách văn bản ngắn hơn chỉ mục thì bỏ các doc id vượt quá
+        assert_eq!(tfidf.search_by_keyword("this", &transformed[..1]).len(), 1);
+    }
+
+    const RANKED: [&str; 4] = [
+        "This is the first document.",
+        "This document is the second document.",
+        "And this is the third one.",
+        "Is this the first document?",
+    ];
+
+    #[test]
+    fn test_cosine_search_ranks_by_similarity() {
+        let tfidf = fitted(&RANKED);
+        let hits = tfidf.search("second document", 2);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].doc, 1);
+        assert!(hits[0].score > hits[1].score);
+        assert!(hits[0].score <= 1.0 + 1e-9);
+    }
+
+    #[test]
+    fn test_cosine_of_document_with_itself_is_one() {
+        let tfidf = fitted(&RANKED);
+        let hits = tfidf.search(RANKED[2], 1);
+        assert_eq!(hits[0].doc, 2);
+        assert!((hits[0].score - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bm25_search_ranks_matching_documents() {
+        let mut tfidf = TFIDF::new().with_scoring(Scoring::bm25());
+        tfidf.fit(&RANKED);
+        let hits = tfidf.search("second document", 10);
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].doc, 1);
+        // Văn bản ngắn hơn được ưu tiên khi cùng tf
+        assert_eq!(hits[1].doc, 0);
+    }
+
+    #[test]
+    fn test_bm25_parameters_change_scores() {
+        let score = |k1: f64, b: f64| {
+            let mut tfidf = TFIDF::new().with_scoring(Scoring::Bm25 { k1, b });
+            tfidf.fit(&RANKED);
+            tfidf.search("document", 1)[0].score
+        };
+        // k1 = 0 bỏ qua tf, chỉ còn IDF
+        let idf = (1.0 + (4.0 - 3.0 + 0.5) / (3.0 + 0.5_f64)).ln();
+        assert!((score(0.0, 0.75) - idf).abs() < 1e-12);
+        assert!(score(1.2, 0.75) > score(0.0, 0.75));
+    }
+
+    #[test]
+    fn test_search_without_matches_is_empty() {
+        let tfidf = fitted(&RANKED);
+        assert!(tfidf.search("missing", 10).is_empty());
+        assert!(tfidf.search("", 10).is_empty());
+        assert!(tfidf.search("document", 0).is_empty());
+        assert!(TFIDF::new().search("document", 10).is_empty());
+    }
+
+    const BOOLEAN: [&str; 4] = [
+        "Machine learning for product search.",
+        "Learning machine repair.",
+        "Deep learning and machine vision.",
+        "Product catalog without learning.",
+    ];
+
+    // Doc id khớp truy vấn, đã sắp xếp
+    fn matching(tfidf: &TFIDF, input: &str) -> Vec<usize> {
+        let mut docs: Vec<usize> = tfidf
+            .query(input, 10)
+            .unwrap()
+            .into_iter()
+            .map(|h| h.doc)
+            .collect();
+        docs.sort();
+        docs
+    }
+
+    #[test]
+    fn test_phrase_query_requires_adjacent_terms_in_order() {
+        let tfidf = fitted(&BOOLEAN);
+        assert_eq!(matching(&tfidf, "\"machine learning\""), vec![0]);
+        assert_eq!(matching(&tfidf, "\"learning machine\""), vec![1]);
+        assert!(matching(&tfidf, "\"machine search\"").is_empty());
+    }
+
+    #[test]
+    fn test_adjacent_terms_are_joined_with_and() {
+        let tfidf = fitted(&BOOLEAN);
+        assert_eq!(matching(&tfidf, "machine learning"), vec![0, 1, 2]);
+        assert_eq!(
+            matching(&tfidf, "machine learning"),
+            matching(&tfidf, "machine AND learning")
+        );
+    }
+
+    #[test]
+    fn test_not_binds_tighter_than_and_and_or() {
+        let tfidf = fitted(&BOOLEAN);
+        assert_eq!(
+            matching(&tfidf, "machine AND NOT \"machine learning\""),
+            vec![1, 2]
+        );
+        assert_eq!(matching(&tfidf, "product OR vision NOT deep"), vec![0, 3]);
+        assert_eq!(matching(&tfidf, "NOT learning"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_parentheses_group_subqueries() {
+        let tfidf = fitted(&BOOLEAN);
+        assert_eq!(
+            matching(&tfidf, "(vision OR repair) AND learning"),
+            vec![1, 2]
+        );
+        assert_eq!(matching(&tfidf, "vision OR (repair AND deep)"), vec![2]);
+    }
+
+    #[test]
+    fn test_field_prefix_limits_subquery_to_field() {
+        let tfidf = fitted(&BOOLEAN);
+        assert_eq!(matching(&tfidf, "content:catalog"), vec![3]);
+        assert!(matching(&tfidf, "title:catalog").is_empty());
+    }
+
+    #[test]
+    fn test_query_matches_are_ranked() {
+        let tfidf = fitted(&BOOLEAN);
+        let hits = tfidf
+            .query("learning AND (product OR machine)", 10)
+            .unwrap();
+        assert_eq!(hits.len(), 4);
+        assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+        assert_eq!(tfidf.query("learning", 2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_malformed_queries_are_rejected() {
+        assert_eq!(Query::parse("(machine"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(Query::parse("\"machine"), Err(ParseError::UnclosedQuote));
+        assert_eq!(Query::parse("machine AND"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            Query::parse("machine)"),
+            Err(ParseError::Unexpected("Close".to_string()))
+        );
+        assert_eq!(Query::parse(""), Err(ParseError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_operand_without_terms_is_rejected() {
+        let tfidf = fitted(&BOOLEAN);
+        let empty = Err(ParseError::Empty("!!".to_string()));
+        assert_eq!(tfidf.query("machine AND \"!!\"", 10), empty);
+        assert_eq!(tfidf.query("NOT \"!!\"", 10), empty);
+        assert_eq!(tfidf.query("machine OR !!", 10), empty);
+        assert_eq!(Query::parse("\"\""), Err(ParseError::Empty(String::new())));
+    }
+
+    fn texts(tokens: Vec<Token>) -> Vec<String> {
+        tokens.into_iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn test_nfc_composes_vietnamese_marks() {
+        // "máy tính" viết ở dạng tổ hợp (NFD)
+        assert_eq!(nfc("ma\u{301}y ti\u{301}nh"), "máy tính");
+        // Dấu mũ rồi dấu thanh: "ế"
+        assert_eq!(nfc("e\u{302}\u{301}"), "ế");
+        // Dấu không ghép được thì giữ nguyên
+        assert_eq!(nfc("b\u{301}"), "b\u{301}");
+    }
+
+    #[test]
+    fn test_fold_removes_diacritics() {
+        assert_eq!(fold("Điện thoại"), "Dien thoai");
+        assert_eq!(fold("ĐƯỜNG phố"), "DUONG pho");
+        assert_eq!(fold("plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_syllable_parse() {
+        assert_eq!(
+            Syllable::parse("nghiêng"),
+            Some(Syllable {
+                consonant: Some("ngh".to_string()),
+                vowel: "iê".to_string(),
+                consonant_ending: Some("ng".to_string()),
+            })
+        );
+        assert_eq!(Syllable::parse("áo").unwrap().consonant, None);
+        assert_eq!(Syllable::parse("xyz"), None);
+        assert_eq!(Syllable::parse(""), None);
+    }
+
+    #[test]
+    fn test_vietnamese_joins_dictionary_words() {
+        let text = "Máy tính, máy ảnh.";
+        let tokens = Vietnamese::new().tokenize(text);
+        assert_eq!(texts(tokens.clone()), ["máy_tính", "máy_ảnh"]);
+        assert_eq!(&text[tokens[1].start..tokens[1].end], "máy ảnh");
+        assert_eq!(tokens[1].position, 1);
+        // Khớp dài nhất trước
+        assert_eq!(texts(Vietnamese::new().tokenize("áo sơ mi")), ["áo_sơ_mi"]);
+    }
+
+    #[test]
+    fn test_vietnamese_does_not_join_across_punctuation() {
+        let vietnamese = Vietnamese::new();
+        assert_eq!(texts(vietnamese.tokenize("máy. tính")), ["máy", "tính"]);
+        assert_eq!(texts(vietnamese.tokenize("tính máy")), ["tính", "máy"]);
+    }
+
+    #[test]
+    fn test_vietnamese_folding_matches_unaccented_queries() {
+        let documents = vec![
+            "Máy tính xách tay",
+            "Điện thoại di động",
+            "Bàn phím máy tính",
+        ];
+        let mut tfidf = TFIDF::new().with_analyzer(Vietnamese::new().with_folding(true));
+        tfidf.fit(&documents);
+        let mut docs: Vec<usize> = tfidf
+            .search("may tinh", 10)
+            .into_iter()
+            .map(|h| h.doc)
+            .collect();
+        docs.sort();
+        assert_eq!(docs, [0, 2]);
+        assert_eq!(matching(&tfidf, "\"dien thoai\""), vec![1]);
+    }
+
+    #[test]
+    fn test_vietnamese_folding_can_be_turned_off_again() {
+        let vietnamese = Vietnamese::new().with_folding(true).with_folding(false);
+        assert_eq!(texts(vietnamese.tokenize("Máy tính")), ["máy_tính"]);
+        assert_eq!(texts(vietnamese.tokenize("may tinh")), ["may", "tinh"]);
+
+        let mut vietnamese = Vietnamese::new();
+        vietnamese.add_words(&["bàn phím"]);
+        let vietnamese = vietnamese.with_folding(true);
+        assert_eq!(texts(vietnamese.tokenize("Bàn phím")), ["ban_phim"]);
+    }
+
+    #[test]
+    fn test_pipeline_strips_html_and_keeps_offsets() {
+        let text = "<p>Running <b>shoes</b> and the BOOTS</p>";
+        let pipeline = Pipeline::new(Words)
+            .char_filter(HtmlStrip)
+            .token_filter(Lowercase)
+            .token_filter(Stopwords::english())
+            .token_filter(Stemmer)
+            .token_filter(Synonyms::new(&[&["boot", "shoe"]]));
+        let tokens = pipeline.analyze(text);
+        assert_eq!(
+            texts(tokens.clone()),
+            ["run", "boot", "shoe", "shoe", "boot"]
+        );
+        // Vị trí trỏ về văn bản gốc; stopword để lại khoảng trống vị trí
+        assert_eq!(&text[tokens[1].start..tokens[1].end], "shoes");
+        assert_eq!(tokens[3].position, 4);
+        assert_eq!(tokens[4].position, 4);
+    }
+
+    #[test]
+    fn test_html_strip_keeps_text_after_unclosed_angle_bracket() {
+        let pipeline = Pipeline::new(Words).char_filter(HtmlStrip);
+        assert_eq!(
+            texts(pipeline.analyze("giá < 100k rẻ")),
+            ["giá", "100k", "rẻ"]
+        );
+        // Dấu '<' thứ hai mở thẻ thật, phần trước nó được giữ lại
+        assert_eq!(texts(pipeline.analyze("a < b <i>c</i>")), ["a", "b", "c"]);
+        let tokens = pipeline.analyze("x <y");
+        assert_eq!(tokens[1].text, "y");
+        assert_eq!(tokens[1].start, 3);
+    }
+
+    #[test]
+    fn test_nfc_char_filter_maps_to_decomposed_offsets() {
+        let decomposed = "A\u{301}o so\u{31b}\u{300}"; // "Áo sờ" dạng tổ hợp
+        let tokens = Pipeline::new(Whitespace)
+            .char_filter(Nfc)
+            .token_filter(Lowercase)
+            .analyze(decomposed);
+        assert_eq!(tokens[0].text, "áo");
+        assert_eq!(tokens[1].text, "sờ");
+        assert_eq!(tokens[1].end, decomposed.len());
+    }
+
+    #[test]
+    fn test_vietnamese_stopwords_and_fold_filter() {
+        let folded = Pipeline::new(Words)
+            .token_filter(Lowercase)
+            .token_filter(Stopwords::vietnamese())
+            .token_filter(Fold)
+            .analyze("Áo của Đường");
+        assert_eq!(texts(folded), ["ao", "duong"]);
+    }
+
+    #[test]
+    fn test_ngram_tokenizers() {
+        assert_eq!(
+            texts(Pipeline::new(NGram::edge(1, 3)).analyze("Áo")),
+            ["Á", "Áo"]
+        );
+        assert_eq!(
+            texts(Pipeline::new(NGram::new(2, 2)).analyze("abc")),
+            ["ab", "bc"]
+        );
+        // Từ ngắn hơn `min` không sinh n-gram nào
+        assert!(Pipeline::new(NGram::new(3, 4)).analyze("ab").is_empty());
+    }
+
+    #[test]
+    fn test_stemmer_keeps_short_stems() {
+        assert_eq!(Stemmer::stem("running"), "run");
+        assert_eq!(Stemmer::stem("categories"), "category");
+        assert_eq!(Stemmer::stem("is"), "is");
+        assert_eq!(Stemmer::stem("glass"), "glass");
+    }
+
+    #[test]
+    fn test_length_filter_counts_chars() {
+        let short = Pipeline::new(Words)
+            .token_filter(Length { min: 2, max: 3 })
+            .analyze("a bb cccc ằằ");
+        assert_eq!(texts(short), ["bb", "ằằ"]);
+    }
+
+    #[test]
+    fn test_synonyms_match_at_search_time() {
+        let documents = vec!["Running shoes", "Leather boots", "Red hat"];
+        let mut tfidf = TFIDF::new().with_analyzer(
+            Pipeline::new(Words)
+                .token_filter(Lowercase)
+                .token_filter(Stemmer)
+                .token_filter(Synonyms::new(&[&["boot", "shoe"]])),
+        );
+        tfidf.fit(&documents);
+        let mut docs: Vec<usize> = tfidf.search("shoe", 10).iter().map(|h| h.doc).collect();
+        docs.sort();
+        assert_eq!(docs, [0, 1]);
+    }
+
+    #[test]
+    fn test_field_analyzer_applies_to_its_field_only() {
+        let stemmed = Pipeline::new(Words)
+            .token_filter(Lowercase)
+            .token_filter(Stemmer);
+        let mut tfidf = TFIDF::new().with_field_analyzer("title", stemmed);
+        tfidf.fit_documents(vec![
+            Document::new("Running shoes").with_field("title", "Running shoes"),
+            Document::new("Walking boots").with_field("title", "Walking boots"),
+        ]);
+        assert_eq!(tfidf.fields["title"].index.lookup("run"), [0]);
+        assert!(tfidf.index.lookup("run").is_empty());
+        // Truy vấn được phân tích bằng bộ phân tích của trường được nhắc tới
+        assert_eq!(matching(&tfidf, "title:running"), vec![0]);
+        assert!(matching(&tfidf, "content:run").is_empty());
+        assert_eq!(matching(&tfidf, "content:running"), vec![0]);
+    }
+
+    // Hai mô hình có cùng IDF và cùng chuẩn cho mọi văn bản còn sống
+    fn assert_same_model(actual: &TFIDF, expected: &TFIDF, docs: &[(usize, usize)]) {
+        assert_eq!(actual.idf_scores.len(), expected.idf_scores.len());
+        for (term, idf) in &expected.idf_scores {
+            assert!((actual.idf_scores[term] - idf).abs() < 1e-12, "{}", term);
+        }
+        for &(a, b) in docs {
+            assert!((actual.norms[a] - expected.norms[b]).abs() < 1e-12, "{}", a);
+        }
+    }
+
+    #[test]
+    fn test_add_document_matches_full_fit() {
+        let documents = ["Red shirt for men.", "Blue shirt for women.", "Red shoes."];
+        let mut incremental = fitted(&documents[..2]);
+        assert_eq!(incremental.add_document(documents[2]), 2);
+        incremental.refresh();
+
+        let full = fitted(&documents);
+        assert_same_model(&incremental, &full, &[(0, 0), (1, 1), (2, 2)]);
+        let hits = incremental.search("red shoes", 3);
+        let expected = full.search("red shoes", 3);
+        assert_eq!(hits.len(), expected.len());
+        for (hit, expected) in hits.iter().zip(&expected) {
+            assert_eq!(hit.doc, expected.doc);
+            assert!((hit.score - expected.score).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_search_before_refresh_does_not_panic() {
+        let mut tfidf = fitted(&["cotton shirt", "wool coat"]);
+        tfidf.add_document("cotton socks");
+        let mut docs: Vec<usize> = tfidf.search("cotton", 10).iter().map(|h| h.doc).collect();
+        docs.sort();
+        assert_eq!(docs, [0, 2]);
+        assert_eq!(tfidf.query("cotton", 10).unwrap().len(), 2);
+        assert_eq!(tfidf.more_like_this(0, 10).len(), 1);
+
+        tfidf.refresh();
+        assert_eq!(tfidf.search("socks", 10)[0].doc, 2);
+    }
+
+    #[test]
+    fn test_update_document_keeps_doc_id() {
+        let documents = ["Red shirt for men.", "Blue shirt for women.", "Red shoes."];
+        let mut incremental = fitted(&documents);
+        assert!(incremental.update_document(0, Document::new("Green hat.")));
+        incremental.refresh();
+        assert_eq!(incremental.index.lookup("red"), vec![2]);
+        assert!(incremental.idf_scores.contains_key("green"));
+        assert!(!incremental.idf_scores.contains_key("men"));
+
+        let expected = fitted(&["Green hat.", documents[1], documents[2]]);
+        assert_same_model(&incremental, &expected, &[(0, 0), (1, 1), (2, 2)]);
+        assert!(!incremental.update_document(7, Document::new("Missing.")));
+    }
+
+    #[test]
+    fn test_update_document_reindexes_fields_and_id() {
+        let mut tfidf = TFIDF::new();
+        tfidf.fit_documents([
+            Document::new("Linen shirt")
+                .with_id("a")
+                .with_field("title", "Linen shirt")
+                .with_stored("price", Value::Number(10.0)),
+            Document::new("Wool coat")
+                .with_id("b")
+                .with_field("title", "Wool coat"),
+        ]);
+
+        let updated = Document::new("Cotton dress")
+            .with_id("c")
+            .with_field("title", "Cotton dress")
+            .with_stored("price", Value::Number(25.0));
+        assert!(tfidf.update_document(0, updated));
+        tfidf.refresh();
+
+        assert_eq!(tfidf.find("a"), None);
+        assert_eq!(tfidf.find("c"), Some(0));
+        assert!(matching(&tfidf, "title:linen").is_empty());
+        assert_eq!(matching(&tfidf, "title:dress"), vec![0]);
+        let stored = tfidf.get(0).unwrap();
+        assert_eq!(stored.text("title"), Some("Cotton dress"));
+        assert_eq!(stored.stored.get("price"), Some(&Value::Number(25.0)));
+
+        let expected = {
+            let mut expected = TFIDF::new();
+            expected.fit_documents([
+                Document::new("Cotton dress").with_field("title", "Cotton dress"),
+                Document::new("Wool coat").with_field("title", "Wool coat"),
+            ]);
+            expected
+        };
+        assert_same_model(&tfidf, &expected, &[(0, 0), (1, 1)]);
+
+        // Mã định danh đang thuộc văn bản khác thì không được cập nhật
+        assert!(!tfidf.update_document(0, Document::new("Wool hat").with_id("b")));
+        assert_eq!(tfidf.find("b"), Some(1));
+        assert_eq!(tfidf.get(0).unwrap().content, "Cotton dress");
+    }
+
+    #[test]
+    fn test_remove_document_matches_full_fit() {
+        let documents = ["Red shirt for men.", "Blue shirt for women.", "Red shoes."];
+        let mut incremental = fitted(&documents);
+        assert!(incremental.remove_document(1));
+        assert!(!incremental.remove_document(1));
+        incremental.refresh();
+        assert_eq!(incremental.index.doc_count, 2);
+        assert!(incremental.index.lookup("shirt") == [0]);
+        assert_eq!(incremental.norms[1], 0.0);
+
+        let expected = fitted(&[documents[0], documents[2]]);
+        assert_same_model(&incremental, &expected, &[(0, 0), (2, 1)]);
+        assert_eq!(matching(&incremental, "NOT men"), vec![2]);
+    }
+
+    #[test]
+    fn test_unaffected_norms_follow_document_count() {
+        // Văn bản 0 và 1 không có term chung với văn bản mới: chỉ N thay đổi
+        let documents = ["red shirt", "blue coat", "green hat"];
+        for scheme in [
+            IdfScheme::Sklearn { smooth: false },
+            IdfScheme::Sklearn { smooth: true },
+            IdfScheme::Standard,
+            IdfScheme::Smooth,
+            IdfScheme::Probabilistic,
+        ] {
+            let mut incremental = TFIDF::new().with_idf_scheme(scheme);
+            incremental.fit(&documents[..2]);
+            incremental.add_document(documents[2]);
+            incremental.refresh();
+
+            let mut full = TFIDF::new().with_idf_scheme(scheme);
+            full.fit(&documents);
+            assert_same_model(&incremental, &full, &[(0, 0), (1, 1), (2, 2)]);
+        }
+    }
+
+    #[test]
+    fn test_refresh_after_load_rebuilds_parts() {
+        let mut tfidf = fitted(&["red shirt", "blue coat"]);
+        let path = std::env::temp_dir().join(format!("tfidf-parts-{}.idx", std::process::id()));
+        tfidf.save(&path).unwrap();
+        tfidf = TFIDF::load(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        tfidf.add_document("green hat");
+        tfidf.refresh();
+        assert_same_model(
+            &tfidf,
+            &fitted(&["red shirt", "blue coat", "green hat"]),
+            &[(0, 0), (1, 1), (2, 2)],
+        );
+    }
+
+    #[test]
+    fn test_field_statistics_follow_incremental_changes() {
+        let mut tfidf = TFIDF::new();
+        tfidf.fit_documents(vec![
+            Document::new("a").with_field("title", "Linen shirt"),
+            Document::new("b").with_field("title", "Wool coat"),
+        ]);
+        tfidf.add(Document::new("c").with_field("title", "Linen dress"));
+        tfidf.refresh();
+
+        let mut expected = TFIDF::new();
+        expected.fit_documents(vec![
+            Document::new("a").with_field("title", "Linen shirt"),
+            Document::new("b").with_field("title", "Wool coat"),
+            Document::new("c").with_field("title", "Linen dress"),
+        ]);
+        let (field, other) = (&tfidf.fields["title"], &expected.fields["title"]);
+        assert_eq!(field.idf_scores, other.idf_scores);
+        for doc in 0..3 {
+            assert!((field.norms[doc] - other.norms[doc]).abs() < 1e-12);
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tfidf-{}-{}.idx", name, std::process::id()))
+    }
+
+    // File chỉ mục một văn bản chỉ có term "a"; `postings` là các cặp
+    // (delta doc id, các delta vị trí) ghi thẳng, không qua kiểm tra
+    fn crafted_index(postings: &[(u64, &[u64])]) -> Vec<u8> {
+        let mut payload = Encoder::new();
+        for &(doc, positions) in postings {
+            payload.uint(doc);
+            payload.uint(positions.len() as u64);
+            for &position in positions {
+                payload.uint(position);
+            }
+        }
+        // Danh sách term của văn bản 0 và tên term
+        let forward = payload.bytes.len() as u64;
+        payload.uint(1);
+        payload.uint(0);
+        let name = payload.bytes.len() as u64;
+        payload.bytes.push(b'a');
+        let term_table = payload.bytes.len() as u64;
+        for value in [name, 1, postings.len() as u64] {
+            payload.fixed(value);
+        }
+        payload.float(1.0);
+        payload.fixed(0);
+        let doc_table = payload.bytes.len() as u64;
+        payload.fixed(1);
+        payload.fixed(1);
+        payload.float(1.0);
+        payload.fixed(forward);
+        let footer = payload.bytes.len() as u64;
+        for value in [1, doc_table, 1, term_table] {
+            payload.fixed(value);
+        }
+        // Phần gốc: cấu hình, chỉ mục nội dung; không có trường, boost, từ vựng hay văn
+        // bản lưu trữ
+        let root = payload.bytes.len() as u64;
+        for setting in [0, 0, 2, 0, footer, 0, 0, 0, root, 0] {
+            payload.uint(setting);
+        }
+        payload.fixed(root);
+        framed(MAGIC, &payload.bytes)
+    }
+
+    // Gắn header như `FileWriter` trước payload viết tay
+    fn framed(magic: &[u8; 8], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&checksum(FNV_OFFSET, payload).to_le_bytes());
+        bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn load_bytes(name: &str, bytes: &[u8]) -> Result<TFIDF, PersistError> {
+        let path = temp_path(name);
+        std::fs::write(&path, bytes).unwrap();
+        let loaded = TFIDF::load(&path, Tokenizer);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn test_save_and_load_round_trips_model() {
+        let documents = vec![
+            "This is the first document.",
+            "This document is the second document.",
+            "And this is the third one.",
+        ];
+        let mut tfidf = TFIDF::new().with_scoring(Scoring::bm25());
+        tfidf.fit(&documents);
+        tfidf.remove_document(2);
+        tfidf.refresh();
+
+        let path = temp_path("round-trip");
+        tfidf.save(&path).unwrap();
+        let loaded = TFIDF::load(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.scoring, tfidf.scoring);
+        assert_eq!(loaded.idf_scores, tfidf.idf_scores);
+        // Postings được đọc thẳng từ file, không chép vào bộ nhớ
+        assert!(loaded.index.mapped.is_some() && loaded.index.postings.is_empty());
+        let mut terms = loaded.index.term_list();
+        terms.sort_unstable();
+        let mut expected = tfidf.index.term_list();
+        expected.sort_unstable();
+        assert_eq!(terms, expected);
+        for term in terms {
+            assert_eq!(loaded.index.postings(term), tfidf.index.postings(term));
+        }
+        for doc in 0..3 {
+            let mut terms = tfidf.index.doc_terms(doc);
+            terms.sort_unstable();
+            assert_eq!(loaded.index.doc_terms(doc), terms);
+        }
+        assert_eq!(loaded.index.doc_count, 2);
+        assert_eq!(loaded.norms, tfidf.norms);
+        assert_eq!(
+            loaded.search("second document", 3),
+            tfidf.search("second document", 3)
+        );
+        assert_eq!(loaded.query("\"first document\"", 3).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_crafted_index_loads() {
+        let loaded = load_bytes("crafted", &crafted_index(&[(0, &[4])])).unwrap();
+        assert_eq!(loaded.index.postings("a")[0].positions, [4]);
+        assert_eq!(loaded.index.document_frequency("a"), 1);
+        assert_eq!(loaded.index.doc_terms(0), ["a"]);
+        assert!(loaded.index.postings("b").is_empty());
+    }
+
+    #[test]
+    fn test_load_rejects_corrupted_payload() {
+        let mut bytes = crafted_index(&[(0, &[0])]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(matches!(
+            load_bytes("checksum", &bytes),
+            Err(PersistError::Checksum)
+        ));
+
+        // Payload ngắn hơn độ dài ghi trong header
+        let mut bytes = crafted_index(&[(0, &[0])]);
+        bytes.pop();
+        assert!(matches!(
+            load_bytes("truncated", &bytes),
+            Err(PersistError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_other_versions_and_files() {
+        let mut bytes = crafted_index(&[(0, &[0])]);
+        bytes[8..12].copy_from_slice(&0u32.to_le_bytes());
+        assert!(matches!(
+            load_bytes("version", &bytes),
+            Err(PersistError::Version(0))
+        ));
+        assert!(matches!(
+            load_bytes("magic", b"not an index"),
+            Err(PersistError::Magic)
+        ));
+        assert!(matches!(
+            TFIDF::load(temp_path("missing"), Tokenizer),
+            Err(PersistError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_overflowing_deltas() {
+        // Checksum hợp lệ nhưng cộng dồn delta tràn usize
+        let positions = [usize::MAX as u64, 1];
+        assert!(matches!(
+            load_bytes("position-overflow", &crafted_index(&[(0, &positions)])),
+            Err(PersistError::Corrupt)
+        ));
+        assert!(matches!(
+            load_bytes(
+                "doc-overflow",
+                &crafted_index(&[(0, &[0]), (u64::MAX, &[0])])
+            ),
+            Err(PersistError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_duplicate_postings() {
+        assert!(matches!(
+            load_bytes("duplicate", &crafted_index(&[(0, &[0]), (0, &[0])])),
+            Err(PersistError::Corrupt)
+        ));
+    }
+
+    fn catalog() -> TFIDF {
+        let mut tfidf = TFIDF::new().with_boost("title", 3.0);
+        tfidf.fit_documents(vec![
+            Document::new("A quiet shirt for summer days.")
+                .with_id("sku-1")
+                .with_field("title", "Linen shirt")
+                .with_stored("price", Value::Number(25.0)),
+            Document::new("Linen is a breathable fabric used in many shirts.")
+                .with_id("sku-2")
+                .with_field("title", "Cotton dress")
+                .with_stored("price", Value::Number(40.0)),
+        ]);
+        tfidf
+    }
+
+    #[test]
+    fn test_search_results_carry_ids_stored_fields_and_snippets() {
+        // Khớp ở tiêu đề được nhân boost nên xếp trên khớp ở nội dung
+        let results = catalog().search_results("linen", 2);
+        assert_eq!(results[0].id, "sku-1");
+        assert_eq!(results[0].stored["price"], Value::Number(25.0));
+        assert_eq!(results[1].id, "sku-2");
+        assert_eq!(
+            results[1].snippet,
+            "<em>Linen</em> is a breathable fabric used in many shirts."
+        );
+    }
+
+    #[test]
+    fn test_boost_changes_field_ranking() {
+        let tfidf = catalog().with_boost("title", 0.1);
+        assert_eq!(tfidf.search_results("linen", 2)[0].id, "sku-2");
+    }
+
+    #[test]
+    fn test_field_query_limits_to_field() {
+        let tfidf = catalog();
+        assert_eq!(matching(&tfidf, "title:cotton"), vec![1]);
+        assert!(matching(&tfidf, "title:summer").is_empty());
+        assert_eq!(matching(&tfidf, "summer"), vec![0]);
+    }
+
+    #[test]
+    fn test_adding_same_id_replaces_document() {
+        let mut tfidf = catalog();
+        let doc = tfidf.add(
+            Document::new("Wool coat.")
+                .with_id("sku-1")
+                .with_field("title", "Coat"),
+        );
+        tfidf.refresh();
+        assert_eq!(doc, 0);
+        assert_eq!(tfidf.find("sku-1"), Some(0));
+        assert_eq!(tfidf.get(0).unwrap().text("title"), Some("Coat"));
+        assert!(tfidf.get(0).unwrap().stored.is_empty());
+        let hits: Vec<usize> = tfidf.search("linen", 10).iter().map(|h| h.doc).collect();
+        assert_eq!(hits, [1]);
+    }
+
+    #[test]
+    fn test_documents_without_id_are_not_addressable() {
+        let mut tfidf = catalog();
+        let doc = tfidf.add(Document::new("Silk scarf."));
+        assert_eq!(doc, 2);
+        assert_eq!(tfidf.find(""), None);
+        assert_eq!(tfidf.find("sku-9"), None);
+        assert!(tfidf.get(9).is_none());
+        tfidf.remove_document(doc);
+        assert!(tfidf.get(doc).is_none());
+    }
+
+    #[test]
+    fn test_ids_and_stored_fields_survive_save_and_load() {
+        let tfidf = catalog();
+        let path = temp_path("fields");
+        tfidf.save(&path).unwrap();
+        let loaded = TFIDF::load(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.find("sku-2"), Some(1));
+        assert_eq!(loaded.get(1).unwrap().text("title"), Some("Cotton dress"));
+        assert_eq!(
+            loaded.search_results("linen", 2),
+            tfidf.search_results("linen", 2)
+        );
+    }
+
+    #[test]
+    fn test_loaded_model_copies_index_on_first_change() {
+        let tfidf = catalog();
+        let path = temp_path("mapped");
+        tfidf.save(&path).unwrap();
+        let mut loaded = TFIDF::load(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.store.mapped.is_some());
+        assert!(loaded.fields["title"].index.mapped.is_some());
+
+        // Xóa văn bản chép chỉ mục và kho văn bản vào bộ nhớ rồi sửa như bình thường
+        assert!(loaded.remove_document(0));
+        loaded.refresh();
+        assert!(loaded.index.mapped.is_none() && loaded.store.mapped.is_none());
+        assert!(loaded.fields["title"].index.mapped.is_none());
+        assert_eq!(loaded.find("sku-1"), None);
+        assert_eq!(loaded.get(1).unwrap().id, "sku-2");
+        let mut expected = catalog();
+        expected.remove_document(0);
+        expected.refresh();
+        assert_eq!(loaded.search("linen", 2), expected.search("linen", 2));
+    }
+
+    #[test]
+    fn test_load_uses_given_analyzer() {
+        let codes = || Pipeline::new(Whitespace);
+        let mut tfidf = TFIDF::new().with_analyzer(codes());
+        tfidf.fit(&["LS-02 linen shirt", "CT-01 cotton tee"]);
+        let path = temp_path("analyzer");
+        tfidf.save(&path).unwrap();
+        let loaded = TFIDF::load(&path, codes()).unwrap();
+        let default = TFIDF::load(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.search("LS-02", 1), tfidf.search("LS-02", 1));
+        assert_eq!(loaded.search("LS-02", 1)[0].doc, 0);
+        // Tokenizer mặc định tách "LS-02" thành term không có trong chỉ mục
+        assert!(default.search("LS-02", 1).is_empty());
+    }
+
+    const SHOP: &str = "Cửa hàng mở cửa lúc tám giờ sáng. Áo sơ mi lụa tơ tằm được may thủ công \
+                        tại Hà Nội. Giao hàng toàn quốc trong ba ngày.";
+
+    fn shop(highlighter: Highlighter) -> TFIDF {
+        let mut tfidf = TFIDF::new().with_highlighter(highlighter);
+        tfidf.fit(&[SHOP, "Áo khoác len.", "Quần lụa."]);
+        tfidf
+    }
+
+    #[test]
+    fn test_highlight_picks_best_fragment() {
+        let tfidf = shop(
+            Highlighter::new()
+                .with_fragment_len(40)
+                .with_markers("[", "]"),
+        );
+        let fragments = tfidf.highlight("áo lụa", SHOP);
+        assert_eq!(fragments.len(), 1);
+        assert!(fragments[0].text.starts_with("[Áo] sơ mi [lụa]"));
+        assert!(SHOP[fragments[0].start..].starts_with("Áo sơ mi"));
+    }
+
+    #[test]
+    fn test_fragment_len_counts_chars() {
+        // Mỗi ký tự "ầ" dài 3 byte: đếm theo byte thì hai token không vừa một đoạn
+        let text = "ầầầ ầầầ ầầầ";
+        let tfidf = shop(Highlighter::new().with_fragment_len(7));
+        let fragments = tfidf.highlight("ầầầ", text);
+        assert_eq!(fragments[0].text, "<em>ầầầ</em> <em>ầầầ</em>");
+    }
+
+    #[test]
+    fn test_snippet_joins_non_overlapping_fragments_in_order() {
+        let tfidf = shop(
+            Highlighter::new()
+                .with_fragment_len(20)
+                .with_fragments(2)
+                .with_separator(" | "),
+        );
+        assert_eq!(
+            tfidf.snippet("cửa giao", SHOP),
+            "<em>Cửa</em> hàng mở <em>cửa</em> lúc | <em>Giao</em> hàng toàn quốc"
+        );
+    }
+
+    #[test]
+    fn test_snippet_without_matches_returns_text_start() {
+        let tfidf = shop(Highlighter::new().with_fragment_len(20));
+        assert_eq!(tfidf.snippet("len", SHOP), "Cửa hàng mở cửa lúc");
+        assert!(tfidf.highlight("áo", "").is_empty());
+        assert!(tfidf.highlight("áo", "...").is_empty());
+    }
+
+    const GADGETS: [&str; 3] = [
+        "Samsung galaxy phone with a large screen.",
+        "Apple iphone charger and cable.",
+        "Samsonite travel luggage.",
+    ];
+
+    fn hit_docs(hits: &[Hit]) -> Vec<usize> {
+        hits.iter().map(|h| h.doc).collect()
+    }
+
+    #[test]
+    fn test_levenshtein_counts_chars() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("áo", "ao"), 1);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("same", "same"), 0);
+    }
+
+    #[test]
+    fn test_bk_tree_finds_terms_within_distance() {
+        assert!(BkTree::default().find("shirt", 2).is_empty());
+        let mut tree = BkTree::default();
+        for term in ["shirt", "shirts", "skirt", "short", "coat", "shirt"] {
+            tree.insert(term);
+        }
+        assert_eq!(tree.nodes.len(), 5);
+        let mut found = tree.find("shirt", 1);
+        found.sort();
+        assert_eq!(
+            found,
+            [("shirt", 0), ("shirts", 1), ("short", 1), ("skirt", 1)]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_search_tolerates_typos() {
+        let tfidf = fitted(&GADGETS);
+        assert!(tfidf.search("samsnug", 3).is_empty());
+        assert_eq!(hit_docs(&tfidf.search_fuzzy("samsnug galaxi", 3)), [0]);
+        // Term ngắn không được sửa để tránh khớp nhầm
+        assert!(tfidf.search_fuzzy("apl", 3).is_empty());
+    }
+
+    #[test]
+    fn test_exact_match_ranks_above_fuzzy_match() {
+        let mut tfidf = TFIDF::new().with_fuzzy(Fuzzy::new(1));
+        tfidf.fit(&["red cable", "red cables"]);
+        let hits = tfidf.search_fuzzy("cables", 2);
+        assert_eq!(hits[0].doc, 1);
+        assert!(hits[1].score < hits[0].score);
+    }
+
+    #[test]
+    fn test_penalty_scales_fuzzy_scores() {
+        let score = |penalty: f64| {
+            let mut tfidf = TFIDF::new().with_fuzzy(Fuzzy::new(1).with_penalty(penalty));
+            tfidf.fit(&["red cable", "red cables"]);
+            let hits = tfidf.search_fuzzy("cables", 2);
+            hits.iter().find(|h| h.doc == 0).unwrap().score
+        };
+        assert!((score(0.5) / score(0.25) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_max_expansions_keeps_best_terms() {
+        let documents = ["shirt", "shirts", "skirt"];
+        let mut tfidf = TFIDF::new().with_fuzzy(Fuzzy::new(2));
+        tfidf.fit(&documents);
+        assert_eq!(tfidf.search_fuzzy("shirt", 3).len(), 3);
+
+        let mut tfidf = TFIDF::new().with_fuzzy(Fuzzy::new(2).with_max_expansions(1));
+        tfidf.fit(&documents);
+        assert_eq!(hit_docs(&tfidf.search_fuzzy("shirt", 3)), [0]);
+    }
+
+    #[test]
+    fn test_prefix_search_completes_last_term() {
+        let tfidf = fitted(&GADGETS);
+        assert_eq!(hit_docs(&tfidf.search_prefix("travel sams", 3)), [2, 0]);
+        // Chỉ term cuối được khớp theo tiền tố
+        assert_eq!(hit_docs(&tfidf.search_prefix("sams travel", 3)), [2]);
+    }
+
+    #[test]
+    fn test_removed_terms_are_not_expanded() {
+        let mut tfidf = fitted(&GADGETS);
+        tfidf.remove_document(2);
+        tfidf.refresh();
+        assert!(tfidf.search_fuzzy("samsonit", 3).is_empty());
+        assert!(tfidf.search_prefix("samso", 3).is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_search_uses_field_lexicon() {
+        let mut tfidf = TFIDF::new();
+        tfidf.fit_documents(vec![
+            Document::new("Travel bag.").with_field("title", "Samsonite luggage"),
+            Document::new("Phone case.").with_field("title", "Galaxy cover"),
+        ]);
+        // "samsonite" chỉ có trong tiêu đề, không có trong nội dung
+        assert_eq!(hit_docs(&tfidf.search_fuzzy("samsonit", 2)), [0]);
+        assert_eq!(hit_docs(&tfidf.search_prefix("gala", 2)), [1]);
+
+        tfidf.remove_document(0);
+        tfidf.refresh();
+        assert!(tfidf.search_fuzzy("samsonit", 2).is_empty());
+    }
+
+    const LEARNING: [&str; 3] = [
+        "Machine learning models. Machine learning in the cloud. Deep learning.",
+        "The cloud is a server.",
+        "A model of a house.",
+    ];
+
+    fn phrases(tfidf: &TFIDF, k: usize, diversity: f64) -> Vec<String> {
+        tfidf
+            .extract_keyphrases(0, k, diversity)
+            .into_iter()
+            .map(|k| k.phrase)
+            .collect()
+    }
+
+    #[test]
+    fn test_keyphrases_rank_by_score() {
+        let tfidf = fitted(&LEARNING);
+        // Điểm theo IDF trung bình nên cụm dài không tự động đứng đầu
+        assert_eq!(
+            phrases(&tfidf, 4, 0.0),
+            ["learning", "machine", "machine learning", "deep"]
+        );
+    }
+
+    #[test]
+    fn test_keyphrases_do_not_cross_stopwords_or_punctuation() {
+        let tfidf = fitted(&LEARNING);
+        let all = phrases(&tfidf, 100, 0.0);
+        assert!(all.contains(&"machine learning models".to_string()));
+        assert!(all.contains(&"deep learning".to_string()));
+        for phrase in &all {
+            let words: Vec<&str> = phrase.split(' ').collect();
+            assert!(!tfidf.stopwords.contains(words[0]), "{}", phrase);
+            assert!(
+                !tfidf.stopwords.contains(words[words.len() - 1]),
+                "{}",
+                phrase
+            );
+        }
+        // "models. machine" và "learning. deep" vượt qua dấu chấm
+        assert!(!all.iter().any(|p| p.contains("models machine")));
+        assert!(!all.iter().any(|p| p.contains("learning deep")));
+    }
+
+    #[test]
+    fn test_keyphrase_score_uses_mean_idf() {
+        let tfidf = fitted(&LEARNING);
+        let keyphrases = tfidf.extract_keyphrases(0, 100, 0.0);
+        let score = |phrase: &str| {
+            keyphrases
+                .iter()
+                .find(|k| k.phrase == phrase)
+                .unwrap()
+                .score
+        };
+        let (machine, learning) = (tfidf.idf_scores["machine"], tfidf.idf_scores["learning"]);
+        // Văn bản 0 có 10 token: "machine" 2 lần, "learning" 3 lần, "machine learning" 2 lần
+        assert!((score("machine") - 2.0 / 10.0 * machine).abs() < 1e-12);
+        assert!((score("learning") - 3.0 / 10.0 * learning).abs() < 1e-12);
+        assert!(
+            (score("machine learning") - 2.0 / 10.0 * (machine + learning) / 2.0).abs() < 1e-12
+        );
+    }
+
+    #[test]
+    fn test_diversity_demotes_overlapping_phrases() {
+        let tfidf = fitted(&LEARNING);
+        // Không đa dạng thì "machine learning" trùng từ với hai cụm đứng trước
+        assert_eq!(
+            phrases(&tfidf, 3, 0.0),
+            ["learning", "machine", "machine learning"]
+        );
+        assert_eq!(phrases(&tfidf, 3, 0.7), ["learning", "machine", "deep"]);
+    }
+
+    #[test]
+    fn test_keyphrases_follow_configured_stopwords() {
+        // Khi "in" và "the" không còn là từ dừng, cụm có thể bắt đầu hay kết thúc bằng chúng
+        let mut tfidf = TFIDF::new().with_stopwords(Stopwords::new(&["machine"]));
+        tfidf.fit(&LEARNING);
+        let all = phrases(&tfidf, 100, 0.0);
+        assert!(all.contains(&"in the cloud".to_string()));
+        assert!(!all
+            .iter()
+            .any(|p| p.starts_with("machine") || p.ends_with("machine")));
+        assert!(all.contains(&"learning models".to_string()));
+    }
+
+    #[test]
+    fn test_keyphrases_of_missing_or_empty_documents() {
+        let mut tfidf = fitted(&LEARNING);
+        assert!(tfidf.extract_keyphrases(7, 3, 0.5).is_empty());
+        assert!(tfidf.keyphrases("", 3, 0.5).is_empty());
+        // Chỉ có từ dừng thì không có cụm nào
+        assert!(tfidf.keyphrases("the of a", 3, 0.5).is_empty());
+        tfidf.remove_document(0);
+        assert!(tfidf.extract_keyphrases(0, 3, 0.5).is_empty());
+    }
+
+    const CLOTHES: [&str; 4] = [
+        "Red cotton shirt with short sleeves.",
+        "Blue denim jeans.",
+        "Red cotton shirt with long sleeves.",
+        "Green cotton socks.",
+    ];
+
+    #[test]
+    fn test_sparse_vector_sorts_and_merges_pairs() {
+        let a = SparseVector::from_pairs(vec![(3, 1.0), (0, 2.0), (3, 1.0)]);
+        assert_eq!(a.indices, [0, 3]);
+        assert_eq!(a.values, [2.0, 2.0]);
+        assert_eq!(a.get(3), Some(2.0));
+        assert_eq!(a.get(1), None);
+        assert!(SparseVector::from_pairs(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_sparse_vector_dot_and_cosine() {
+        let a = SparseVector::from_pairs(vec![(0, 2.0), (3, 2.0)]);
+        let b = SparseVector::from_pairs(vec![(0, 1.0), (5, 4.0)]);
+        assert!((a.dot(&b) - 2.0).abs() < 1e-12);
+        assert!((a.cosine(&b) - 2.0 / (8.0_f64.sqrt() * 17.0_f64.sqrt())).abs() < 1e-12);
+        assert!((a.cosine(&a) - 1.0).abs() < 1e-12);
+        assert_eq!(a.cosine(&SparseVector::default()), 0.0);
+    }
+
+    #[test]
+    fn test_more_like_this_ranks_similar_documents() {
+        let tfidf = fitted(&CLOTHES);
+        let hits = tfidf.more_like_this(0, 3);
+        assert_eq!(hit_docs(&hits), [2, 3]);
+        // Điểm bằng cosine giữa các vector thưa
+        let expected = tfidf.vector(0).unwrap().cosine(&tfidf.vector(2).unwrap());
+        assert!((hits[0].score - expected).abs() < 1e-12);
+        assert!(tfidf.more_like_this(9, 3).is_empty());
+    }
+
+    #[test]
+    fn test_transformed_documents_use_model_vocabulary() {
+        let tfidf = fitted(&CLOTHES);
+        let transformed = tfidf.transform_all(&CLOTHES);
+        assert!(Arc::ptr_eq(&transformed[0].vocabulary, &tfidf.vocabulary));
+        let similarity = transformed[0]
+            .tfidf_scores
+            .cosine(&transformed[2].tfidf_scores);
+        let expected = tfidf.vector(0).unwrap().cosine(&tfidf.vector(2).unwrap());
+        assert!((similarity - expected).abs() < 1e-12);
+        assert!(transformed[1].score("denim").unwrap() > 0.0);
+        assert_eq!(transformed[1].score("cotton"), None);
+
+        assert_eq!(tfidf.vocabulary.len(), tfidf.idf_scores.len());
+        assert!(tfidf.vectorize("unknown words").is_empty());
+    }
+
+    #[test]
+    fn test_standalone_documents_are_mapped_to_model_ids() {
+        let tfidf = fitted(&CLOTHES);
+        let mut document = Document::new("Green denim socks.");
+        document.compute_tfidf(&tfidf.idf_scores);
+        assert!(!Arc::ptr_eq(&document.vocabulary, &tfidf.vocabulary));
+        let vector = &tfidf.vectors(&[document])[0];
+        assert_eq!(vector, &tfidf.vectorize("Green denim socks."));
+    }
+
+    #[test]
+    fn test_vocabulary_ids_are_stable() {
+        let mut tfidf = fitted(&CLOTHES);
+        let ids: Vec<(String, u32)> = tfidf
+            .vocabulary
+            .terms
+            .iter()
+            .map(|term| (term.clone(), tfidf.vocabulary.id(term).unwrap()))
+            .collect();
+        let before = tfidf.transform(CLOTHES[1]);
+
+        // Term bị xóa vẫn giữ id, term mới được cấp id tiếp theo
+        tfidf.remove_document(1);
+        tfidf.add_document("Black wool coat.");
+        tfidf.refresh();
+        for (term, id) in &ids {
+            assert_eq!(tfidf.vocabulary.id(term), Some(*id));
+        }
+        assert_eq!(tfidf.vocabulary.id("black"), Some(ids.len() as u32));
+        // Văn bản transform trước đó giữ bản từ vựng cũ và vẫn đọc được
+        assert!(!Arc::ptr_eq(&before.vocabulary, &tfidf.vocabulary));
+        assert!(before.score("denim").unwrap() > 0.0);
+        assert_eq!(
+            tfidf.vectors(std::slice::from_ref(&before)),
+            [before.tfidf_scores]
+        );
+
+        let path = temp_path("vocabulary");
+        tfidf.save(&path).unwrap();
+        let loaded = TFIDF::load(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.vocabulary.terms, tfidf.vocabulary.terms);
+        assert_eq!(loaded.vector(0), tfidf.vector(0));
+    }
+
+    fn products(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!("product {} in category {} with tag {}", i, i % 7, i % 3))
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_fit_matches_sequential_fit() {
+        let documents = products(50);
+        let documents: Vec<&str> = documents.iter().map(String::as_str).collect();
+        let sequential = fitted(&documents);
+        // Nhiều luồng hơn văn bản, và 0 luồng được coi như 1
+        for threads in [0, 4, 64] {
+            let mut parallel = TFIDF::new();
+            parallel.fit_parallel(&documents, threads);
+            assert_eq!(parallel.index.doc_count, 50);
+            assert_eq!(parallel.idf_scores, sequential.idf_scores);
+            assert_eq!(parallel.index.postings("3"), sequential.index.postings("3"));
+            // Cùng kết quả, sai khác chỉ do thứ tự cộng số thực
+            let hits = parallel.search("category 3", 10);
+            let expected = sequential.search("category 3", 10);
+            // Các văn bản cùng điểm có thể đổi chỗ nên chỉ so sánh điểm
+            assert_eq!(hits[0].doc, expected[0].doc);
+            for (hit, expected) in hits.iter().zip(&expected) {
+                assert!((hit.score - expected.score).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_parallel_keeps_order() {
+        let documents = products(50);
+        let documents: Vec<&str> = documents.iter().map(String::as_str).collect();
+        let tfidf = fitted(&documents);
+        let expected = tfidf.transform_all(&documents);
+        let transformed = tfidf.transform_parallel(&documents, 3);
+        assert_eq!(transformed.len(), expected.len());
+        for (document, expected) in transformed.iter().zip(&expected) {
+            assert_eq!(document.content, expected.content);
+            assert_eq!(document.tfidf_scores, expected.tfidf_scores);
+        }
+        assert!(tfidf.transform_parallel(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn test_fit_reader_streams_lines() {
+        let documents = products(50);
+        let mut streamed = TFIDF::new().with_store_content(false);
+        let count = streamed
+            .fit_reader(std::io::Cursor::new(documents.join("\n")), 2)
+            .unwrap();
+        assert_eq!(count, 50);
+        let documents: Vec<&str> = documents.iter().map(String::as_str).collect();
+        assert_eq!(streamed.idf_scores, fitted(&documents).idf_scores);
+        // Không giữ văn bản gốc
+        assert_eq!(streamed.get(3).unwrap().content, "");
+    }
+
+    #[test]
+    fn test_fit_stream_merges_spilled_batches() {
+        let dir = std::env::temp_dir().join(format!("tfidf-spill-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Hơn hai lô với một luồng nên chỉ mục được trộn từ ba file tạm
+        let documents = products(2 * BATCH_SIZE + 100);
+        let mut streamed = TFIDF::new().with_spill_dir(&dir);
+        streamed.fit_stream(documents.clone(), 1).unwrap();
+        // Chỉ còn file kết quả, đang được ánh xạ
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(streamed.index.mapped.is_some());
+
+        let documents: Vec<&str> = documents.iter().map(String::as_str).collect();
+        let expected = fitted(&documents);
+        assert_eq!(streamed.index.doc_count, documents.len());
+        assert_eq!(streamed.idf_scores, expected.idf_scores);
+        assert_eq!(streamed.vocabulary.terms, expected.vocabulary.terms);
+        assert_eq!(streamed.index.postings("3"), expected.index.postings("3"));
+        // Chỉ mục trong bộ nhớ không giữ thứ tự term của văn bản
+        let mut terms = expected.index.doc_terms(2000);
+        terms.sort_unstable();
+        assert_eq!(streamed.index.doc_terms(2000), terms);
+        for (norm, expected) in streamed.norms.iter().zip(&expected.norms) {
+            assert!((norm - expected).abs() < 1e-12);
+        }
+        assert_eq!(streamed.get(1500).unwrap().content, documents[1500]);
+        // Các văn bản cùng điểm có thể đổi chỗ nên chỉ so sánh điểm
+        let hits = streamed.search("category 3 tag 1", 5);
+        for (hit, expected) in hits.iter().zip(&expected.search("category 3 tag 1", 5)) {
+            assert!((hit.score - expected.score).abs() < 1e-12);
+        }
+
+        // Sửa sau khi fit chép chỉ mục vào bộ nhớ như mô hình đã nạp
+        streamed.add_document("product wool");
+        streamed.refresh();
+        assert_eq!(hit_docs(&streamed.search("wool", 10)), [documents.len()]);
+        drop(streamed);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    // Nguồn đọc hỏng sau phần dữ liệu đầu
+    struct Broken;
+
+    impl std::io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk error"))
+        }
+    }
+
+    #[test]
+    fn test_fit_reader_error_keeps_previous_model() {
+        let mut tfidf = TFIDF::new().with_scoring(Scoring::bm25());
+        tfidf.fit(&["red shirt", "blue coat"]);
+        let reader = std::io::BufReader::new(std::io::Read::chain(
+            std::io::Cursor::new("green hat\nyellow scarf\n"),
+            Broken,
+        ));
+        assert!(tfidf.fit_reader(reader, 2).is_err());
+        assert_eq!(tfidf.index.doc_count, 2);
+        assert_eq!(hit_docs(&tfidf.search("coat", 10)), [1]);
+        assert!(tfidf.search("green", 10).is_empty());
+        assert_eq!(tfidf.scoring, Scoring::bm25());
+
+        // Lần fit thành công giữ cấu hình của mô hình
+        tfidf
+            .fit_reader(std::io::Cursor::new("green hat\nyellow scarf"), 2)
+            .unwrap();
+        assert_eq!(hit_docs(&tfidf.search("green", 10)), [0]);
+        assert_eq!(tfidf.scoring, Scoring::bm25());
+    }
+
+    // Bộ văn bản mẫu trong tài liệu của scikit-learn
+    const SKLEARN: [&str; 4] = [
+        "This is the first document.",
+        "This document is the second document.",
+        "And this is the third one.",
+        "Is this the first document?",
+    ];
+
+    #[test]
+    fn test_matches_sklearn_defaults() {
+        // Giá trị tham chiếu từ TfidfVectorizer() mặc định của scikit-learn
+        let mut tfidf = TFIDF::new()
+            .with_tf_scheme(TfScheme::Count)
+            .with_idf_scheme(IdfScheme::Sklearn { smooth: true })
+            .with_normalization(Normalization::L2);
+        tfidf.fit(&SKLEARN);
+        let doc = tfidf.transform(SKLEARN[0]);
+        for (term, expected) in [
+            ("document", 0.46979139),
+            ("first", 0.58028582),
+            ("is", 0.38408524),
+            ("the", 0.38408524),
+            ("this", 0.38408524),
+        ] {
+            assert!(
+                (doc.score(term).unwrap() - expected).abs() < 1e-8,
+                "{}",
+                term
+            );
+        }
+        let doc = tfidf.transform(SKLEARN[1]);
+        assert!((doc.score("document").unwrap() - 0.6876236).abs() < 1e-7);
+        assert!((doc.score("second").unwrap() - 0.53864762).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_idf_scheme_weights() {
+        // N = 4, df("first") = 2, df("this") = 4
+        let idf = |scheme: IdfScheme, df: usize| scheme.weight(df, 4);
+        assert_eq!(idf(IdfScheme::Standard, 4), 0.0);
+        assert!((idf(IdfScheme::Standard, 2) - 2.0_f64.ln()).abs() < 1e-12);
+        assert!((idf(IdfScheme::Smooth, 2) - 3.0_f64.ln()).abs() < 1e-12);
+        assert!((idf(IdfScheme::Probabilistic, 1) - 3.0_f64.ln()).abs() < 1e-12);
+        // Term có trong hơn nửa số văn bản không bị trọng số âm
+        assert_eq!(idf(IdfScheme::Probabilistic, 4), 0.0);
+        assert_eq!(idf(IdfScheme::Probabilistic, 3), 0.0);
+        assert_eq!(idf(IdfScheme::Smooth, 0), 0.0);
+    }
+
+    #[test]
+    fn test_log_tf_with_l1_normalization() {
+        let mut tfidf = TFIDF::new()
+            .with_tf_scheme(TfScheme::Log)
+            .with_idf_scheme(IdfScheme::Smooth)
+            .with_normalization(Normalization::L1);
+        tfidf.fit(&SKLEARN);
+        let doc = tfidf.transform(SKLEARN[1]);
+        let document = (1.0 + 2.0_f64.ln()) * (1.0 + 4.0 / 3.0_f64).ln();
+        let second = (1.0 + 4.0_f64).ln();
+        let total = document + second + 3.0 * 2.0_f64.ln();
+        assert!((doc.score("document").unwrap() - document / total).abs() < 1e-12);
+        assert!((doc.tfidf_scores.values.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_zero_norm_does_not_produce_nan() {
+        // Văn bản chỉ có term xuất hiện ở mọi nơi có chuẩn 0 và không sinh NaN
+        let mut tfidf = TFIDF::new()
+            .with_idf_scheme(IdfScheme::Standard)
+            .with_normalization(Normalization::L2);
+        tfidf.fit(&SKLEARN);
+        let doc = tfidf.transform("this is");
+        assert!(doc.tfidf_scores.values.iter().all(|v| *v == 0.0));
+        assert!(tfidf
+            .search("this is", 4)
+            .iter()
+            .all(|h| h.score.is_finite()));
+        let doc = TFIDF::new()
+            .with_normalization(Normalization::L1)
+            .transform("anything");
+        assert!(doc.tfidf_scores.values.iter().all(|v| v.is_finite()));
+    }
+
+    // Vector của ba mô tả áo sơ mi và ba mô tả phụ kiện điện thoại
+    fn topics() -> (TFIDF, Vec<SparseVector>, usize) {
+        let documents = [
+            "Cotton shirt with long sleeves.",
+            "Linen shirt for summer.",
+            "Cotton shirt, slim fit.",
+            "Wireless phone charger.",
+            "Android phone case.",
+            "Fast phone charger cable.",
+        ];
+        let mut tfidf = TFIDF::new().with_normalization(Normalization::L2);
+        tfidf.fit(&documents);
+        let vectors = tfidf.vectors(&tfidf.transform_all(&documents));
+        let dimension = tfidf.vocabulary.len();
+        (tfidf, vectors, dimension)
+    }
+
+    #[test]
+    fn test_kmeans_separates_topics() {
+        let (_, vectors, dimension) = topics();
+        let clusters = KMeans::new(2).fit(&vectors, dimension);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters.labels, [0, 0, 0, 1, 1, 1]);
+        assert_eq!(clusters.members(1), [3, 4, 5]);
+    }
+
+    #[test]
+    fn test_cluster_keywords_come_from_centroids() {
+        let (tfidf, vectors, dimension) = topics();
+        let clusters = KMeans::new(2).fit(&vectors, dimension);
+        assert_eq!(tfidf.cluster_keywords(&clusters, 0, 1), ["shirt"]);
+        assert_eq!(
+            tfidf.cluster_keywords(&clusters, 1, 2),
+            ["phone", "charger"]
+        );
+    }
+
+    #[test]
+    fn test_kmeans_is_reproducible_for_a_seed() {
+        let (_, vectors, dimension) = topics();
+        let labels = |seed: u64| {
+            KMeans::new(3)
+                .with_seed(seed)
+                .with_restarts(1)
+                .fit(&vectors, dimension)
+                .labels
+        };
+        assert_eq!(labels(7), labels(7));
+        // Một lần chạy phụ thuộc vào tâm khởi tạo nên các seed cho ra các cách chia khác nhau
+        let distinct: HashSet<Vec<usize>> = (0..20).map(labels).collect();
+        assert!(distinct.len() > 1);
+    }
+
+    #[test]
+    fn test_kmeans_iteration_and_restart_limits() {
+        let (_, vectors, dimension) = topics();
+        // Số lần lặp và số lần chạy ít nhất là 1 nên mọi văn bản đều có nhãn
+        let clusters = KMeans::new(2)
+            .with_max_iterations(0)
+            .with_restarts(0)
+            .fit(&vectors, dimension);
+        assert_eq!(clusters.labels.len(), 6);
+        assert!(clusters.labels.iter().all(|&label| label < 2));
+    }
+
+    #[test]
+    fn test_kmeans_edge_cases() {
+        let (_, vectors, dimension) = topics();
+        assert!(KMeans::new(2).fit(&[], dimension).labels.is_empty());
+        // k lớn hơn số văn bản thì mỗi văn bản một cụm, k = 0 thì một cụm
+        assert_eq!(KMeans::new(10).fit(&vectors, dimension).len(), 6);
+        assert_eq!(KMeans::new(0).fit(&vectors, dimension).len(), 1);
+    }
+
+    #[test]
+    fn test_agglomerative_separates_topics() {
+        let (_, vectors, dimension) = topics();
+        assert_eq!(
+            agglomerative(&vectors, 2, dimension).labels,
+            [0, 0, 0, 1, 1, 1]
+        );
+        assert_eq!(agglomerative(&vectors, 6, dimension).len(), 6);
+    }
+
+    #[test]
+    fn test_agglomerative_matches_greedy_merging() {
+        // Gộp tham lam cặp gần nhất trên ma trận đầy đủ, O(n³), làm chuẩn so sánh
+        fn greedy(vectors: &[SparseVector], k: usize) -> Vec<usize> {
+            let n = vectors.len();
+            let mut distance: Vec<Vec<f64>> = (0..n)
+                .map(|i| {
+                    (0..n)
+                        .map(|j| 1.0 - vectors[i].cosine(&vectors[j]))
+                        .collect()
+                })
+                .collect();
+            let mut sizes = vec![1usize; n];
+            let mut active = vec![true; n];
+            let mut labels: Vec<usize> = (0..n).collect();
+            for _ in 0..n - k {
+                let mut best = (0, 0, f64::INFINITY);
+                for a in (0..n).filter(|&a| active[a]) {
+                    for b in (a + 1..n).filter(|&b| active[b]) {
+                        if distance[a][b] < best.2 {
+                            best = (a, b, distance[a][b]);
+                        }
+                    }
+                }
+                let (a, b, _) = best;
+                for x in (0..n).filter(|&x| active[x] && x != a && x != b) {
+                    let merged = (sizes[a] as f64 * distance[a][x]
+                        + sizes[b] as f64 * distance[b][x])
+                        / (sizes[a] + sizes[b]) as f64;
+                    distance[a][x] = merged;
+                    distance[x][a] = merged;
+                }
+                sizes[a] += sizes[b];
+                active[b] = false;
+                labels.iter_mut().filter(|l| **l == b).for_each(|l| *l = a);
+            }
+            labels
+        }
+
+        let mut rng = SplitMix(7);
+        let dimension = 6;
+        let vectors: Vec<SparseVector> = (0..40)
+            .map(|_| {
+                let mut pairs = Vec::new();
+                for id in 0..dimension as u32 {
+                    if rng.next().is_multiple_of(2) {
+                        pairs.push((id, rng.next_f64()));
+                    }
+                }
+                SparseVector::from_pairs(pairs)
+            })
+            .collect();
+        for k in [1, 2, 5, 13, 40] {
+            let expected = Clusters::from_labels(&vectors, &greedy(&vectors, k), dimension);
+            assert_eq!(
+                agglomerative(&vectors, k, dimension).labels,
+                expected.labels,
+                "k = {}",
+                k
+            );
+        }
+    }
+
+    #[test]
+    fn test_silhouette_prefers_separated_clusters() {
+        let (_, vectors, _) = topics();
+        let good = silhouette(&vectors, &[0, 0, 0, 1, 1, 1]);
+        assert!(good > silhouette(&vectors, &[0, 1, 0, 1, 0, 1]));
+        assert_eq!(silhouette(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn test_choose_k_uses_silhouette() {
+        let (_, vectors, dimension) = topics();
+        let (k, best) = KMeans::new(2).choose_k(&vectors, dimension, 2..=4).unwrap();
+        assert_eq!(k, 2);
+        assert_eq!(best.labels, [0, 0, 0, 1, 1, 1]);
+        // k phải nằm trong [2, số văn bản)
+        assert!(KMeans::new(2)
+            .choose_k(&vectors, dimension, 6..=9)
+            .is_none());
+    }
+
+    fn shop_catalog() -> TFIDF {
+        let product = |name: &str, price: f64, available: bool| Product {
+            name: name.to_string(),
+            price,
+            available,
+        };
+        let products = [
+            (product("Áo sơ mi trắng", 250_000.0, true), "Shirts"),
+            (product("Áo sơ mi kẻ", 150_000.0, true), "Shirts"),
+            (product("Áo sơ mi lụa", 450_000.0, true), "Shirts"),
+            (product("Áo thun đen", 120_000.0, false), "Shirts"),
+            (product("Áo khoác gió", 280_000.0, true), "Jackets"),
+            (product("Quần jeans", 300_000.0, true), "Pants"),
+        ];
+        let mut tfidf = TFIDF::new();
+        tfidf.fit_documents(
+            products
+                .iter()
+                .enumerate()
+                .map(|(i, (p, category))| p.to_document(&format!("sku-{}", i), category)),
+        );
+        tfidf
+    }
+
+    fn result_ids(response: &SearchResponse) -> Vec<&str> {
+        response.hits.iter().map(|h| h.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_first_k_keeps_best_items_in_order() {
+        let items = [5, 1, 4, 1, 3, 9, 2];
+        assert_eq!(first_k(items, 3, |a, b| a.cmp(b)), [1, 1, 2]);
+        assert_eq!(first_k(items, 2, |a: &i32, b| b.cmp(a)), [9, 5]);
+        assert_eq!(first_k(items, 100, |a, b| a.cmp(b)).len(), 7);
+        assert!(first_k(items, 0, |a, b| a.cmp(b)).is_empty());
+    }
+
+    #[test]
+    fn test_filters_on_stored_fields() {
+        // Tìm "áo" trong Shirts, giá 100k-300k, còn hàng, rẻ nhất trước
+        let request = SearchRequest::new("áo")
+            .filter(Filter::Equals(
+                "category".into(),
+                Value::Text("Shirts".into()),
+            ))
+            .filter(Filter::Range(
+                "price".into(),
+                Some(100_000.0),
+                Some(300_000.0),
+            ))
+            .filter(Filter::Equals("available".into(), Value::Flag(true)))
+            .sort(Sort::Field {
+                name: "price".into(),
+                descending: false,
+            });
+        let response = shop_catalog().search_with(&request);
+        assert_eq!(response.total, 2);
+        assert_eq!(result_ids(&response), ["sku-1", "sku-0"]);
+    }
+
+    #[test]
+    fn test_filter_on_missing_or_mistyped_field_matches_nothing() {
+        let tfidf = shop_catalog();
+        let request = SearchRequest::new("áo").filter(Filter::Range("color".into(), None, None));
+        assert_eq!(tfidf.search_with(&request).total, 0);
+        let request = SearchRequest::new("áo").filter(Filter::Range("category".into(), None, None));
+        assert_eq!(tfidf.search_with(&request).total, 0);
+    }
+
+    #[test]
+    fn test_facets_count_whole_filtered_set() {
+        // Facet được đếm trên toàn bộ tập khớp, không chỉ trang hiện tại
+        let request = SearchRequest::new("áo")
+            .facet(Facet::Terms("category".into()))
+            .facet(Facet::Ranges("price".into(), vec![200_000.0, 400_000.0]))
+            .page(0, 1);
+        let response = shop_catalog().search_with(&request);
+        assert_eq!(response.total, 5);
+        assert_eq!(response.hits.len(), 1);
+        let count = |value: &str, count| FacetCount {
+            value: value.to_string(),
+            count,
+        };
+        assert_eq!(
+            response.facets["category"],
+            [count("Shirts", 4), count("Jackets", 1)]
+        );
+        assert_eq!(
+            response.facets["price"],
+            [
+                count("*-200000", 2),
+                count("200000-400000", 2),
+                count("400000-*", 1)
+            ]
+        );
     }
-}
 
-#[derive(Debug, Clone)]
-struct Document {
-    content: String,
-    tfidf_scores: HashMap<String, f64>,
-}
+    #[test]
+    fn test_pages_follow_sort_order() {
+        let tfidf = shop_catalog();
+        let page = |offset: usize| {
+            let request = SearchRequest::new("")
+                .sort(Sort::Field {
+                    name: "price".into(),
+                    descending: true,
+                })
+                .page(offset, 2);
+            let response = tfidf.search_with(&request);
+            assert_eq!(response.total, 6);
+            result_ids(&response)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(page(0), ["sku-2", "sku-5"]);
+        assert_eq!(page(2), ["sku-4", "sku-0"]);
+        assert_eq!(page(4), ["sku-1", "sku-3"]);
+        assert!(page(6).is_empty());
+    }
 
-impl Document {
-    fn new(content: &str) -> Self {
-        Document {
-            content: content.to_string(),
-            tfidf_scores: HashMap::new(),
-        }
+    #[test]
+    fn test_relevance_sort_matches_search() {
+        let tfidf = shop_catalog();
+        let response = tfidf.search_with(&SearchRequest::new("áo sơ mi").page(0, 3));
+        let expected: Vec<usize> = hit_docs(&tfidf.search("áo sơ mi", 3));
+        assert_eq!(
+            response.hits.iter().map(|h| h.doc).collect::<Vec<_>>(),
+            expected
+        );
     }
 
-    fn compute_tfidf(&mut self, idf_scores: &HashMap<String, f64>) {
-        let tokens = Tokenizer::tokenize(&self.content);
-        let mut term_frequency: HashMap<String, f64> = HashMap::new();
+    #[test]
+    fn test_empty_query_browses_catalog() {
+        let request = SearchRequest::new("").filter(Filter::Any(
+            "category".into(),
+            vec![Value::Text("Pants".into()), Value::Text("Jackets".into())],
+        ));
+        let response = shop_catalog().search_with(&request);
+        assert_eq!(response.total, 2);
+        assert_eq!(result_ids(&response), ["sku-4", "sku-5"]);
+    }
 
-        for token in &tokens {
-            *term_frequency.entry(token.clone()).or_insert(0.0) += 1.0;
-        }
+    const SUGGEST: [&str; 6] = [
+        "Áo sơ mi nam.",
+        "Áo thun nữ.",
+        "Áo khoác nam.",
+        "Ấm siêu tốc.",
+        "Apple iphone charger.",
+        "Samsung galaxy phone.",
+    ];
 
-        let num_unique_tokens = tokens.len() as f64;
+    fn suggested(suggestions: Vec<Suggestion>) -> Vec<String> {
+        suggestions.into_iter().map(|s| s.text).collect()
+    }
 
-        for (term, frequency) in term_frequency {
-            if let Some(idf_score) = idf_scores.get(&term) {
-                let tfidf = (frequency / num_unique_tokens) * idf_score;
-                self.tfidf_scores.insert(term, tfidf);
-            }
-        }
+    #[test]
+    fn test_complete_orders_by_document_frequency() {
+        let suggester = fitted(&SUGGEST).suggester(5);
+        // "áo" có trong 3 văn bản nên đứng trước
+        assert_eq!(suggested(suggester.complete("a", 3)), ["áo", "apple", "ấm"]);
+        assert_eq!(suggester.complete("a", 3)[0].weight, 3.0);
+        assert_eq!(suggested(suggester.complete("a", 1)), ["áo"]);
     }
 
-    fn normalize_tfidf_scores(&mut self) {
-        let norm: f64 = self
-            .tfidf_scores
-            .values()
-            .map(|v| v.powi(2))
-            .sum::<f64>()
-            .sqrt();
-        for value in self.tfidf_scores.values_mut() {
-            *value /= norm;
-        }
+    #[test]
+    fn test_complete_ignores_accents_and_case() {
+        let suggester = fitted(&SUGGEST).suggester(5);
+        assert_eq!(suggested(suggester.complete("ao", 3)), ["áo"]);
+        assert_eq!(suggested(suggester.complete("ÁO", 3)), ["áo"]);
+        assert_eq!(suggested(suggester.complete("  sam", 3)), ["samsung"]);
     }
 
-    fn get_top_keywords(&self, k: usize) -> Vec<String> {
-        let mut keywords: Vec<_> = self.tfidf_scores.iter().collect();
-        keywords.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal));
-        keywords
-            .into_iter()
-            .take(k)
-            .map(|(term, _)| term.clone())
-            .collect()
+    #[test]
+    fn test_complete_without_match_is_empty() {
+        let suggester = fitted(&SUGGEST).suggester(5);
+        assert!(suggester.complete("xyz", 3).is_empty());
+        assert!(suggester.complete("a", 0).is_empty());
+        assert!(TFIDF::new().suggester(5).complete("a", 3).is_empty());
     }
-}
 
-struct TFIDF {
-    idf_scores: HashMap<String, f64>,
-}
+    #[test]
+    fn test_complete_is_capped_by_size() {
+        let suggester = fitted(&SUGGEST).suggester(2);
+        assert_eq!(suggested(suggester.complete("a", 5)), ["áo", "apple"]);
+        // Kích thước 0 vẫn giữ được một gợi ý
+        assert_eq!(
+            suggested(Suggester::new(0).complete("", 5)),
+            Vec::<String>::new()
+        );
+        let mut one = Suggester::new(0);
+        one.record_query("áo");
+        assert_eq!(suggested(one.complete("", 5)), ["áo"]);
+    }
 
-impl TFIDF {
-    fn new() -> Self {
-        TFIDF {
-            idf_scores: HashMap::new(),
-        }
+    #[test]
+    fn test_recorded_queries_gain_weight() {
+        let mut suggester = fitted(&SUGGEST).suggester(5).with_query_weight(2.0);
+        suggester.record_query("Áo khoác nam");
+        suggester.record_query("áo khoác nam");
+        // Truy vấn được tìm nhiều lần vượt lên trên term
+        assert_eq!(
+            suggested(suggester.complete("Áo", 2)),
+            ["áo khoác nam", "áo"]
+        );
+        assert_eq!(suggester.complete("áo k", 5)[0].weight, 4.0);
     }
 
-    fn transform(&self, document: &str) -> Document {
-        let mut doc = Document::new(document);
-        doc.compute_tfidf(&self.idf_scores);
-        doc
+    #[test]
+    fn test_record_query_ignores_blank_input() {
+        let mut suggester = Suggester::new(5);
+        suggester.record_query("   ");
+        assert!(suggester.complete("", 5).is_empty());
+        suggester.record_query("  Áo Len  ");
+        assert_eq!(suggested(suggester.complete("ao l", 5)), ["áo len"]);
     }
 
-    fn transform_all(&self, documents: &[&str]) -> Vec<Document> {
-        documents.iter().map(|doc| self.transform(doc)).collect()
+    #[test]
+    fn test_did_you_mean_corrects_misspelled_terms() {
+        let tfidf = fitted(&SUGGEST);
+        assert_eq!(
+            tfidf.did_you_mean("samsnug galaxi"),
+            Some("samsung galaxy".to_string())
+        );
+        // Term đúng được giữ nguyên, chỉ term sai được thay
+        assert_eq!(
+            tfidf.did_you_mean("áo samsnug"),
+            Some("áo samsung".to_string())
+        );
     }
 
-    fn search_by_keyword<'a>(&'a self, keyword: &str, documents: &'a [Document]) -> Vec<&Document> {
-        documents
+    #[test]
+    fn test_did_you_mean_without_correction() {
+        let tfidf = fitted(&SUGGEST);
+        assert_eq!(tfidf.did_you_mean("áo nam"), None);
+        // Quá xa mọi term thì không có gì để sửa
+        assert_eq!(tfidf.did_you_mean("qqqqqqqq"), None);
+        assert_eq!(tfidf.did_you_mean(""), None);
+    }
+
+    fn product(id: &str, text: &str) -> Document {
+        Document::new(text).with_id(id)
+    }
+
+    // Hai segment: [a, b, c] đầy bộ đệm và [d] do commit
+    fn segmented() -> IndexWriter {
+        let mut writer = IndexWriter::new(Tokenizer)
+            .with_max_buffered(3)
+            .with_merge_policy(MergePolicy::new(2));
+        writer.add(product("a", "red cotton shirt"));
+        writer.add(product("b", "blue cotton shirt"));
+        writer.add(product("c", "green wool coat"));
+        writer.add(product("d", "red wool scarf"));
+        writer.commit();
+        writer
+    }
+
+    fn segment_ids(searcher: &Searcher, query: &str) -> Vec<String> {
+        let mut ids = hit_ids(searcher, &searcher.search(query, 10));
+        ids.sort();
+        ids
+    }
+
+    fn hit_ids(searcher: &Searcher, hits: &[Hit]) -> Vec<String> {
+        hits.iter()
+            .map(|h| searcher.document(h.doc).unwrap().id.clone())
+            .collect()
+    }
+
+    fn segment_sizes(writer: &IndexWriter) -> Vec<usize> {
+        writer
+            .searcher()
+            .snapshot
+            .segments
             .iter()
-            .filter(|doc| doc.tfidf_scores.contains_key(keyword))
+            .map(|r| r.segment.documents.len())
             .collect()
     }
-}
 
-impl TFIDF {
-    // ... (các phần khác của impl không thay đổi)
+    #[test]
+    fn test_commit_writes_buffer_as_segment() {
+        let mut writer = segmented();
+        assert_eq!(segment_sizes(&writer), [3, 1]);
+        // Commit khi bộ đệm rỗng không tạo segment
+        writer.commit();
+        assert_eq!(segment_sizes(&writer), [3, 1]);
 
-    fn fit(&mut self, documents: &[&str]) {
-        let num_documents = documents.len() as f64;
+        // Văn bản chưa commit chưa được tìm thấy
+        writer.add(product("e", "yellow silk scarf"));
+        assert!(segment_ids(&writer.searcher(), "silk").is_empty());
+        writer.commit();
+        assert_eq!(segment_ids(&writer.searcher(), "silk"), ["e"]);
+    }
 
-        let mut document_term_frequency: Vec<HashMap<String, f64>> = Vec::new();
+    #[test]
+    fn test_empty_writer_finds_nothing() {
+        let searcher = IndexWriter::new(Tokenizer).searcher();
+        assert_eq!(searcher.doc_count(), 0);
+        assert!(searcher.search("shirt", 10).is_empty());
+        assert!(searcher.get("a").is_none());
+    }
 
-        for document in documents {
-            let tokens = Tokenizer::tokenize(document);
-            let mut term_frequency: HashMap<String, f64> = HashMap::new();
+    #[test]
+    fn test_delete_and_update_by_id() {
+        let mut writer = segmented();
+        assert!(writer.delete("b"));
+        assert!(!writer.delete("b"));
+        assert!(!writer.delete("missing"));
+        // Cập nhật = xóa văn bản cũ rồi thêm văn bản mới cùng mã định danh
+        writer.add(product("c", "green linen coat"));
+        // Xóa văn bản còn trong bộ đệm
+        writer.add(product("e", "yellow silk scarf"));
+        assert!(writer.delete("e"));
+        writer.commit();
+
+        let searcher = writer.searcher();
+        assert_eq!(searcher.doc_count(), 3);
+        assert_eq!(segment_ids(&searcher, "cotton"), ["a"]);
+        assert_eq!(segment_ids(&searcher, "wool"), ["d"]);
+        assert!(segment_ids(&searcher, "silk").is_empty());
+        assert_eq!(searcher.get("c").unwrap().content, "green linen coat");
+        assert!(searcher.get("b").is_none());
+    }
+
+    #[test]
+    fn test_deletes_apply_at_commit() {
+        let mut writer = segmented();
+        let generation = writer.searcher().snapshot.generation;
+        assert!(writer.delete("a"));
+        // Xóa chỉ được ghi nhận ở writer, snapshot chưa đổi
+        assert_eq!(writer.searcher().snapshot.generation, generation);
+        assert_eq!(segment_ids(&writer.searcher(), "cotton"), ["a", "b"]);
+        writer.commit();
+        assert_eq!(writer.searcher().snapshot.generation, generation + 1);
+        assert_eq!(segment_ids(&writer.searcher(), "cotton"), ["b"]);
+
+        // Không có gì để xóa hay ghi thì không công bố snapshot mới
+        assert!(!writer.delete("missing"));
+        writer.commit();
+        writer.commit();
+        assert_eq!(writer.searcher().snapshot.generation, generation + 1);
+
+        // Thêm lại văn bản vừa xóa trong cùng lần commit
+        writer.delete("d");
+        writer.add(product("d", "red silk scarf"));
+        writer.commit();
+        let searcher = writer.searcher();
+        assert!(segment_ids(&searcher, "wool").contains(&"c".to_string()));
+        assert_eq!(segment_ids(&searcher, "silk"), ["d"]);
+        assert_eq!(searcher.doc_count(), 3);
+    }
 
-            for token in &tokens {
-                *term_frequency.entry(token.clone()).or_insert(0.0) += 1.0;
+    #[test]
+    fn test_snapshot_lengths_skip_deleted() {
+        let mut writer = segmented();
+        writer.add(product("e", "white tee").with_field("title", "Tee"));
+        writer.commit();
+        writer.delete("a");
+        writer.delete("e");
+        writer.commit();
+        // Tổng độ dài còn lại phải bằng khi đếm lại từng văn bản còn sống
+        let searcher = writer.searcher();
+        let mut counted: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for reader in &searcher.snapshot.segments {
+            for (name, index) in &reader.segment.indexes {
+                for doc in (0..index.slots()).filter(|&doc| index.is_live(doc)) {
+                    if reader.is_live(doc) {
+                        let (docs, total) = counted.entry(name.clone()).or_default();
+                        *docs += 1;
+                        *total += index.lengths[doc];
+                    }
+                }
             }
+        }
+        assert_eq!(searcher.snapshot.lengths, counted);
+        // Mọi văn bản có `title` đã bị xóa
+        assert!(!searcher.snapshot.lengths.contains_key("title"));
+        assert!(searcher.search_field("title", "tee", 10).is_empty());
+    }
 
-            document_term_frequency.push(term_frequency);
+    fn shop_segments() -> IndexWriter {
+        let mut writer = IndexWriter::new(Tokenizer)
+            .with_max_buffered(2)
+            .with_highlighter(Highlighter::new().with_fragment_len(30));
+        for (id, title, text, category, price) in [
+            ("a", "Cotton shirt", "red cotton shirt", "shirts", 150.0),
+            ("b", "Linen shirt", "white linen shirt", "shirts", 250.0),
+            ("c", "Wool coat", "long wool coat", "coats", 900.0),
+            ("d", "Cotton socks", "cotton socks", "socks", 50.0),
+        ] {
+            writer.add(
+                product(id, text)
+                    .with_field("title", title)
+                    .with_stored("category", Value::Text(category.to_string()))
+                    .with_stored("price", Value::Number(price)),
+            );
         }
+        writer.commit();
+        writer
+    }
 
-        for term_frequency in &document_term_frequency {
-            for (term, _) in term_frequency {
-                *self.idf_scores.entry(term.clone()).or_insert(0.0) += 1.0;
-            }
+    #[test]
+    fn test_searcher_search_with_filters_and_facets() {
+        let searcher = shop_segments().searcher();
+        assert_eq!(searcher.snapshot.segments.len(), 2);
+        let request = SearchRequest::new("cotton shirt")
+            .filter(Filter::Range("price".into(), None, Some(300.0)))
+            .facet(Facet::Terms("category".into()))
+            .sort(Sort::Field {
+                name: "price".into(),
+                descending: true,
+            });
+        let response = searcher.search_with(&request);
+        assert_eq!(result_ids(&response), ["b", "a", "d"]);
+        assert_eq!(response.total, 3);
+        assert_eq!(
+            response.facets["category"],
+            [
+                FacetCount {
+                    value: "shirts".into(),
+                    count: 2
+                },
+                FacetCount {
+                    value: "socks".into(),
+                    count: 1
+                },
+            ]
+        );
+        // Truy vấn rỗng duyệt mọi văn bản còn sống qua bộ lọc
+        let browse = SearchRequest::new("").filter(Filter::Equals(
+            "category".into(),
+            Value::Text("coats".into()),
+        ));
+        assert_eq!(result_ids(&searcher.search_with(&browse)), ["c"]);
+    }
+
+    #[test]
+    fn test_searcher_results_have_snippets() {
+        let searcher = shop_segments().searcher();
+        let results = searcher.search_results("wool", 10);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "c");
+        assert_eq!(results[0].snippet, "long <em>wool</em> coat");
+        assert_eq!(results[0].stored["price"], Value::Number(900.0));
+        // Không có term nào khớp thì lấy đoạn đầu văn bản, không đánh dấu
+        assert_eq!(searcher.snippet("socks", "no match here"), "no match here");
+    }
+
+    #[test]
+    fn test_searcher_query_and_fuzzy() {
+        let mut writer = shop_segments();
+        writer.delete("d");
+        writer.commit();
+        let searcher = writer.searcher();
+        let query = |input: &str| {
+            let mut ids = hit_ids(&searcher, &searcher.query(input, 10).unwrap());
+            ids.sort();
+            ids
+        };
+        assert_eq!(query("shirt AND NOT linen"), ["a"]);
+        assert_eq!(query("title:coat OR red"), ["a", "c"]);
+        assert_eq!(query("\"linen shirt\""), ["b"]);
+        // Văn bản đã xóa không khớp kể cả dưới NOT
+        assert_eq!(query("NOT shirt"), ["c"]);
+        assert!(searcher.query("shirt AND", 10).is_err());
+
+        assert_eq!(
+            hit_ids(&searcher, &searcher.search_fuzzy("cottn", 10)),
+            ["a"]
+        );
+        assert_eq!(
+            hit_ids(&searcher, &searcher.search_prefix("lin", 10)),
+            ["b"]
+        );
+        // "socks" chỉ còn trong văn bản đã xóa nên không được dùng để mở rộng
+        assert!(searcher.search_fuzzy("sockss", 10).is_empty());
+    }
+
+    #[test]
+    fn test_segment_field_analyzer_and_boost() {
+        let mut writer = IndexWriter::new(Tokenizer)
+            .with_field_analyzer("sku", Pipeline::new(Whitespace).token_filter(Lowercase))
+            .with_boost("title", 3.0);
+        writer.add(
+            product("a", "shirt")
+                .with_field("title", "Linen")
+                .with_field("sku", "LS-02"),
+        );
+        writer.add(product("b", "linen").with_field("title", "Shirt"));
+        writer.commit();
+        let searcher = writer.searcher();
+        // Mã SKU giữ nguyên dấu gạch vì trường dùng bộ phân tích riêng
+        assert_eq!(
+            hit_ids(&searcher, &searcher.search_field("sku", "ls-02", 10)),
+            ["a"]
+        );
+        assert!(searcher.search_field("sku", "ls", 10).is_empty());
+        // Khớp ở tiêu đề được nhân boost nên xếp trên
+        assert_eq!(
+            hit_ids(&searcher, &searcher.search("linen", 10)),
+            ["a", "b"]
+        );
+        assert_eq!(
+            hit_ids(&searcher, &searcher.search("shirt", 10)),
+            ["b", "a"]
+        );
+    }
+
+    #[test]
+    fn test_searcher_keeps_its_snapshot() {
+        let mut writer = segmented();
+        let before = writer.searcher();
+        writer.delete("b");
+        writer.add(product("c", "green linen coat"));
+        writer.commit();
+        while let Some(handle) = writer.maybe_merge() {
+            handle.join().unwrap();
         }
+        // Searcher cũ vẫn thấy snapshot lúc nó được mở
+        assert_eq!(before.doc_count(), 4);
+        assert_eq!(segment_ids(&before, "cotton"), ["a", "b"]);
+        assert_eq!(segment_ids(&before, "wool"), ["c", "d"]);
+        assert!(writer.searcher().snapshot.generation > before.snapshot.generation);
+    }
 
-        for (term, score) in &mut self.idf_scores {
-            *score = ((num_documents / (*score + 1.0)) + 1.0).ln(); // Sửa đổi ở đây
+    #[test]
+    fn test_merge_keeps_results() {
+        let mut writer = segmented();
+        writer.delete("b");
+        writer.add(product("c", "green linen coat"));
+        writer.commit();
+        let before = writer.searcher();
+
+        // Gộp nền cho tới khi chính sách không chọn gì nữa
+        while let Some(handle) = writer.maybe_merge() {
+            handle.join().unwrap();
         }
+        // Segment nhiều văn bản đã xóa được viết lại, các segment nhỏ cùng tầng được gộp
+        assert_eq!(segment_sizes(&writer), [2, 1]);
+        let merged = writer.searcher();
+        assert_eq!(merged.doc_count(), 3);
+        // Doc id toàn cục đổi sau khi gộp, mã định danh và điểm thì không
+        let ranked = |searcher: &Searcher| {
+            let hits = searcher.search("red", 10);
+            let scores: Vec<f64> = hits.iter().map(|h| h.score).collect();
+            (hit_ids(searcher, &hits), scores)
+        };
+        assert_eq!(ranked(&merged), ranked(&before));
+        assert_eq!(merged.snapshot.lengths, before.snapshot.lengths);
+        assert!(writer.maybe_merge().is_none());
+    }
+
+    #[test]
+    fn test_merge_policy_groups_segments_by_tier() {
+        let mut writer = IndexWriter::new(Tokenizer)
+            .with_max_buffered(1)
+            .with_merge_policy(MergePolicy::new(3));
+        writer.add(product("a", "red"));
+        writer.add(product("b", "blue"));
+        let policy = MergePolicy::new(3);
+        // Chưa đủ 3 segment cùng tầng
+        assert_eq!(policy.select(&writer.searcher().snapshot.segments), None);
+        writer.add(product("c", "green"));
+        writer.add(product("d", "white"));
+        assert_eq!(
+            policy.select(&writer.searcher().snapshot.segments),
+            Some(vec![0, 1, 2])
+        );
+        // Hệ số gộp nhỏ hơn 2 được nâng lên 2
+        assert_eq!(MergePolicy::new(0).merge_factor, 2);
     }
-}
 
+    #[test]
+    fn test_max_deleted_ratio_rewrites_segment() {
+        let mut writer = IndexWriter::new(Tokenizer)
+            .with_merge_policy(MergePolicy::new(10).with_max_deleted_ratio(0.5));
+        for id in ["a", "b", "c", "d"] {
+            writer.add(product(id, "cotton shirt"));
+        }
+        writer.commit();
+        writer.delete("a");
+        writer.delete("b");
+        writer.commit();
+        // Đúng bằng ngưỡng thì chưa viết lại
+        assert!(writer.maybe_merge().is_none());
+        writer.delete("c");
+        writer.commit();
+        writer.maybe_merge().unwrap().join().unwrap();
+        assert_eq!(segment_sizes(&writer), [1]);
+        assert_eq!(segment_ids(&writer.searcher(), "cotton"), ["d"]);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Ngưỡng 1 không bao giờ viết lại vì chỉ xóa, kể cả khi xóa hết
+        let mut writer = IndexWriter::new(Tokenizer)
+            .with_merge_policy(MergePolicy::new(10).with_max_deleted_ratio(1.0));
+        writer.add(product("a", "cotton shirt"));
+        writer.commit();
+        writer.delete("a");
+        writer.commit();
+        assert!(writer.maybe_merge().is_none());
+    }
 
     #[test]
-    fn test_tokenize() {
-        let text = "The quick brown fox.";
-        let expected_tokens: HashSet<String> = ["the", "quick", "brown", "fox"].iter().map(|s| s.to_string()).collect();
-        assert_eq!(Tokenizer::tokenize(text), expected_tokens);
+    fn test_merging_flag_resets_after_panic() {
+        let shared = Arc::new(Shared::default());
+        shared.merging.store(true, Ordering::SeqCst);
+        let guard = MergeGuard(shared.clone());
+        let result = std::thread::spawn(move || {
+            let _guard = guard;
+            panic!("lần gộp bị lỗi");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(!shared.merging.load(Ordering::SeqCst));
     }
 
     #[test]
-    fn test_compute_tfidf() {
-        let mut tfidf = TFIDF::new();
-        let documents = vec![
-            "Blue house.",
-            "Red house.",
-            "Blue window."
-        ];
-        tfidf.fit(&documents);
+    fn test_segment_search_by_field() {
+        let mut writer = IndexWriter::new(Tokenizer);
+        writer.add(product("a", "warm winter coat").with_field("title", "Wool coat"));
+        writer.add(product("b", "wool blend scarf").with_field("title", "Scarf"));
+        writer.add(product("c", "plain cotton shirt"));
+        writer.commit();
+        let searcher = writer.searcher();
+        let ids = |field: &str, query: &str| -> Vec<String> {
+            hit_ids(&searcher, &searcher.search_field(field, query, 10))
+        };
+        assert_eq!(ids("title", "wool"), ["a"]);
+        assert_eq!(ids(DEFAULT_FIELD, "wool"), ["b"]);
+        assert!(ids("title", "cotton").is_empty());
+        assert!(ids("brand", "wool").is_empty());
+        assert_eq!(searcher.snapshot.lengths["title"], (2, 3));
+    }
 
-        let mut doc = Document::new("Blue house.");
-        doc.compute_tfidf(&tfidf.idf_scores);
+    #[test]
+    fn test_segment_bm25_parameters() {
+        let score = |k1: f64, b: f64| -> Vec<f64> {
+            let mut writer = IndexWriter::new(Tokenizer).with_bm25(k1, b);
+            writer.add(product("a", "shirt shirt"));
+            writer.add(product("b", "shirt with a long description"));
+            writer.add(product("c", "coat"));
+            writer.commit();
+            writer
+                .searcher()
+                .search("shirt", 10)
+                .into_iter()
+                .map(|h| h.score)
+                .collect()
+        };
+        // k1 = 0 bỏ qua tf, b = 0 bỏ qua độ dài: hai văn bản cùng điểm IDF
+        let flat = score(0.0, 0.0);
+        assert_eq!(flat.len(), 2);
+        assert!((flat[0] - flat[1]).abs() < 1e-12);
+        let default = score(1.2, 0.75);
+        assert!(default[0] > default[1]);
+        // Cùng tham số với TFIDF thì cùng điểm
+        let mut tfidf = TFIDF::new().with_scoring(Scoring::bm25());
+        tfidf.fit(&["shirt shirt", "shirt with a long description", "coat"]);
+        let expected: Vec<f64> = tfidf.search("shirt", 10).iter().map(|h| h.score).collect();
+        assert_eq!(default, expected);
+    }
+
+    #[test]
+    fn test_segments_save_and_open() {
+        let mut writer = segmented();
+        writer.delete("b");
+        writer.add(product("e", "white cotton tee").with_field("title", "Tee"));
+        writer.commit();
+        let path = temp_path("segments");
+        writer.save(&path).unwrap();
+        let mut opened = IndexWriter::open(&path, Tokenizer).unwrap();
+        std::fs::remove_file(&path).unwrap();
 
-        let blue_score = doc.tfidf_scores.get("blue").unwrap();
-        let house_score = doc.tfidf_scores.get("house").unwrap();
+        let (saved, loaded) = (writer.searcher(), opened.searcher());
+        assert_eq!(segment_sizes(&opened), segment_sizes(&writer));
+        assert_eq!(loaded.doc_count(), saved.doc_count());
+        for query in ["cotton", "red", "wool coat"] {
+            assert_eq!(loaded.search(query, 10), saved.search(query, 10));
+        }
+        assert_eq!(
+            hit_ids(&loaded, &loaded.search_field("title", "tee", 10)),
+            ["e"]
+        );
+        assert_eq!(loaded.snapshot.lengths, saved.snapshot.lengths);
+        assert!(loaded.get("b").is_none());
 
-        let expected_blue_score = (1.0 / 2.0) * (f64::ln(3.0 / 2.0) + 1.0);
-        let expected_house_score = (1.0 / 2.0) * (f64::ln(3.0 / 2.0) + 1.0);
+        // Segment mở từ file đọc postings và văn bản từ vùng nhớ ánh xạ
+        let reader = &loaded.snapshot.segments[0];
+        assert!(reader.segment.indexes[DEFAULT_FIELD].mapped.is_some());
+        assert!(reader.segment.documents.mapped.is_some());
 
-        assert!((blue_score - expected_blue_score).abs() < f64::EPSILON);
-        assert!((house_score - expected_house_score).abs() < f64::EPSILON);
+        // Segment mới sau khi mở không trùng id với segment đã lưu
+        opened.add(product("f", "black wool socks"));
+        opened.commit();
+        let mut ids: Vec<u64> = opened
+            .searcher()
+            .snapshot
+            .segments
+            .iter()
+            .map(|r| r.segment.id)
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 4);
+
+        // Gộp được segment đọc từ file với segment mới trong bộ nhớ
+        opened.delete("a");
+        opened.commit();
+        while let Some(handle) = opened.maybe_merge() {
+            handle.join().unwrap();
+        }
+        let merged = opened.searcher();
+        assert_eq!(segment_ids(&merged, "wool"), ["c", "d", "f"]);
+        assert_eq!(segment_ids(&merged, "cotton"), ["e"]);
+        assert_eq!(merged.get("e").unwrap().text("title"), Some("Tee"));
+    }
+
+    #[test]
+    fn test_open_rejects_other_files() {
+        let path = temp_path("segments-model");
+        fitted(&CORPUS).save(&path).unwrap();
+        assert!(matches!(
+            IndexWriter::open(&path, Tokenizer),
+            Err(PersistError::Magic)
+        ));
+
+        segmented().save(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            IndexWriter::open(&path, Tokenizer),
+            Err(PersistError::Checksum)
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            IndexWriter::open(&path, Tokenizer),
+            Err(PersistError::Io(_))
+        ));
+    }
+
+    fn graded(pairs: &[(&str, u32)]) -> HashMap<String, u32> {
+        pairs
+            .iter()
+            .map(|&(id, grade)| (id.to_string(), grade))
+            .collect()
+    }
+
+    fn ranked(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    const JUDGMENTS: &str = "# truy vấn\tmã\tmức độ\n\
+                             cotton shirt\tsku-0\t2\n\
+                             cotton shirt\tsku-2\t1\n\
+                             \n\
+                             wool\tsku-1\t1\n";
+
+    #[test]
+    fn test_relevance_metrics() {
+        let grades = graded(&[("a", 3), ("b", 1), ("c", 2), ("x", 0)]);
+        let relevance = Relevance::of(&ranked(&["a", "x", "b", "y"]), &grades, 4);
+        assert_eq!(relevance.precision, 0.5);
+        assert!((relevance.recall - 2.0 / 3.0).abs() < 1e-12);
+        assert!((relevance.average_precision - (1.0 + 2.0 / 3.0) / 3.0).abs() < 1e-12);
+        assert_eq!(relevance.reciprocal_rank, 1.0);
+        let dcg = 7.0 + 1.0 / 4.0_f64.log2();
+        let idcg = 7.0 + 3.0 / 3.0_f64.log2() + 1.0 / 4.0_f64.log2();
+        assert!((relevance.ndcg - dcg / idcg).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_relevance_cuts_ranking_at_k() {
+        let grades = graded(&[("a", 1), ("b", 1)]);
+        let relevance = Relevance::of(&ranked(&["x", "a", "b"]), &grades, 1);
+        assert_eq!(relevance, Relevance::default());
+        let relevance = Relevance::of(&ranked(&["x", "a", "b"]), &grades, 2);
+        assert_eq!(relevance.precision, 0.5);
+        assert_eq!(relevance.reciprocal_rank, 0.5);
+        // k = 0 được tính như k = 1
+        assert_eq!(
+            Relevance::of(&ranked(&["a"]), &grades, 0),
+            Relevance::of(&ranked(&["a"]), &grades, 1)
+        );
+    }
+
+    #[test]
+    fn test_relevance_without_relevant_documents() {
+        let relevance = Relevance::of(&ranked(&["a", "b"]), &graded(&[("a", 0)]), 2);
+        assert_eq!(relevance, Relevance::default());
+        assert_eq!(
+            Relevance::of(&[], &graded(&[("a", 2)]), 3),
+            Relevance::default()
+        );
+    }
+
+    #[test]
+    fn test_relevance_at_max_grade_is_finite() {
+        let grades = graded(&[("a", MAX_GRADE), ("b", 1)]);
+        let relevance = Relevance::of(&ranked(&["b", "a"]), &grades, 2);
+        assert!(relevance.ndcg.is_finite());
+        assert!(relevance.ndcg > 0.0 && relevance.ndcg < 1.0);
+    }
+
+    #[test]
+    fn test_mean_relevance() {
+        let full = Relevance {
+            precision: 1.0,
+            recall: 1.0,
+            average_precision: 1.0,
+            reciprocal_rank: 1.0,
+            ndcg: 1.0,
+        };
+        let mean = Relevance::mean(&[full, Relevance::default()]);
+        assert_eq!(mean.precision, 0.5);
+        assert_eq!(mean.ndcg, 0.5);
+        assert_eq!(Relevance::mean(&[]), Relevance::default());
+    }
+
+    #[test]
+    fn test_parse_judgments() {
+        let judgments = Judgments::parse(JUDGMENTS).unwrap();
+        assert_eq!(judgments.queries.len(), 2);
+        assert_eq!(judgments.queries["cotton shirt"]["sku-2"], 1);
+        assert_eq!(judgments.queries["wool"], graded(&[("sku-1", 1)]));
+        assert!(Judgments::parse("").unwrap().queries.is_empty());
+    }
+
+    #[test]
+    fn test_parse_judgments_rejects_bad_lines() {
+        assert!(matches!(
+            Judgments::parse("cotton\tsku-0"),
+            Err(JudgmentError::Line(1, _))
+        ));
+        assert!(matches!(
+            Judgments::parse("# ghi chú\ncotton\tsku-0\thigh"),
+            Err(JudgmentError::Line(2, _))
+        ));
+        assert!(matches!(
+            Judgments::parse("cotton\tsku-0\t-1"),
+            Err(JudgmentError::Line(1, _))
+        ));
+        assert!(matches!(
+            Judgments::parse("cotton\tsku-0\t1\textra"),
+            Err(JudgmentError::Line(1, _))
+        ));
+    }
+
+    #[test]
+    fn test_parse_judgments_bounds_grades() {
+        let line = format!("cotton\tsku-0\t{}", MAX_GRADE);
+        assert!(Judgments::parse(&line).is_ok());
+        // Mức độ quá lớn làm 2^mức độ tràn khi tính nDCG
+        for grade in [MAX_GRADE + 1, 64, u32::MAX] {
+            let line = format!("cotton\tsku-0\t{}", grade);
+            assert!(matches!(
+                Judgments::parse(&line),
+                Err(JudgmentError::Line(1, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_load_judgments() {
+        let path = temp_path("judgments");
+        std::fs::write(&path, JUDGMENTS).unwrap();
+        let loaded = Judgments::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.queries, Judgments::parse(JUDGMENTS).unwrap().queries);
+        assert!(matches!(Judgments::load(&path), Err(JudgmentError::Io(_))));
+    }
+
+    // Hai cấu hình xếp hạng trên cùng bộ văn bản có mã "sku-<i>"
+    fn evaluated(k: usize) -> Comparison {
+        let documents = [
+            "Cotton shirt, slim fit cotton shirt.",
+            "Wool coat.",
+            "Cotton socks and a linen shirt for the summer holidays.",
+        ];
+        let fit = |mut tfidf: TFIDF| {
+            tfidf.fit_documents(
+                documents
+                    .iter()
+                    .enumerate()
+                    .map(|(i, text)| Document::new(text).with_id(&format!("sku-{}", i))),
+            );
+            tfidf
+        };
+        let baseline = fit(TFIDF::new());
+        let candidate = fit(TFIDF::new().with_scoring(Scoring::bm25()));
+        let judgments = Judgments::parse(JUDGMENTS).unwrap();
+        Comparison::new(&baseline, &candidate, &judgments, k)
+    }
+
+    #[test]
+    fn test_evaluate_judgments() {
+        let comparison = evaluated(2);
+        assert_eq!(comparison.baseline.k, 2);
+        assert_eq!(comparison.baseline.mean.reciprocal_rank, 1.0);
+        assert_eq!(comparison.baseline.queries["wool"].ndcg, 1.0);
+        assert_eq!(comparison.candidate.mean.recall, 1.0);
+    }
+
+    #[test]
+    fn test_evaluate_uses_doc_id_without_document_id() {
+        let tfidf = fitted(&["cotton shirt", "wool coat"]);
+        let judgments = Judgments::parse("wool\t1\t1\nshirt\tsku-0\t1").unwrap();
+        let evaluation = tfidf.evaluate_judgments(&judgments, 1);
+        assert_eq!(evaluation.queries["wool"].precision, 1.0);
+        assert_eq!(evaluation.queries["shirt"].precision, 0.0);
+    }
+
+    #[test]
+    fn test_comparison_report() {
+        let report = evaluated(2).to_string();
+        assert!(report.starts_with("metric"));
+        assert!(report.contains("nDCG@2"));
+        assert!(report.contains("MAP"));
+        assert!(report
+            .lines()
+            .any(|line| line.trim_start().starts_with("cotton shirt")));
+        assert!(report
+            .lines()
+            .any(|line| line.trim_start().starts_with("wool")));
     }
 
     // Thêm các test khác tương tự...
@@ -185,10 +8987,501 @@ fn main() {
         println!("Top keywords: {:?}", top_keywords);
     }
 
+    // Trọng số của "document" trong văn bản thứ hai với từng cách đếm tần suất
+    let mut raw = Document::new(documents[1]);
+    raw.compute_tfidf(&tfidf.idf_scores);
+    println!("Raw: {:.4}", raw.score("document").unwrap_or(0.0));
+    for scheme in [
+        TfScheme::Count,
+        TfScheme::Log,
+        TfScheme::Augmented,
+        TfScheme::Boolean,
+    ] {
+        let mut doc = Document::new(documents[1]);
+        doc.compute_tfidf_with(&tfidf.idf_scores, scheme);
+        println!("{:?}: {:.4}", scheme, doc.score("document").unwrap_or(0.0));
+    }
+
+    // IDF của "document" (có trong 3/4 văn bản) theo từng công thức, và trọng số của nó
+    // với tf log và chuẩn hóa L1
+    for scheme in [
+        IdfScheme::Standard,
+        IdfScheme::Smooth,
+        IdfScheme::Probabilistic,
+        IdfScheme::Sklearn { smooth: true },
+    ] {
+        let mut model = TFIDF::new()
+            .with_tf_scheme(TfScheme::Log)
+            .with_idf_scheme(scheme)
+            .with_normalization(Normalization::L1);
+        model.fit(&documents);
+        println!(
+            "{:?}: idf = {:.4}, L1 weight in the second document = {:.4}",
+            scheme,
+            model.idf_scores["document"],
+            model
+                .transform(documents[1])
+                .score("document")
+                .unwrap_or(0.0)
+        );
+    }
+
     let keyword_to_search = "document";
     let search_results = tfidf.search_by_keyword(keyword_to_search, &tfidf_documents);
     println!("Search results for '{}':", keyword_to_search);
     for (i, result) in search_results.iter().enumerate() {
-        println!("{}: {:?}", i + 1, result);
+        println!("{}: {}", i + 1, result.content);
+    }
+    println!(
+        "Documents containing 'second' or 'third': {:?}",
+        tfidf.index.lookup_any(&["second", "third"])
+    );
+
+    let query = "first document";
+    println!("Ranked results for '{}':", query);
+    for hit in tfidf.search(query, 3) {
+        println!("{:.4} {}", hit.score, documents[hit.doc]);
+    }
+
+    // Lưu mô hình rồi nạp lại, kết quả tìm kiếm phải giống hệt
+    let path = std::env::temp_dir().join("tfidf-model.idx");
+    match tfidf
+        .save(&path)
+        .and_then(|_| TFIDF::load(&path, Tokenizer))
+    {
+        Ok(loaded) => println!(
+            "Reloaded model gives the same results: {}",
+            loaded.search(query, 3) == tfidf.search(query, 3)
+        ),
+        Err(error) => println!("Could not persist model: {}", error),
+    }
+    let _ = std::fs::remove_file(&path);
+
+    println!("Similar to '{}':", documents[0]);
+    for hit in tfidf.more_like_this(0, 2) {
+        println!("{:.4} {}", hit.score, documents[hit.doc]);
+    }
+    for text in ["second document", "unknown words"] {
+        let vector = tfidf.vectorize(text);
+        if vector.is_empty() {
+            println!("'{}' has no known terms", text);
+        } else {
+            println!("'{}' has {} weighted term(s)", text, vector.len());
+        }
+    }
+
+    let query = "\"first document\" AND NOT second";
+    if let Ok(parsed) = Query::parse(query) {
+        println!("Parsed query: {:?}", parsed);
+    }
+    match tfidf.query(query, 3) {
+        Ok(hits) => println!("Query '{}': {} result(s)", query, hits.len()),
+        Err(error) => println!("Query '{}' failed: {}", query, error),
+    }
+
+    // Tiếng Việt: ghép từ theo từ điển và bỏ dấu để gõ không dấu vẫn tìm được
+    let mut vietnamese = Vietnamese::new().with_folding(true);
+    vietnamese.add_word("sạc dự phòng");
+    let mut store = TFIDF::new().with_analyzer(vietnamese);
+    store.fit(&[
+        "Máy tính xách tay mỏng nhẹ.",
+        "Điện thoại và sạc dự phòng.",
+        "Máy ảnh kỹ thuật số.",
+    ]);
+    for query in ["may tinh", "sac du phong"] {
+        let found: Vec<usize> = store.search(query, 3).iter().map(|hit| hit.doc).collect();
+        println!("Vietnamese results for '{}': {:?}", query, found);
+    }
+
+    // Chuỗi phân tích riêng: bỏ HTML, chuẩn hóa, stem và từ đồng nghĩa cho mô tả; mã sản
+    // phẩm tách theo khoảng trắng; tiêu đề dùng edge n-gram để khớp khi đang gõ
+    let descriptions = Pipeline::new(Words)
+        .char_filter(HtmlStrip)
+        .char_filter(Nfc)
+        .token_filter(Lowercase)
+        .token_filter(Fold)
+        .token_filter(Stopwords::english())
+        .token_filter(Stemmer)
+        .token_filter(Synonyms::new(&[&["tee", "tshirt"]]))
+        .token_filter(Length { min: 2, max: 20 });
+    let mut articles = TFIDF::new()
+        .with_analyzer(descriptions)
+        .with_field_analyzer("sku", Pipeline::new(Whitespace).token_filter(Lowercase))
+        .with_field_analyzer(
+            "title",
+            Pipeline::new(NGram::edge(2, 6)).token_filter(Lowercase),
+        );
+    articles.fit_documents(vec![
+        Document::new("<p>Organic cotton <b>tees</b> in café colours.</p>")
+            .with_field("title", "Cotton tee")
+            .with_field("sku", "CT-01"),
+        Document::new("<p>A <i>linen</i> shirt for the beach.</p>")
+            .with_field("title", "Linen shirt")
+            .with_field("sku", "LS-02"),
+    ]);
+    for query in ["tshirt", "cafe"] {
+        let found: Vec<usize> = articles
+            .search(query, 3)
+            .iter()
+            .map(|hit| hit.doc)
+            .collect();
+        println!("Pipeline results for '{}': {:?}", query, found);
+    }
+    for query in ["title:lin", "sku:ls-02"] {
+        match articles.query(query, 3) {
+            Ok(hits) => {
+                let found: Vec<usize> = hits.iter().map(|hit| hit.doc).collect();
+                println!("Pipeline results for '{}': {:?}", query, found);
+            }
+            Err(error) => println!("Query '{}' failed: {}", query, error),
+        }
+    }
+    let trigrams: Vec<String> = Pipeline::new(NGram::new(3, 3))
+        .analyze("linen")
+        .into_iter()
+        .map(|token| token.text)
+        .collect();
+    println!("Trigrams of 'linen': {:?}", trigrams);
+
+    // Danh mục sản phẩm có mã định danh, tiêu đề và trường lưu trữ
+    let highlighter = Highlighter::new()
+        .with_fragment_len(40)
+        .with_fragments(2)
+        .with_markers("[", "]")
+        .with_separator(" ... ");
+    let mut catalog = TFIDF::new()
+        .with_boost("title", 2.0)
+        .with_highlighter(highlighter)
+        // "days" không phải là một tag có ích nên được loại khỏi cụm từ khóa
+        .with_stopwords(Stopwords::english().union(Stopwords::new(&["days"])));
+    catalog.fit_documents(vec![
+        Document::new("Breathable linen shirt for hot summer days.")
+            .with_id("sku-1")
+            .with_field("title", "Linen shirt")
+            .with_stored("price", Value::Number(25.0))
+            .with_stored("category", Value::Text("shirts".to_string())),
+        Document::new("Warm wool coat, lined with linen.")
+            .with_id("sku-2")
+            .with_field("title", "Wool coat")
+            .with_stored("price", Value::Number(120.0))
+            .with_stored("category", Value::Text("coats".to_string())),
+    ]);
+    let query = "linen";
+    println!("Catalog results for '{}':", query);
+    for result in catalog.search_results(query, 10) {
+        let stored: Vec<String> = result
+            .stored
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        println!(
+            "{} ({:.4}) [{}] {}",
+            result.id,
+            result.score,
+            stored.join(", "),
+            result.snippet
+        );
+    }
+    for doc in 0..2 {
+        let tags: Vec<String> = catalog
+            .extract_keyphrases(doc, 3, 0.5)
+            .into_iter()
+            .map(|keyphrase| keyphrase.phrase)
+            .collect();
+        let product = catalog.get(doc).unwrap();
+        println!(
+            "Tags for {} ({}): {}",
+            product.id,
+            product.text("title").unwrap_or_default(),
+            tags.join(", ")
+        );
+    }
+
+    // "áo" trong Shirts, giá 100k-300k, còn hàng, kèm số lượng theo danh mục
+    let products = [
+        ("Áo sơ mi trắng", 250_000.0, true, "Shirts"),
+        ("Áo sơ mi lụa", 450_000.0, true, "Shirts"),
+        ("Áo thun đen", 120_000.0, false, "Shirts"),
+        ("Áo khoác gió", 280_000.0, true, "Jackets"),
+    ];
+    let mut shop = TFIDF::new();
+    shop.fit_documents(products.iter().enumerate().map(
+        |(i, &(name, price, available, category))| {
+            let product = Product {
+                name: name.to_string(),
+                price,
+                available,
+            };
+            product.to_document(&format!("sku-{}", i + 1), category)
+        },
+    ));
+    let request = SearchRequest::new("áo")
+        .filter(Filter::Equals(
+            "category".into(),
+            Value::Text("Shirts".into()),
+        ))
+        .filter(Filter::Range(
+            "price".into(),
+            Some(100_000.0),
+            Some(300_000.0),
+        ))
+        .filter(Filter::Equals("available".into(), Value::Flag(true)))
+        .facet(Facet::Terms("category".into()))
+        .facet(Facet::Ranges("price".into(), vec![200_000.0]))
+        .sort(Sort::Field {
+            name: "price".into(),
+            descending: false,
+        })
+        .page(0, 10);
+    let response = shop.search_with(&request);
+    println!(
+        "Shop results: {} of {}",
+        response.hits.len(),
+        response.total
+    );
+    for result in &response.hits {
+        println!(
+            "{} {} {}",
+            result.id, result.stored["price"], result.snippet
+        );
+    }
+    for (facet, counts) in &response.facets {
+        let counts: Vec<String> = counts
+            .iter()
+            .map(|c| format!("{} ({})", c.value, c.count))
+            .collect();
+        println!("Facet {}: {}", facet, counts.join(", "));
+    }
+    let browse = SearchRequest::new("").filter(Filter::Any(
+        "category".into(),
+        vec![Value::Text("Jackets".into())],
+    ));
+    println!("Jackets: {}", shop.search_with(&browse).total);
+
+    // Gom nhóm mô tả sản phẩm, đặt tên nhóm theo từ khóa của tâm cụm
+    let descriptions = [
+        "Cotton shirt with long sleeves.",
+        "Linen shirt for summer.",
+        "Wireless phone charger.",
+        "Android phone case.",
+    ];
+    let mut grouping = TFIDF::new().with_normalization(Normalization::L2);
+    grouping.fit(&descriptions);
+    let vectors = grouping.vectors(&grouping.transform_all(&descriptions));
+    let dimension = grouping.vocabulary.len();
+    let kmeans = KMeans::new(2)
+        .with_seed(7)
+        .with_restarts(5)
+        .with_max_iterations(50);
+    if let Some((k, clusters)) = kmeans.choose_k(&vectors, dimension, 2..=3) {
+        println!(
+            "k-means chose k = {} (silhouette {:.3})",
+            k,
+            silhouette(&vectors, &clusters.labels)
+        );
+        for cluster in 0..clusters.len() {
+            let members: Vec<&str> = clusters
+                .members(cluster)
+                .into_iter()
+                .map(|doc| descriptions[doc])
+                .collect();
+            println!(
+                "[{}] {}",
+                grouping.cluster_keywords(&clusters, cluster, 2).join(", "),
+                members.join(" | ")
+            );
+        }
+    }
+    let hierarchical = agglomerative(&vectors, 2, dimension);
+    println!("Agglomerative labels: {:?}", hierarchical.labels);
+
+    // Fit từ nguồn đọc từng dòng (ví dụ file xuất danh mục) rồi thêm văn bản sau đó
+    let dump = "Linen shirt\nWool coat\nCotton socks";
+    let mut streamed = TFIDF::new()
+        .with_store_content(false)
+        .with_spill_dir(std::env::temp_dir());
+    match streamed.fit_reader(std::io::Cursor::new(dump), 2) {
+        Ok(count) => println!("Streamed {} document(s)", count),
+        Err(error) => println!("Streaming fit failed: {}", error),
+    }
+    let lines: Vec<&str> = dump.lines().collect();
+    let mut parallel = TFIDF::new();
+    parallel.fit_parallel(&lines, 2);
+    println!(
+        "Parallel fit transformed {} document(s)",
+        parallel.transform_parallel(&lines, 2).len()
+    );
+    let doc = streamed.add_document("Wool socks");
+    streamed.update_document(1, Document::new("Wool coat with hood"));
+    streamed.refresh();
+    println!(
+        "Added doc {}, 'wool' now matches {} document(s)",
+        doc,
+        streamed.search("wool", 10).len()
+    );
+
+    // Tìm kiếm chấp nhận lỗi chính tả, và khớp tiền tố cho term đang gõ dở
+    let mut gadgets =
+        TFIDF::new().with_fuzzy(Fuzzy::new(2).with_penalty(0.5).with_max_expansions(10));
+    gadgets.fit(&[
+        "Samsung galaxy phone with a large screen.",
+        "Apple iphone charger and cable.",
+        "Samsonite travel luggage.",
+    ]);
+    let query = "samsnug galaxi";
+    println!("Fuzzy results for '{}':", query);
+    for hit in gadgets.search_fuzzy(query, 3) {
+        println!("{:.4} {}", hit.score, gadgets.get(hit.doc).unwrap().content);
+    }
+    let query = "travel sams";
+    println!("Prefix results for '{}':", query);
+    for hit in gadgets.search_prefix(query, 3) {
+        println!("{:.4} {}", hit.score, gadgets.get(hit.doc).unwrap().content);
+    }
+
+    // Gợi ý cho ô tìm kiếm: term của từ vựng cộng với các truy vấn đã được tìm
+    let mut suggester = gadgets.suggester(5).with_query_weight(2.0);
+    suggester.record_query("samsung galaxy");
+    for suggestion in suggester.complete("sam", 3) {
+        println!("Suggest {} ({})", suggestion.text, suggestion.weight);
+    }
+    if let Some(correction) = gadgets.did_you_mean("samsnug galaxi") {
+        println!("Did you mean '{}'?", correction);
+    }
+
+    // Chỉ mục dạng segment: ghi theo lô, xóa bằng tombstone, gộp ở luồng nền
+    let mut writer = IndexWriter::new(Tokenizer)
+        .with_max_buffered(2)
+        .with_merge_policy(MergePolicy::new(2).with_max_deleted_ratio(0.5))
+        .with_bm25(1.2, 0.75)
+        .with_field_analyzer("sku", Pipeline::new(Whitespace).token_filter(Lowercase))
+        .with_boost("title", 2.0)
+        .with_highlighter(Highlighter::new().with_fragment_len(40))
+        .with_fuzzy(Fuzzy::new(2));
+    for (id, title, description, category) in [
+        (
+            "sku-1",
+            "Cotton shirt",
+            "Breathable cotton shirt for summer",
+            "shirts",
+        ),
+        ("sku-2", "Wool coat", "Warm wool coat with hood", "coats"),
+        ("sku-3", "Wool socks", "Thick socks in merino wool", "socks"),
+        ("sku-4", "Linen shirt", "Light linen shirt", "shirts"),
+    ] {
+        writer.add(
+            Document::new(description)
+                .with_id(id)
+                .with_field("title", title)
+                .with_field("sku", &id.to_uppercase())
+                .with_stored("category", Value::Text(category.to_string())),
+        );
+    }
+    writer.delete("sku-3");
+    writer.commit();
+    while let Some(handle) = writer.maybe_merge() {
+        handle.join().unwrap();
+    }
+    let searcher = writer.searcher();
+    println!(
+        "Segments: {} with {} live document(s)",
+        searcher.snapshot.segments.len(),
+        searcher.doc_count()
+    );
+    for hit in searcher.search_field("title", "wool", 3) {
+        println!(
+            "{:.4} {}",
+            hit.score,
+            searcher.document(hit.doc).unwrap().content
+        );
+    }
+    for result in searcher.search_results("shirt", 3) {
+        println!("{} {}", result.id, result.snippet);
+    }
+    if let Ok(hits) = searcher.query("shirt AND NOT title:linen", 3) {
+        let ids: Vec<&str> = hits
+            .iter()
+            .filter_map(|hit| searcher.document(hit.doc))
+            .map(|document| document.id.as_str())
+            .collect();
+        println!("Shirts but not linen: {}", ids.join(", "));
+    }
+    for hit in searcher.search_fuzzy("wooll", 3) {
+        println!(
+            "Fuzzy {:.4} {}",
+            hit.score,
+            searcher.document(hit.doc).unwrap().id
+        );
+    }
+    for hit in searcher.search_prefix("lin", 3) {
+        println!(
+            "Prefix {:.4} {}",
+            hit.score,
+            searcher.document(hit.doc).unwrap().id
+        );
+    }
+    let response = searcher.search_with(
+        &SearchRequest::new("shirt")
+            .filter(Filter::Equals(
+                "category".into(),
+                Value::Text("shirts".into()),
+            ))
+            .facet(Facet::Terms("category".into())),
+    );
+    println!(
+        "{} shirt(s) in {:?}",
+        response.total, response.facets["category"]
+    );
+    if let Some(coat) = searcher.get("sku-2") {
+        println!("sku-2: {}", coat.content);
+    }
+    let path = std::env::temp_dir().join("tfidf-segments.idx");
+    match writer
+        .save(&path)
+        .and_then(|_| IndexWriter::open(&path, Tokenizer))
+    {
+        Ok(opened) => println!(
+            "Reopened {} document(s) from {}",
+            opened.searcher().doc_count(),
+            path.display()
+        ),
+        Err(error) => println!("Could not persist segments: {}", error),
+    }
+    let _ = std::fs::remove_file(&path);
+
+    // So sánh cosine với BM25 trên một bộ đánh giá nhỏ
+    let products = [
+        "Cotton shirt, slim fit cotton shirt.",
+        "Wool coat.",
+        "Cotton socks and a linen shirt for the summer holidays.",
+    ];
+    let fit = |mut model: TFIDF| {
+        model.fit_documents(
+            products
+                .iter()
+                .enumerate()
+                .map(|(i, text)| Document::new(text).with_id(&format!("sku-{}", i))),
+        );
+        model
+    };
+    // Bộ đánh giá thường nằm trong file TSV do người gán nhãn soạn
+    let path = std::env::temp_dir().join("tfidf-judgments.tsv");
+    let judgments = "cotton shirt\tsku-0\t2\ncotton shirt\tsku-2\t1\nwool\tsku-1\t1\n";
+    if let Err(error) = std::fs::write(&path, judgments) {
+        println!("Could not write judgments: {}", error);
+    }
+    let loaded = Judgments::load(&path);
+    let _ = std::fs::remove_file(&path);
+    match loaded {
+        Ok(judgments) => print!(
+            "{}",
+            Comparison::new(
+                &fit(TFIDF::new()),
+                &fit(TFIDF::new().with_scoring(Scoring::bm25())),
+                &judgments,
+                2
+            )
+        ),
+        Err(error) => println!("Could not load judgments: {}", error),
     }
 }